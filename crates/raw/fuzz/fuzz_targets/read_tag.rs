@@ -0,0 +1,7 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate raw;
+
+fuzz_target!(|data: &[u8]| {
+    raw::cr2::fuzz::read_tag(data);
+});
@@ -0,0 +1,122 @@
+use cameras::Area;
+
+/// Laplacian of the pixel at (x, y), the caller keeps a 1 pixel border
+fn laplacian(luma: &[f32], width: usize, x: usize, y: usize) -> f64 {
+    let p = |x: usize, y: usize| luma[y * width + x] as f64;
+    p(x - 1, y) + p(x + 1, y) + p(x, y - 1) + p(x, y + 1) - 4.0 * p(x, y)
+}
+
+/// Sharpness of an image as variance of its Laplacian
+///
+/// The luminance is given row by row, e.g. from a decoded preview. Only
+/// the region is scored if one is given, e.g. around the AF points. Sharp
+/// images score higher, the scale depends on the content, so compare
+/// scores of similar shots only. None if the region has no inner pixels.
+pub fn focus_score(luma: &[f32], width: usize, region: Option<Area>) -> Option<f64> {
+    if width == 0 {
+        return None;
+    }
+    let height = luma.len() / width;
+    let r = region.unwrap_or(Area { top: 0, left: 0, bottom: height as u32, right: width as u32 });
+    let (top, left) = (r.top.max(1) as usize, r.left.max(1) as usize);
+    let (bottom, right) = ((r.bottom as usize).min(height.saturating_sub(1)), (r.right as usize).min(width.saturating_sub(1)));
+    if top >= bottom || left >= right {
+        return None;
+    }
+    let values: Vec<f64> = (top..bottom).flat_map(|y| (left..right).map(move |x| (x, y)))
+        .map(|(x, y)| laplacian(luma, width, x, y)).collect();
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    Some(values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64)
+}
+
+/// Focus peaking mask, true where the contrast of an edge exceeds the
+/// threshold
+///
+/// The mask has the size of the luminance image, e.g. the decoded
+/// preview, so it can be drawn over it. Border pixels are never set.
+/// A threshold of 0.1 to 0.3 suits luminance in 0..1.
+pub fn peaking_mask(luma: &[f32], width: usize, threshold: f64) -> Vec<bool> {
+    let mut mask = vec![false; luma.len()];
+    let height = if width > 0 { luma.len() / width } else { 0 };
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width - 1 {
+            mask[y * width + x] = laplacian(luma, width, x, y).abs() > threshold;
+        }
+    }
+    mask
+}
+
+/// Share of pixels ignored at the bright end, e.g. hot pixels
+const HIGHLIGHT_TOLERANCE: f64 = 0.0001;
+
+/// Exposure headroom of a color channel
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Headroom {
+    /// Stops the exposure can be increased before the brightest pixels
+    /// clip, 0 if they already do
+    pub ev: f64,
+    /// Share of pixels at or above the white level
+    pub clipped: f64
+}
+
+/// Headroom of a channel for exposing to the right
+///
+/// The histogram counts the raw values of the channel, index = value.
+/// Black and white level are those of RawImage::black_level and
+/// RawImage::white_level. None for empty histograms.
+pub fn headroom(histogram: &[u64], black_level: u32, white_level: u32) -> Option<Headroom> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 || white_level <= black_level {
+        return None;
+    }
+    let clipped: u64 = histogram.iter().skip(white_level as usize).sum();
+    // the brightest value once the tolerated pixels are left out
+    let mut left = (total as f64 * HIGHLIGHT_TOLERANCE) as u64;
+    let mut brightest = 0;
+    for (v, &n) in histogram.iter().enumerate().rev() {
+        if n > left {
+            brightest = v as u32;
+            break;
+        }
+        left -= n;
+    }
+    let ev = if brightest <= black_level { ::std::f64::INFINITY }
+             else { ((white_level - black_level) as f64 / (brightest - black_level) as f64).log2().max(0.0) };
+    Some(Headroom { ev: ev, clipped: clipped as f64 / total as f64 })
+}
+
+#[test]
+fn test_focus_score() {
+    let sharp: Vec<f32> = (0..64).map(|i| if (i % 8 + i / 8) % 2 == 0 { 1.0 } else { 0.0 }).collect();
+    let soft: Vec<f32> = (0..64).map(|i| (i % 8) as f32 / 8.0).collect();
+    let s = focus_score(&sharp, 8, None).unwrap();
+    assert!(s > focus_score(&soft, 8, None).unwrap());
+    assert_eq!(Some(0.0), focus_score(&soft, 8, Some(Area { top: 2, left: 2, bottom: 5, right: 5 })));
+    assert_eq!(None, focus_score(&sharp[..16], 8, None));
+}
+
+#[test]
+fn test_peaking_mask() {
+    // a vertical edge between columns 3 and 4
+    let luma: Vec<f32> = (0..48).map(|i| if i % 8 < 4 { 0.0 } else { 1.0 }).collect();
+    let mask = peaking_mask(&luma, 8, 0.5);
+    assert_eq!(vec![false, false, false, true, true, false, false, false], mask[8..16].to_vec());
+    assert!(mask[..8].iter().all(|m| !m));
+    assert!(peaking_mask(&[], 0, 0.5).is_empty());
+}
+
+#[test]
+fn test_headroom() {
+    let mut h = vec![0u64; 16384];
+    h[2048] = 100000;
+    h[9216] = 1000;
+    h[16000] = 5;
+    let r = headroom(&h, 2048, 16383).unwrap();
+    assert!((r.ev - 1.0).abs() < 0.01, "{:?}", r);
+    assert_eq!(0.0, r.clipped);
+    h[16383] = 2000;
+    let r = headroom(&h, 2048, 16383).unwrap();
+    assert_eq!(0.0, r.ev);
+    assert!(r.clipped > 0.019);
+    assert_eq!(None, headroom(&[], 0, 1));
+}
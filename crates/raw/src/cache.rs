@@ -0,0 +1,175 @@
+use std::fs::{self,File};
+use std::io::{self,Read,Write};
+use std::path::{Path,PathBuf};
+use std::time::UNIX_EPOCH;
+use cr2::{self,RawFileError};
+use hash::{self,Sha256};
+use source;
+
+/// Embedded images that can be cached
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PreviewKind {
+    Thumbnail,
+    Preview
+}
+
+impl PreviewKind {
+    fn suffix(&self) -> &'static str {
+        match *self {
+            PreviewKind::Thumbnail => "thumb.jpg",
+            PreviewKind::Preview => "preview.jpg"
+        }
+    }
+}
+
+/// On-disk cache of embedded previews
+///
+/// Every raw file has an index entry named after the hash of its path.
+/// The entry holds the mtime, size and content hash of the file. The
+/// previews are stored under the content hash, so copies of the same
+/// exposure share them. A changed mtime or size makes the entry stale.
+pub struct Cache {
+    dir: PathBuf
+}
+
+/// Modification time in nanoseconds and size of a file
+pub(crate) fn stat(path: &str) -> io::Result<(u128, u64)> {
+    let m = try!(fs::metadata(path));
+    let mtime = try!(m.modified()).duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    Ok((mtime, m.len()))
+}
+
+impl Cache {
+    /// Opens the cache in dir, the directory is created if needed
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Cache> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(fs::create_dir_all(dir.join("index")));
+        Ok(Cache { dir: dir })
+    }
+
+    fn index_path(&self, path: &str) -> PathBuf {
+        let mut h = Sha256::default();
+        h.update(path.as_bytes());
+        self.dir.join("index").join(hash::to_hex(&h.finish()))
+    }
+
+    fn preview_path(&self, content: &str, kind: PreviewKind) -> PathBuf {
+        self.dir.join(format!("{}.{}", content, kind.suffix()))
+    }
+
+    /// Content hash of a file from its index entry, None if there is
+    /// no entry or the file changed since
+    fn content_hash(&self, path: &str) -> io::Result<Option<String>> {
+        let mut entry = String::new();
+        match File::open(self.index_path(path)) {
+            Ok(mut f) => { try!(f.read_to_string(&mut entry)); },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        let (mtime, size) = try!(stat(path));
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        match fields.as_slice() {
+            [m, s, h] if *m == mtime.to_string() && *s == size.to_string() => Ok(Some(h.to_string())),
+            _ => Ok(None)
+        }
+    }
+
+    /// Returns a cached preview, None if it is not cached or stale
+    pub fn get(&self, path: &str, kind: PreviewKind) -> io::Result<Option<Vec<u8>>> {
+        let content = match try!(self.content_hash(path)) {
+            Some(h) => h,
+            None => return Ok(None)
+        };
+        let mut jpeg = Vec::new();
+        match File::open(self.preview_path(&content, kind)) {
+            Ok(mut f) => { try!(f.read_to_end(&mut jpeg)); },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        Ok(Some(jpeg))
+    }
+
+    /// Returns a preview, extracting and caching it if necessary
+    pub fn get_or_insert(&self, path: &str, kind: PreviewKind) -> Result<Vec<u8>,RawFileError> {
+        if let Some(jpeg) = try!(self.get(path, kind)) {
+            return Ok(jpeg);
+        }
+        let stat = try!(stat(path));
+        let image = try!(cr2::open(path.to_string()));
+        let content = hash::to_hex(&try!(image.content_hash()));
+        let jpeg = match kind {
+            PreviewKind::Thumbnail => try!(image.thumbnail()),
+            PreviewKind::Preview => try!(image.preview())
+        };
+        try!(write(&self.preview_path(&content, kind), &jpeg));
+        try!(write(&self.index_path(path), format!("{} {} {}\n", stat.0, stat.1, content).as_bytes()));
+        Ok(jpeg)
+    }
+
+    /// Removes the index entry of a file
+    ///
+    /// The previews stay, other copies of the file may still use them.
+    pub fn invalidate(&self, path: &str) -> io::Result<()> {
+        match fs::remove_file(self.index_path(path)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r
+        }
+    }
+
+    /// Removes all entries and previews
+    pub fn clear(&self) -> io::Result<()> {
+        try!(fs::remove_dir_all(&self.dir));
+        fs::create_dir_all(self.dir.join("index"))
+    }
+}
+
+/// Writes a file through a temporary copy, readers never see partial data
+fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    source::replace_file(path, |f| f.write_all(data))
+}
+
+#[test]
+fn test_cache() {
+    use std::time::Duration;
+    use synth::{Cr2Builder,Ifd};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().strip(&[0xff, 0xd8, 2]))
+        .ifd(Ifd::new().thumbnail(&[0xff, 0xd8, 1]))
+        .ifd(Ifd::new())
+        .ifd(Ifd::new().strip(&[1, 2, 3]))
+        .raw_ifd(3)
+        .build();
+    let dir = ::std::env::temp_dir().join(format!("rraw-cache-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cache = Cache::new(dir.join("cache")).unwrap();
+    let (a, b) = (dir.join("a.cr2"), dir.join("b.cr2"));
+    fs::write(&a, &f).unwrap();
+    fs::write(&b, &f).unwrap();
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    assert_eq!(None, cache.get(a, PreviewKind::Thumbnail).unwrap());
+    assert_eq!(vec![0xff, 0xd8, 1], cache.get_or_insert(a, PreviewKind::Thumbnail).unwrap());
+    assert_eq!(Some(vec![0xff, 0xd8, 1]), cache.get(a, PreviewKind::Thumbnail).unwrap());
+    assert_eq!(None, cache.get(a, PreviewKind::Preview).unwrap());
+    // copies share the previews stored under their content hash
+    cache.get_or_insert(b, PreviewKind::Thumbnail).unwrap();
+    cache.get_or_insert(b, PreviewKind::Preview).unwrap();
+    assert_eq!(Some(vec![0xff, 0xd8, 2]), cache.get(a, PreviewKind::Preview).unwrap());
+    let files = |d: PathBuf| fs::read_dir(d).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect::<Vec<_>>();
+    let mut previews = files(dir.join("cache"));
+    previews.sort();
+    assert_eq!(3, previews.len());
+    assert!(previews[0].ends_with(".preview.jpg") && previews[1].ends_with(".thumb.jpg") && previews[2] == "index", "{:?}", previews);
+    assert_eq!(2, files(dir.join("cache").join("index")).len());
+    // a changed mtime makes the entry stale
+    File::options().write(true).open(a).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+    assert_eq!(None, cache.get(a, PreviewKind::Thumbnail).unwrap());
+    assert!(cache.get(b, PreviewKind::Thumbnail).unwrap().is_some());
+    cache.invalidate(b).unwrap();
+    cache.invalidate(b).unwrap();
+    assert_eq!(None, cache.get(b, PreviewKind::Thumbnail).unwrap());
+    cache.get_or_insert(b, PreviewKind::Thumbnail).unwrap();
+    cache.clear().unwrap();
+    assert_eq!(None, cache.get(b, PreviewKind::Thumbnail).unwrap());
+    assert_eq!(vec!["index"], files(dir.join("cache")));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,158 @@
+use std::sync::Mutex;
+
+/// Rectangle on the sensor, bottom and right are exclusive
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Area {
+    pub top: u32,
+    pub left: u32,
+    pub bottom: u32,
+    pub right: u32
+}
+
+/// Raw quality settings of a camera
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum RawMode {
+    /// Full resolution
+    Raw,
+    /// Medium resolution, called sRAW1 on older models
+    MRaw,
+    /// Small resolution, called sRAW2 on older models
+    SRaw
+}
+
+/// Levels, geometry and color of a camera model
+///
+/// CR2 files do not store these values in their TIFF tags, so they are
+/// taken from this database.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Camera {
+    /// Model name as written to the model tag
+    pub model: String,
+    pub white_level: u32,
+    pub black_level: u32,
+    /// Area of the sensor that is exposed to light
+    pub active_area: Option<Area>,
+    /// Width and height of the raw data including the masked borders
+    pub sensor_size: Option<(u32, u32)>,
+    /// Colors of the top left 2x2 pixels, 0 = red, 1 = green, 2 = blue
+    pub cfa_pattern: Option<[u8; 4]>,
+    /// Area of the sensor in the camera JPEG
+    pub default_crop: Option<Area>,
+    /// XYZ to camera color matrix for D65, row by row
+    pub color_matrix: Option<[f64; 9]>,
+    pub raw_modes: Vec<RawMode>
+}
+
+struct Model {
+    model: &'static str,
+    white_level: u32,
+    black_level: u32,
+    /// top, left, bottom, right
+    active_area: [u32; 4],
+    sensor_size: (u32, u32),
+    /// Matrix scaled by 10000
+    color_matrix: [i32; 9],
+    raw_modes: &'static [RawMode]
+}
+
+const RGGB: [u8; 4] = [0, 1, 1, 2];
+const ALL_MODES: &[RawMode] = &[RawMode::Raw, RawMode::MRaw, RawMode::SRaw];
+
+/// White levels and color matrices are the ones used by dcraw, the
+/// active areas are the sensor borders the cameras write to their
+/// MakerNote. All models have a RGGB color filter array.
+const CAMERAS: &[Model] = &[
+    Model { model: "Canon EOS 5D Mark II", white_level: 0x3cf0, black_level: 1024,
+            active_area: [56, 168, 3800, 5784], sensor_size: (5792, 3804),
+            color_matrix: [4716, 603, -830, -7798, 15474, 2480, -1496, 1937, 6651], raw_modes: ALL_MODES },
+    Model { model: "Canon EOS 5D Mark III", white_level: 0x3c80, black_level: 2048,
+            active_area: [80, 122, 3920, 5882], sensor_size: (5920, 3950),
+            color_matrix: [6722, -635, -963, -4287, 12460, 2028, -908, 2162, 5668], raw_modes: ALL_MODES },
+    Model { model: "Canon EOS 6D", white_level: 0x3c82, black_level: 2048,
+            active_area: [38, 72, 3686, 5544], sensor_size: (5568, 3708),
+            color_matrix: [7034, -804, -1014, -4420, 12564, 2058, -851, 1994, 5758], raw_modes: ALL_MODES },
+    Model { model: "Canon EOS 7D", white_level: 0x3510, black_level: 1024,
+            active_area: [56, 168, 3512, 5352], sensor_size: (5360, 3516),
+            color_matrix: [6844, -996, -856, -3876, 11761, 2396, -593, 1772, 6198], raw_modes: ALL_MODES },
+    Model { model: "Canon EOS 550D", white_level: 0x3dd7, black_level: 1024,
+            active_area: [56, 152, 3512, 5336], sensor_size: (5344, 3516),
+            color_matrix: [6941, -1164, -857, -3825, 11597, 2534, -416, 1540, 6039], raw_modes: &[RawMode::Raw] },
+];
+
+fn area(a: [u32; 4]) -> Area {
+    Area{top: a[0], left: a[1], bottom: a[2], right: a[3]}
+}
+
+/// Values set by the user, these take precedence over the built-in ones
+static OVERRIDES: Mutex<Vec<Camera>> = Mutex::new(Vec::new());
+
+/// Looks up a camera model, user overrides first
+pub fn lookup(model: &str) -> Option<Camera> {
+    let model = model.trim();
+    if let Some(c) = OVERRIDES.lock().unwrap().iter().find(|c| c.model == model) {
+        return Some(c.clone());
+    }
+    CAMERAS.iter().find(|c| c.model == model).map(|c| {
+        let mut matrix = [0.0; 9];
+        for (m, v) in matrix.iter_mut().zip(c.color_matrix.iter()) {
+            *m = *v as f64 / 10000.0;
+        }
+        Camera {
+            model: c.model.to_string(),
+            white_level: c.white_level,
+            black_level: c.black_level,
+            active_area: Some(area(c.active_area)),
+            sensor_size: Some(c.sensor_size),
+            cfa_pattern: Some(RGGB),
+            // the built-in models show the whole active area in their JPEGs
+            default_crop: Some(area(c.active_area)),
+            color_matrix: Some(matrix),
+            raw_modes: c.raw_modes.to_vec()
+        }
+    })
+}
+
+/// Names of the models in the database
+pub fn models() -> Vec<&'static str> {
+    CAMERAS.iter().map(|c| c.model).collect()
+}
+
+/// Replaces the values for a camera model or adds an unknown model
+pub fn set_override(camera: Camera) {
+    let mut o = OVERRIDES.lock().unwrap();
+    o.retain(|c| c.model != camera.model);
+    o.push(camera);
+}
+
+/// Removes a user override, the built-in values are used again
+pub fn remove_override(model: &str) {
+    OVERRIDES.lock().unwrap().retain(|c| c.model != model);
+}
+
+#[test]
+fn test_lookup() {
+    let c = lookup("Canon EOS 5D Mark III").unwrap();
+    assert_eq!(0x3c80,c.white_level);
+    let a = c.active_area.unwrap();
+    assert_eq!((5760,3840),(a.right-a.left,a.bottom-a.top));
+    assert_eq!(Some((5920,3950)),c.sensor_size);
+    assert_eq!(Some(0.6722),c.color_matrix.map(|m| m[0]));
+    assert_eq!(vec![RawMode::Raw],lookup("Canon EOS 550D").unwrap().raw_modes);
+    assert!(lookup("Canon EOS 5D Mark IV").is_none());
+    for m in models() {
+        let c = lookup(m).unwrap();
+        let (w, h) = c.sensor_size.unwrap();
+        let a = c.active_area.unwrap();
+        assert!(a.right <= w && a.bottom <= h, "{}", m);
+    }
+}
+
+#[test]
+fn test_override() {
+    let mut c = lookup("Canon EOS 6D").unwrap();
+    c.white_level = 16383;
+    set_override(c);
+    assert_eq!(16383,lookup("Canon EOS 6D").unwrap().white_level);
+    remove_override("Canon EOS 6D");
+    assert_eq!(0x3c82,lookup("Canon EOS 6D").unwrap().white_level);
+}
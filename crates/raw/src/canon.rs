@@ -0,0 +1,215 @@
+/// AF area mode of the camera
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AfAreaMode {
+    ManualFocus,
+    PointExpansionSurround,
+    SinglePoint,
+    Auto,
+    FaceDetect,
+    FaceTracking,
+    Zone,
+    PointExpansion4,
+    Spot,
+    PointExpansion8,
+    Other(u16)
+}
+
+impl From<u16> for AfAreaMode {
+    fn from(v: u16) -> AfAreaMode {
+        match v {
+            0 => AfAreaMode::ManualFocus,
+            1 => AfAreaMode::PointExpansionSurround,
+            2 => AfAreaMode::SinglePoint,
+            4 => AfAreaMode::Auto,
+            5 => AfAreaMode::FaceDetect,
+            6 => AfAreaMode::FaceTracking,
+            7 => AfAreaMode::Zone,
+            8 => AfAreaMode::PointExpansion4,
+            9 => AfAreaMode::Spot,
+            10 => AfAreaMode::PointExpansion8,
+            v => AfAreaMode::Other(v)
+        }
+    }
+}
+
+/// A single AF point
+#[derive(Debug,Clone,PartialEq)]
+pub struct AfPoint {
+    pub width: u16,
+    pub height: u16,
+    /// Center of the point relative to the image center
+    pub x: i16,
+    pub y: i16,
+    pub in_focus: bool,
+    pub selected: bool
+}
+
+/// AF information from the AFInfo2 record
+#[derive(Debug,Clone,PartialEq)]
+pub struct AfInfo {
+    pub area_mode: AfAreaMode,
+    /// Size of the image the point coordinates refer to
+    pub image_width: u16,
+    pub image_height: u16,
+    pub points: Vec<AfPoint>
+}
+
+impl AfInfo {
+    /// Parses the AFInfo2 record (tag 0x0026)
+    ///
+    /// Layout: size, area mode, number of points, valid points, image
+    /// width/height, AF image width/height, followed by the widths,
+    /// heights, x and y positions of all points and two bit fields for
+    /// the points in focus and the selected points.
+    pub(crate) fn parse(v: &[u16]) -> Option<AfInfo> {
+        if v.len() < 8 {
+            return None;
+        }
+        let n = v[2] as usize;
+        let bits = (n + 15) / 16;
+        if v.len() < 8 + 4 * n + 2 * bits {
+            return None;
+        }
+        let bit = |start: usize, i: usize| v[start + i / 16] & (1 << (i % 16)) != 0;
+        let focus = 8 + 4 * n;
+        let selected = focus + bits;
+        let points = (0..n).map(|i| AfPoint {
+            width: v[8 + i],
+            height: v[8 + n + i],
+            x: v[8 + 2 * n + i] as i16,
+            y: v[8 + 3 * n + i] as i16,
+            in_focus: bit(focus, i),
+            selected: bit(selected, i)
+        }).collect();
+        Some(AfInfo {
+            area_mode: AfAreaMode::from(v[1]),
+            image_width: v[6],
+            image_height: v[7],
+            points: points
+        })
+    }
+}
+
+/// Focus distance range in meters from the FileInfo record (tag 0x0093)
+///
+/// Returns (lower, upper), the upper distance is infinite when the
+/// camera writes 0xffff.
+pub(crate) fn focus_distance(file_info: &[u16]) -> Option<(f64,f64)> {
+    if file_info.len() < 22 || (file_info[20] == 0 && file_info[21] == 0) {
+        return None;
+    }
+    let m = |v: u16| if v == 0xffff { ::std::f64::INFINITY } else { v as f64 / 100.0 };
+    Some((m(file_info[21]), m(file_info[20])))
+}
+
+/// Aspect ratio or sensor crop set on the camera
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AspectRatio {
+    Ratio3x2,
+    Ratio1x1,
+    Ratio4x3,
+    Ratio16x9,
+    Ratio4x5,
+    /// 3:2 cropped to the size of an APS-H sensor
+    ApsHCrop,
+    /// 3:2 cropped to the size of an APS-C sensor, the 1.6x crop mode
+    ApsCCrop,
+    Other(u32)
+}
+
+impl From<u32> for AspectRatio {
+    fn from(v: u32) -> AspectRatio {
+        match v {
+            0 => AspectRatio::Ratio3x2,
+            1 => AspectRatio::Ratio1x1,
+            2 => AspectRatio::Ratio4x3,
+            7 => AspectRatio::Ratio16x9,
+            8 => AspectRatio::Ratio4x5,
+            12 => AspectRatio::ApsHCrop,
+            13 => AspectRatio::ApsCCrop,
+            v => AspectRatio::Other(v)
+        }
+    }
+}
+
+/// In-camera crop from the AspectInfo record
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct AspectInfo {
+    pub ratio: AspectRatio,
+    /// Size and position of the crop within the active area
+    pub width: u32,
+    pub height: u32,
+    pub left: u32,
+    pub top: u32
+}
+
+impl AspectInfo {
+    /// Parses the AspectInfo record (tag 0x009a): ratio, width, height,
+    /// left and top. None if the camera did not crop.
+    pub(crate) fn parse(v: &[u32]) -> Option<AspectInfo> {
+        if v.len() < 5 || v[1] == 0 || v[2] == 0 {
+            return None;
+        }
+        Some(AspectInfo { ratio: AspectRatio::from(v[0]), width: v[1], height: v[2], left: v[3], top: v[4] })
+    }
+}
+
+/// Camera attitude from the electronic level, LevelInfo record
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Level {
+    /// Clockwise rotation around the lens axis in degrees
+    pub roll: f64,
+    /// Upward tilt in degrees
+    pub pitch: f64
+}
+
+impl Level {
+    /// Parses the LevelInfo record (tag 0x4059) of signed 32 bit values,
+    /// roll and pitch are the 5th and 6th in 1/100 degrees
+    pub(crate) fn parse(v: &[i32]) -> Option<Level> {
+        if v.len() < 6 {
+            return None;
+        }
+        Some(Level { roll: v[4] as f64 / 100.0, pitch: v[5] as f64 / 100.0 })
+    }
+}
+
+#[test]
+fn test_af_info() {
+    // 3 points, point 1 in focus, point 1 and 2 selected
+    let v = [0, 2, 3, 3, 5760, 3840, 5760, 3840,
+             100, 100, 100,  120, 120, 120,
+             0xff9c, 0, 100,  0, 0, 0,
+             0b010, 0b110];
+    let af = AfInfo::parse(&v).unwrap();
+    assert_eq!(AfAreaMode::SinglePoint, af.area_mode);
+    assert_eq!(3, af.points.len());
+    assert_eq!(-100, af.points[0].x);
+    assert!(!af.points[0].in_focus && af.points[1].in_focus);
+    assert!(!af.points[0].selected && af.points[2].selected);
+    assert!(AfInfo::parse(&v[..12]).is_none());
+}
+
+#[test]
+fn test_focus_distance() {
+    let mut v = [0u16; 22];
+    assert_eq!(None, focus_distance(&v));
+    v[20] = 0xffff;
+    v[21] = 250;
+    assert_eq!(Some((2.5, ::std::f64::INFINITY)), focus_distance(&v));
+}
+
+#[test]
+fn test_aspect_info() {
+    let a = AspectInfo::parse(&[7, 5472, 3072, 0, 288]).unwrap();
+    assert_eq!(AspectRatio::Ratio16x9, a.ratio);
+    assert_eq!((5472, 288), (a.width, a.top));
+    assert_eq!(None, AspectInfo::parse(&[0, 0, 0, 0, 0]));
+}
+
+#[test]
+fn test_level() {
+    let l = Level::parse(&[44, 0, 0, 0, -150, 320]).unwrap();
+    assert_eq!((-1.5, 3.2), (l.roll, l.pitch));
+    assert_eq!(None, Level::parse(&[44]));
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::{Path,PathBuf};
+use std::thread;
+use decoder;
+use datetime;
+
+/// Metadata of a raw file in the catalog
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// Capture time as set on the camera clock
+    pub capture_time: Option<i64>,
+    /// Exposure time in seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// Focal length in mm
+    pub focal_length: Option<f64>
+}
+
+impl Entry {
+    fn read(path: &Path) -> Result<Entry,String> {
+        let image = try!(decoder::open(path.to_string_lossy().into_owned()).map_err(|e| e.to_string()));
+        let e = image.exif_summary();
+        Ok(Entry {
+            path: path.to_path_buf(),
+            make: image.make().map(String::from),
+            model: image.model().map(String::from),
+            lens: image.lens().map(String::from),
+            capture_time: image.capture_time(),
+            exposure_time: e.exposure_time,
+            f_number: e.f_number,
+            iso: e.iso,
+            focal_length: e.focal_length
+        })
+    }
+}
+
+/// Metadata index of all raw files below a directory
+#[derive(Debug,Clone,Default)]
+pub struct Catalog {
+    entries: Vec<Entry>,
+    errors: Vec<(PathBuf,String)>
+}
+
+/// Returns true for the file types open() can read
+fn supported(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).map(|e| e.eq_ignore_ascii_case("cr2")).unwrap_or(false)
+}
+
+/// Collects the supported files below dir
+///
+/// Symbolic links to directories are not followed, so links back up the
+/// tree do not recurse forever.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+        let path = entry.path();
+        if try!(entry.file_type()).is_dir() {
+            try!(walk(&path, files));
+        } else if supported(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Catalog {
+    /// Reads the metadata of all raw files below root using the given
+    /// number of threads, files that fail to parse are listed in errors()
+    pub fn scan<P: AsRef<Path>>(root: P, threads: usize) -> io::Result<Catalog> {
+        let mut files = Vec::new();
+        try!(walk(root.as_ref(), &mut files));
+        files.sort();
+        let mut catalog = Catalog::default();
+        if files.is_empty() {
+            return Ok(catalog);
+        }
+        let chunk = (files.len() + threads.max(1) - 1) / threads.max(1);
+        let results: Vec<Vec<(PathBuf,Result<Entry,String>)>> = thread::scope(|s| {
+            let workers: Vec<_> = files.chunks(chunk).map(|c| (c, s.spawn(move || {
+                c.iter().map(|p| (p.clone(), Entry::read(p))).collect()
+            }))).collect();
+            // the files of a panicked worker are reported, not dropped
+            workers.into_iter().map(|(c, w)| w.join().unwrap_or_else(|_| {
+                c.iter().map(|p| (p.clone(), Err("Reading panicked".to_string()))).collect()
+            })).collect()
+        });
+        for (path, r) in results.into_iter().flat_map(|r| r) {
+            match r {
+                Ok(e) => catalog.entries.push(e),
+                Err(e) => catalog.errors.push((path, e))
+            }
+        }
+        Ok(catalog)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Files that could not be read, with the error message
+    pub fn errors(&self) -> &[(PathBuf,String)] {
+        &self.errors
+    }
+
+    /// Entries matching a predicate
+    pub fn query<F: Fn(&Entry) -> bool>(&self, f: F) -> Vec<&Entry> {
+        self.entries.iter().filter(|e| f(e)).collect()
+    }
+
+    /// Entries of a camera model
+    pub fn by_model(&self, model: &str) -> Vec<&Entry> {
+        self.query(|e| e.model.as_ref().map(|m| m == model).unwrap_or(false))
+    }
+
+    /// Entries captured between from and to (inclusive), camera time
+    pub fn by_date(&self, from: i64, to: i64) -> Vec<&Entry> {
+        self.query(|e| e.capture_time.map(|t| t >= from && t <= to).unwrap_or(false))
+    }
+
+    /// Exports the catalog as JSON array
+    pub fn to_json(&self) -> String {
+        let s = |v: &Option<String>| v.as_ref().map(|s| json_string(s)).unwrap_or_else(|| "null".to_string());
+        let n = |v: Option<f64>| v.map(|f| f.to_string()).unwrap_or_else(|| "null".to_string());
+        let items: Vec<String> = self.entries.iter().map(|e| format!(
+            "{{\"path\":{},\"make\":{},\"model\":{},\"lens\":{},\"capture_time\":{},\
+             \"exposure_time\":{},\"f_number\":{},\"iso\":{},\"focal_length\":{}}}",
+            json_string(&e.path.to_string_lossy()), s(&e.make), s(&e.model), s(&e.lens),
+            s(&e.capture_time.map(datetime::format_exif)), n(e.exposure_time), n(e.f_number),
+            n(e.iso.map(|i| i as f64)), n(e.focal_length))).collect();
+        format!("[{}]", items.join(",\n "))
+    }
+}
+
+/// Quotes and escapes a JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut j = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => j.push_str("\\\""),
+            '\\' => j.push_str("\\\\"),
+            '\n' => j.push_str("\\n"),
+            c if (c as u32) < 0x20 => j.push_str(&format!("\\u{:04x}", c as u32)),
+            c => j.push(c)
+        }
+    }
+    j.push('"');
+    j
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!("\"a\\\"b\\\\c\\n\\u0001\"", json_string("a\"b\\c\n\u{1}"));
+}
+
+#[test]
+fn test_supported() {
+    assert!(supported(Path::new("IMG_0001.CR2")));
+    assert!(supported(Path::new("a/b.cr2")));
+    assert!(!supported(Path::new("IMG_0001.JPG")));
+}
+
+#[test]
+fn test_scan() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let root = ::std::env::temp_dir().join(format!("rraw-catalog-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("sub")).unwrap();
+    let build = |model| Cr2Builder::new().ifd(Ifd::new().tag(Tag::ascii(0x110, model)).strip(&[0xff, 0xd8])).build();
+    fs::write(root.join("a.cr2"), build("Canon EOS 6D")).unwrap();
+    fs::write(root.join("sub").join("B.CR2"), build("Canon EOS 5D")).unwrap();
+    fs::write(root.join("broken.cr2"), b"II*\0").unwrap();
+    fs::write(root.join("notes.txt"), b"not a raw file").unwrap();
+    // a link back to the root must not be followed
+    #[cfg(unix)]
+    ::std::os::unix::fs::symlink(&root, root.join("sub").join("loop")).unwrap();
+    for threads in 1..4 {
+        let c = Catalog::scan(&root, threads).unwrap();
+        assert_eq!(2, c.entries().len());
+        assert_eq!(vec![root.join("broken.cr2")], c.errors().iter().map(|e| e.0.clone()).collect::<Vec<_>>());
+        assert_eq!(root.join("sub").join("B.CR2"), c.by_model("Canon EOS 5D")[0].path);
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
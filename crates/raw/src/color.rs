@@ -0,0 +1,221 @@
+/// 3x3 matrix, row by row
+pub type Matrix = [f64; 9];
+
+/// White points as XYZ with Y = 1
+pub const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+pub const D50: [f64; 3] = [0.96422, 1.0, 0.82521];
+
+/// Linear sRGB to XYZ, D65 white
+pub const SRGB_TO_XYZ: Matrix = [
+    0.4124564, 0.3575761, 0.1804375,
+    0.2126729, 0.7151522, 0.0721750,
+    0.0193339, 0.1191920, 0.9503041
+];
+
+/// XYZ to linear sRGB, D65 white
+pub const XYZ_TO_SRGB: Matrix = [
+    3.2404542, -1.5371385, -0.4985314,
+    -0.9692660, 1.8760108, 0.0415560,
+    0.0556434, -0.2040259, 1.0572252
+];
+
+/// Cone response matrix of the Bradford chromatic adaptation
+const BRADFORD: Matrix = [
+    0.8951, 0.2664, -0.1614,
+    -0.7502, 1.7135, 0.0367,
+    0.0389, -0.0685, 1.0296
+];
+
+pub fn multiply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    [m[0] * v[0] + m[1] * v[1] + m[2] * v[2],
+     m[3] * v[0] + m[4] * v[1] + m[5] * v[2],
+     m[6] * v[0] + m[7] * v[1] + m[8] * v[2]]
+}
+
+/// Product a * b, applying it applies b first
+pub fn concat(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [0.0; 9];
+    for r in 0..3 {
+        for c in 0..3 {
+            m[r * 3 + c] = (0..3).map(|k| a[r * 3 + k] * b[k * 3 + c]).sum();
+        }
+    }
+    m
+}
+
+/// Inverse of a matrix, None if it is singular
+pub fn invert(m: &Matrix) -> Option<Matrix> {
+    let cof = [
+        m[4] * m[8] - m[5] * m[7], m[2] * m[7] - m[1] * m[8], m[1] * m[5] - m[2] * m[4],
+        m[5] * m[6] - m[3] * m[8], m[0] * m[8] - m[2] * m[6], m[2] * m[3] - m[0] * m[5],
+        m[3] * m[7] - m[4] * m[6], m[1] * m[6] - m[0] * m[7], m[0] * m[4] - m[1] * m[3]
+    ];
+    let det = m[0] * cof[0] + m[1] * cof[3] + m[2] * cof[6];
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let mut inv = [0.0; 9];
+    for (i, c) in cof.iter().enumerate() {
+        inv[i] = c / det;
+    }
+    Some(inv)
+}
+
+/// Camera RGB to XYZ (D65) from the XYZ to camera matrix of the camera
+/// database, see RawImage::color_matrix
+///
+/// The rows are scaled so that camera white maps to the D65 white point.
+pub fn camera_to_xyz(xyz_to_camera: &Matrix) -> Option<Matrix> {
+    let mut m = *xyz_to_camera;
+    // normalize the camera response to D65 white
+    for r in 0..3 {
+        let sum: f64 = (0..3).map(|c| m[r * 3 + c] * D65[c]).sum();
+        if sum.abs() < 1e-12 {
+            return None;
+        }
+        for c in 0..3 {
+            m[r * 3 + c] /= sum;
+        }
+    }
+    invert(&m)
+}
+
+/// Chromatic adaptation from one white point to another (Bradford)
+pub fn adaptation(from: [f64; 3], to: [f64; 3]) -> Matrix {
+    let (f, t) = (multiply(&BRADFORD, from), multiply(&BRADFORD, to));
+    let scale = [t[0] / f[0], 0.0, 0.0, 0.0, t[1] / f[1], 0.0, 0.0, 0.0, t[2] / f[2]];
+    let inverse = invert(&BRADFORD).unwrap_or(BRADFORD);
+    concat(&inverse, &concat(&scale, &BRADFORD))
+}
+
+/// sRGB transfer function, linear to encoded
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Inverse of srgb_encode
+pub fn srgb_decode(x: f64) -> f64 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Encoded sRGB to XYZ (D65)
+pub fn srgb_to_xyz(rgb: [f64; 3]) -> [f64; 3] {
+    multiply(&SRGB_TO_XYZ, [srgb_decode(rgb[0]), srgb_decode(rgb[1]), srgb_decode(rgb[2])])
+}
+
+/// XYZ (D65) to encoded sRGB, values are not clipped
+pub fn xyz_to_srgb(xyz: [f64; 3]) -> [f64; 3] {
+    let l = multiply(&XYZ_TO_SRGB, xyz);
+    [srgb_encode(l[0]), srgb_encode(l[1]), srgb_encode(l[2])]
+}
+
+const EPSILON: f64 = 216.0 / 24389.0;
+const KAPPA: f64 = 24389.0 / 27.0;
+
+/// XYZ to CIELAB relative to a white point
+pub fn xyz_to_lab(xyz: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+    let f = |t: f64| if t > EPSILON { t.cbrt() } else { (KAPPA * t + 16.0) / 116.0 };
+    let (fx, fy, fz) = (f(xyz[0] / white[0]), f(xyz[1] / white[1]), f(xyz[2] / white[2]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Inverse of xyz_to_lab
+pub fn lab_to_xyz(lab: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let (fx, fz) = (fy + lab[1] / 500.0, fy - lab[2] / 200.0);
+    let f = |t: f64| if t * t * t > EPSILON { t * t * t } else { (116.0 * t - 16.0) / KAPPA };
+    [f(fx) * white[0], f(fy) * white[1], f(fz) * white[2]]
+}
+
+/// CIEDE2000 color difference of two Lab colors
+pub fn delta_e_2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let deg = |r: f64| r.to_degrees();
+    let rad = |d: f64| d.to_radians();
+    let pow7 = |c: f64| c.powi(7) / (c.powi(7) + 25f64.powi(7));
+    let c_bar = ((lab1[1] * lab1[1] + lab1[2] * lab1[2]).sqrt() + (lab2[1] * lab2[1] + lab2[2] * lab2[2]).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - pow7(c_bar).sqrt());
+    let prime = |lab: [f64; 3]| {
+        let a = (1.0 + g) * lab[1];
+        let h = if a == 0.0 && lab[2] == 0.0 { 0.0 } else { (deg(lab[2].atan2(a)) + 360.0) % 360.0 };
+        ((a * a + lab[2] * lab[2]).sqrt(), h)
+    };
+    let ((c1, h1), (c2, h2)) = (prime(lab1), prime(lab2));
+    let dl = lab2[0] - lab1[0];
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 { 0.0 }
+             else if (h2 - h1).abs() <= 180.0 { h2 - h1 }
+             else if h2 - h1 > 180.0 { h2 - h1 - 360.0 }
+             else { h2 - h1 + 360.0 };
+    let dh = 2.0 * (c1 * c2).sqrt() * (rad(dh) / 2.0).sin();
+    let l = (lab1[0] + lab2[0]) / 2.0;
+    let c = (c1 + c2) / 2.0;
+    let h = if c1 * c2 == 0.0 { h1 + h2 }
+            else if (h1 - h2).abs() <= 180.0 { (h1 + h2) / 2.0 }
+            else if h1 + h2 < 360.0 { (h1 + h2 + 360.0) / 2.0 }
+            else { (h1 + h2 - 360.0) / 2.0 };
+    let t = 1.0 - 0.17 * rad(h - 30.0).cos() + 0.24 * rad(2.0 * h).cos() + 0.32 * rad(3.0 * h + 6.0).cos()
+            - 0.20 * rad(4.0 * h - 63.0).cos();
+    let theta = 30.0 * (-((h - 275.0) / 25.0).powi(2)).exp();
+    let rc = 2.0 * pow7(c).sqrt();
+    let sl = 1.0 + 0.015 * (l - 50.0).powi(2) / (20.0 + (l - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * c;
+    let sh = 1.0 + 0.015 * c * t;
+    let rt = -(rad(2.0 * theta)).sin() * rc;
+    ((dl / sl).powi(2) + (dc / sc).powi(2) + (dh / sh).powi(2) + rt * (dc / sc) * (dh / sh)).sqrt()
+}
+
+/// Summary of the differences between two images
+#[derive(Debug,Clone,PartialEq)]
+pub struct Difference {
+    /// CIEDE2000 difference of each pixel
+    pub pixels: Vec<f64>,
+    pub mean: f64,
+    pub max: f64,
+    /// 95th percentile
+    pub p95: f64
+}
+
+/// Compares two images of encoded sRGB pixels of the same size, e.g.
+/// the output of two demosaic algorithms
+pub fn compare(a: &[[f64; 3]], b: &[[f64; 3]]) -> Option<Difference> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let lab = |rgb| xyz_to_lab(srgb_to_xyz(rgb), D65);
+    let pixels: Vec<f64> = a.iter().zip(b.iter()).map(|(x, y)| delta_e_2000(lab(*x), lab(*y))).collect();
+    let mut sorted = pixels.clone();
+    sorted.sort_by(|x, y| x.partial_cmp(y).unwrap_or(::std::cmp::Ordering::Equal));
+    Some(Difference {
+        mean: pixels.iter().sum::<f64>() / pixels.len() as f64,
+        max: sorted[sorted.len() - 1],
+        p95: sorted[(sorted.len() - 1) * 95 / 100],
+        pixels: pixels
+    })
+}
+
+#[test]
+fn test_conversions() {
+    let close = |a: [f64; 3], b: [f64; 3], eps: f64| (0..3).all(|i| (a[i] - b[i]).abs() < eps);
+    let white = srgb_to_xyz([1.0, 1.0, 1.0]);
+    assert!(close(white, D65, 1e-4), "{:?}", white);
+    assert!(close(xyz_to_lab(white, D65), [100.0, 0.0, 0.0], 1e-2));
+    let rgb = [0.2, 0.6, 0.9];
+    assert!(close(xyz_to_srgb(srgb_to_xyz(rgb)), rgb, 1e-6));
+    let lab = [40.0, 20.0, -30.0];
+    assert!(close(xyz_to_lab(lab_to_xyz(lab, D50), D50), lab, 1e-9));
+    assert!(close(multiply(&adaptation(D65, D50), D65), D50, 1e-9));
+    let m = [0.6722, -0.0635, -0.0963, -0.4287, 1.2460, 0.2028, -0.0908, 0.2162, 0.5668];
+    let cam = camera_to_xyz(&m).unwrap();
+    assert!(close(multiply(&cam, [1.0, 1.0, 1.0]), D65, 1e-9));
+}
+
+#[test]
+fn test_delta_e_2000() {
+    // reference pairs from Sharma, Wu and Dalal
+    assert!((delta_e_2000([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485]) - 2.0425).abs() < 1e-4);
+    assert!((delta_e_2000([50.0, 0.0, 0.0], [50.0, -1.0, 2.0]) - 2.3669).abs() < 1e-4);
+    assert!((delta_e_2000([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514]) - 0.9082).abs() < 1e-4);
+    let d = compare(&[[0.5, 0.5, 0.5], [1.0, 0.0, 0.0]], &[[0.5, 0.5, 0.5], [1.0, 0.0, 0.0]]).unwrap();
+    assert_eq!((0.0, 0.0), (d.mean, d.max));
+    assert!(compare(&[[0.0; 3]], &[]).is_none());
+}
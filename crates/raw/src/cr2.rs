@@ -1,15 +1,29 @@
-use std::fs::File;
-use std::io::{self,Seek,Read};
+use std::fs::{self,File};
+use std::io::{self,Seek,Read,Write};
 use std::str;
 use std::mem;
+use std::ptr;
 use std::fmt;
 use std::error::Error;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::any::Any;
-use std::ops::Deref;
+use std::path::Path;
+use cameras::{self,Camera,Area};
+use datetime;
+use hash::{Sha256,Algorithm,Hasher};
+use canon::{self,AfInfo,AspectInfo,Level};
+use exif::{self,ExifSummary,DriveMode,Flash};
+use source::{RawSource,FileSource,SourceReader,StreamSource};
+use trace::{Trace,Event};
+use jpeg;
+use sidecar;
+use validate::Warning;
+use editor::{Editor,Location,Value};
 
-/// Byte order of the containing data
-enum ByteOrder {
+/// Byte order of the containing data, also used for writing
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ByteOrder {
     /// little endian
     Intel,
     /// big endian
@@ -17,107 +31,1500 @@ enum ByteOrder {
 }
 
 /// Sets the default byte order to little endian
-/// TODO big endian is not implemented yet
 impl Default for ByteOrder{
     fn default() -> ByteOrder { ByteOrder::Intel }
 }
 
+impl ByteOrder {
+    /// Reads a value stored in this byte order
+    fn read<T: Copy>(self, b: &[u8]) -> Option<T> {
+        match self {
+            ByteOrder::Intel => b.to::<T>(),
+            ByteOrder::Motorola => {
+                let mut v = b.to_vec();
+                v.reverse();
+                v.to::<T>()
+            }
+        }
+    }
+
+    /// Reads a rational, numerator in the low half as for little endian files
+    fn read_rational(self, b: &[u8]) -> Option<u64> {
+        match (b.get(0..4).and_then(|n| self.read::<u32>(n)), b.get(4..8).and_then(|d| self.read::<u32>(d))) {
+            (Some(n), Some(d)) if b.len() == 8 => Some(n as u64 | (d as u64) << 32),
+            _ => None
+        }
+    }
+
+    fn swapped(self) -> ByteOrder {
+        match self {
+            ByteOrder::Intel => ByteOrder::Motorola,
+            ByteOrder::Motorola => ByteOrder::Intel
+        }
+    }
+}
+
 /// Error types for the raw file reader
+#[derive(Debug)]
 pub enum RawFileError {
     Io(io::Error),
     Utf8(str::Utf8Error),
-    FileFormat(String),
+    /// Malformed file, with the position where parsing failed if known
+    FileFormat {
+        kind: FormatErrorKind,
+        /// English description, may change between versions
+        msg: String,
+        /// Absolute file offset
+        offset: Option<u64>,
+        /// IFD name as in tag lists, e.g. "ifd0" or "exif"
+        ifd: Option<String>,
+        tag: Option<u16>
+    },
     Seek(u64),
     NotImplemented(String),
     TypeError(u16)
 }
 
-/// Display error messages
-impl fmt::Display for RawFileError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-         match *self {
-            RawFileError::Io(ref e) => {write!(f,"IO error: {}",e.description())},
-            RawFileError::Utf8(ref e) => {write!(f,"Utf8 conversion error: {}",e.description())},
-            RawFileError::FileFormat(ref s) => {write!(f,"File format error: {}",s)},
-            RawFileError::Seek(p) => {write!(f,"Seek error: {}",p)},
-            RawFileError::NotImplemented(ref s) => {write!(f,"Feature not Implemented: {}",s)}
-            RawFileError::TypeError(u) => {write!(f,"Unknown Type: {}",u)}
+/// Display error messages
+impl fmt::Display for RawFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+         match *self {
+            RawFileError::Io(ref e) => {write!(f,"IO error: {}",e.description())},
+            RawFileError::Utf8(ref e) => {write!(f,"Utf8 conversion error: {}",e.description())},
+            RawFileError::FileFormat{ref msg,offset,ref ifd,tag,..} => {
+                try!(write!(f,"File format error: {}",msg));
+                if let Some(o) = offset { try!(write!(f," at offset 0x{:x}",o)); }
+                if let Some(ref i) = *ifd { try!(write!(f," in {}",i)); }
+                if let Some(t) = tag { try!(write!(f," tag 0x{:04x}",t)); }
+                Ok(())
+            },
+            RawFileError::Seek(p) => {write!(f,"Seek error: {}",p)},
+            RawFileError::NotImplemented(ref s) => {write!(f,"Feature not Implemented: {}",s)}
+            RawFileError::TypeError(u) => {write!(f,"Unknown Type: {}",u)}
+        }
+    } 
+}
+
+/// Stable numeric codes of the RawFileError variants, for FFI and log
+/// aggregation. Codes are never reused.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum ErrorCode {
+    Io = 1,
+    Utf8 = 2,
+    FileFormat = 3,
+    Seek = 4,
+    NotImplemented = 5,
+    TypeError = 6
+}
+
+impl ErrorCode {
+    /// The code of a number, None for numbers that are no code
+    pub fn from_code(code: u32) -> Option<ErrorCode> {
+        Some(match code {
+            1 => ErrorCode::Io,
+            2 => ErrorCode::Utf8,
+            3 => ErrorCode::FileFormat,
+            4 => ErrorCode::Seek,
+            5 => ErrorCode::NotImplemented,
+            6 => ErrorCode::TypeError,
+            _ => return None
+        })
+    }
+}
+
+/// What is wrong with a malformed file, stable like ErrorCode so that
+/// applications can translate format errors
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum FormatErrorKind {
+    /// Byte order, magic number or signature do not match the format
+    BadMagic,
+    /// Data ends before a structure or value it refers to
+    Truncated,
+    /// A structure or value that is needed is absent
+    Missing,
+    /// A size exceeds a limit of the format or of the reader
+    TooLarge,
+    /// Any other malformed structure or value
+    Invalid
+}
+
+impl FormatErrorKind {
+    /// Key of the kind as returned by RawFileError::details
+    pub fn key(&self) -> &'static str {
+        match *self {
+            FormatErrorKind::BadMagic => "bad_magic",
+            FormatErrorKind::Truncated => "truncated",
+            FormatErrorKind::Missing => "missing",
+            FormatErrorKind::TooLarge => "too_large",
+            FormatErrorKind::Invalid => "invalid"
+        }
+    }
+}
+
+/// Renders errors from their code and details, e.g. in the language of
+/// the application. Display renders them in English.
+pub trait ErrorMessages {
+    fn message(&self, code: ErrorCode, details: &[(&'static str,String)]) -> String;
+}
+
+impl RawFileError {
+    /// Payload of the error as (key, value) pairs, keys are:
+    /// - "cause": text of the underlying IO or UTF-8 error
+    /// - "kind": key of the FormatErrorKind of a format error
+    /// - "msg": English description of a format error
+    /// - "offset": absolute file offset, in hex with 0x
+    /// - "ifd": IFD name as in tag lists
+    /// - "tag": tag id, in hex with 0x
+    /// - "position": position of a failed seek
+    /// - "feature": English name of an unsupported feature
+    /// - "type": unknown TIFF type
+    pub fn details(&self) -> Vec<(&'static str,String)> {
+        match *self {
+            RawFileError::Io(ref e) => vec![("cause",e.to_string())],
+            RawFileError::Utf8(ref e) => vec![("cause",e.to_string())],
+            RawFileError::FileFormat{kind,ref msg,offset,ref ifd,tag} => {
+                let mut d = vec![("kind",kind.key().to_string()),("msg",msg.clone())];
+                if let Some(o) = offset { d.push(("offset",format!("0x{:x}",o))); }
+                if let Some(ref i) = *ifd { d.push(("ifd",i.clone())); }
+                if let Some(t) = tag { d.push(("tag",format!("0x{:04x}",t))); }
+                d
+            },
+            RawFileError::Seek(p) => vec![("position",p.to_string())],
+            RawFileError::NotImplemented(ref s) => vec![("feature",s.clone())],
+            RawFileError::TypeError(t) => vec![("type",t.to_string())]
+        }
+    }
+
+    /// Renders the error with the messages of the application
+    pub fn render(&self, messages: &dyn ErrorMessages) -> String {
+        messages.message(self.code(),&self.details())
+    }
+
+    /// Code of the variant, `code() as u32` gives the number
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            RawFileError::Io(_) => ErrorCode::Io,
+            RawFileError::Utf8(_) => ErrorCode::Utf8,
+            RawFileError::FileFormat{..} => ErrorCode::FileFormat,
+            RawFileError::Seek(_) => ErrorCode::Seek,
+            RawFileError::NotImplemented(_) => ErrorCode::NotImplemented,
+            RawFileError::TypeError(_) => ErrorCode::TypeError
+        }
+    }
+
+    /// Format error without position
+    pub fn format<S: Into<String>>(kind: FormatErrorKind, msg: S) -> RawFileError {
+        RawFileError::FileFormat{kind: kind, msg: msg.into(), offset: None, ifd: None, tag: None}
+    }
+
+    /// Adds the position to a format error, a position already set is
+    /// kept. Reading past the end of the file becomes a format error.
+    fn context(self, offset: Option<u64>, ifd: Option<&str>, tag: Option<u16>) -> RawFileError {
+        match self {
+            RawFileError::FileFormat{kind,msg,offset: o,ifd: i,tag: t} => RawFileError::FileFormat{
+                kind: kind,
+                msg: msg,
+                offset: o.or(offset),
+                ifd: i.or_else(|| ifd.map(String::from)),
+                tag: t.or(tag)
+            },
+            RawFileError::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+                RawFileError::format(FormatErrorKind::Truncated, "Unexpected end of file").context(offset,ifd,tag),
+            e => e
+        }
+    }
+}
+
+    impl From<io::Error> for RawFileError {
+        fn from(e: io::Error) -> RawFileError {
+            RawFileError::Io(e)
+        }
+    }
+
+    impl From<str::Utf8Error> for RawFileError {
+        fn from(e: str::Utf8Error) -> RawFileError {
+            RawFileError::Utf8(e)
+        }
+    }
+
+    /// A tag value, by TIFF type:
+    /// - BYTE, SHORT, LONG, UNDEFINED: Unsigned, one per value
+    /// - SBYTE, SSHORT, SLONG: Signed, one per value
+    /// - RATIONAL: U64, numerator in the low and denominator in the high word
+    /// - SRATIONAL: I64, laid out as U64
+    /// - ASCII: one Strg for all characters, trailing NULs removed
+    /// - FLOAT, DOUBLE: Float
+    #[derive(Debug,Clone,PartialEq)]
+    pub enum TagData {
+        Unsigned(u32),
+        Signed(i32),
+        U64(u64),
+        I64(i64),
+        Strg(String),
+        Float(f64)
+    }
+
+    impl TagData {
+        pub fn as_u32(&self) -> Option<u32> {
+            match *self {
+                TagData::Unsigned(u) => Some(u),
+                _ => None
+            }
+        }
+
+        /// Value of an integer, signed or not
+        pub fn as_i64(&self) -> Option<i64> {
+            match *self {
+                TagData::Unsigned(u) => Some(u as i64),
+                TagData::Signed(i) => Some(i as i64),
+                _ => None
+            }
+        }
+
+        /// Value of a number, None for strings and rationals with a zero
+        /// denominator
+        pub fn as_f64(&self) -> Option<f64> {
+            let (n,d) = match *self {
+                TagData::U64(v) => ((v & 0xffffffff) as f64, (v >> 32) as f64),
+                TagData::I64(v) => (v as i32 as f64, (v >> 32) as i32 as f64),
+                TagData::Float(f) => return Some(f),
+                TagData::Unsigned(u) => return Some(u as f64),
+                TagData::Signed(i) => return Some(i as f64),
+                TagData::Strg(_) => return None
+            };
+            if d == 0.0 { None } else { Some(n/d) }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match *self {
+                TagData::Strg(ref s) => Some(s),
+                _ => None
+            }
+        }
+    }
+
+    /// Formats the value as in tag lists, rationals as fractions
+    impl fmt::Display for TagData {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                TagData::Unsigned(u) => write!(f,"{}",u),
+                TagData::Signed(i) => write!(f,"{}",i),
+                TagData::U64(u) => write!(f,"{}/{}",u & 0xffffffff,u >> 32),
+                TagData::I64(i) => write!(f,"{}/{}",i as i32,(i >> 32) as i32),
+                TagData::Strg(ref s) => write!(f,"{}",s),
+                TagData::Float(v) => write!(f,"{}",v)
+            }
+        }
+    }
+
+    /// Location of a tag value in the file
+    #[derive(Clone,Copy)]
+    pub(crate) struct TagPos {
+        pub(crate) id: u16,
+        pub(crate) tagtype: u16,
+        pub(crate) count: usize,
+        /// absolute offset of the IFD entry
+        pub(crate) entry: u64,
+        /// absolute offset of the value, inline values point into the entry
+        pub(crate) offset: u64
+    }
+
+    /// Tag namespaces, the meaning of a tag id depends on the IFD
+    #[derive(Debug,Clone,Copy,PartialEq)]
+    pub enum IfdKind {
+        /// IFDs of the main chain
+        Tiff,
+        Exif,
+        Gps,
+        /// Canon MakerNote
+        Canon
+    }
+
+    /// A tag as read from the file, passed to TagHandlers
+    #[derive(Debug)]
+    pub struct RawTag<'a> {
+        pub kind: IfdKind,
+        pub id: u16,
+        pub tagtype: u16,
+        pub count: usize,
+        /// Absolute file offset of the value
+        pub offset: u64,
+        /// Value bytes in the byte order of the file, shorter than the
+        /// count implies if the file is truncated
+        pub data: &'a [u8]
+    }
+
+    /// Callbacks invoked for tags while parsing, to capture data the
+    /// crate does not model, see open_with_handlers
+    #[derive(Clone,Default)]
+    pub struct TagHandlers {
+        handlers: Vec<(Option<IfdKind>,Option<u16>,Arc<dyn Fn(&RawTag) + Send + Sync>)>
+    }
+
+    impl TagHandlers {
+        pub fn new() -> TagHandlers {
+            Default::default()
+        }
+
+        /// Calls f for each tag with the id, in IFDs of any kind
+        pub fn tag<F: Fn(&RawTag) + Send + Sync + 'static>(mut self, id: u16, f: F) -> TagHandlers {
+            self.handlers.push((None,Some(id),Arc::new(f)));
+            self
+        }
+
+        /// Calls f for each tag of IFDs of the kind
+        pub fn ifd<F: Fn(&RawTag) + Send + Sync + 'static>(mut self, kind: IfdKind, f: F) -> TagHandlers {
+            self.handlers.push((Some(kind),None,Arc::new(f)));
+            self
+        }
+
+        fn call(&self, tag: &RawTag) {
+            for &(kind,id,ref f) in &self.handlers {
+                if kind.map_or(true, |k| k == tag.kind) && id.map_or(true, |i| i == tag.id) {
+                    f(tag);
+                }
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct Ifd {
+        pub(crate) offset: usize,
+        pub(crate) tags: HashMap<String, Vec<TagData>>,
+        pub(crate) pos: HashMap<String, TagPos>
+    }
+
+    impl Ifd {
+        pub(crate) fn new(offset: usize) -> Ifd {
+            Ifd{offset: offset, tags: HashMap::new(), pos: HashMap::new()}
+        }
+    }
+
+    /// Metadata removed before publishing a file, true removes the data
+    #[derive(Debug,Clone,Copy,PartialEq)]
+    pub struct PrivacyPolicy {
+        /// The complete GPS IFD
+        pub gps: bool,
+        /// Serial numbers of the body and the lens
+        pub serial_numbers: bool,
+        /// Owner name set in the camera
+        pub owner: bool
+    }
+
+    /// Removes all private data
+    impl Default for PrivacyPolicy {
+        fn default() -> PrivacyPolicy {
+            PrivacyPolicy{gps: true, serial_numbers: true, owner: true}
+        }
+    }
+
+    impl PrivacyPolicy {
+        /// Tags removed by the policy, the GPS IFD is handled separately
+        fn tags(&self) -> Vec<(IfdKind,&'static str)> {
+            let mut t = Vec::new();
+            if self.serial_numbers {
+                t.extend_from_slice(&[(IfdKind::Exif,"body_serial_number"),
+                    (IfdKind::Exif,"lens_serial_number"),
+                    (IfdKind::Canon,"serial_number"),
+                    (IfdKind::Canon,"internal_serial_number")]);
+            }
+            if self.owner {
+                t.extend_from_slice(&[(IfdKind::Exif,"camera_owner_name"),
+                    (IfdKind::Canon,"owner_name")]);
+            }
+            t
+        }
+    }
+
+    /// Compression of the raw data (TIFF tag 0x103)
+    #[derive(Debug,Clone,Copy,PartialEq)]
+    pub enum Compression {
+        Uncompressed,
+        /// Old-style JPEG, Canon uses it for lossless JPEG raw data
+        OldJpeg,
+        Jpeg,
+        Deflate,
+        PackBits,
+        /// JPEG XL, used for raw data by DNG 1.7
+        JpegXl,
+        Other(u32)
+    }
+
+    impl From<u32> for Compression {
+        fn from(c: u32) -> Compression {
+            match c {
+                1 => Compression::Uncompressed,
+                6 => Compression::OldJpeg,
+                7 => Compression::Jpeg,
+                8 | 32946 => Compression::Deflate,
+                32773 => Compression::PackBits,
+                52546 => Compression::JpegXl,
+                c => Compression::Other(c)
+            }
+        }
+    }
+
+    /// Organization of the raw data in the file
+    #[derive(Debug,Clone,PartialEq)]
+    pub enum Layout {
+        /// Offset and length of each strip
+        Strips(Vec<(u64,u64)>),
+        /// Offset and length of each tile, row by row
+        Tiles { width: u32, length: u32, tiles: Vec<(u64,u64)> }
+    }
+
+    /// Dimensions of the raw data from the lossless JPEG header
+    #[derive(Debug,Clone,Copy,PartialEq)]
+    struct RawFormat {
+        width: u32,
+        height: u32,
+        bits: u16
+    }
+
+    #[derive(Default,Clone)]
+    pub struct RawImage {
+        file_name: String,
+        byte_order: ByteOrder,
+        raw_offset: usize,
+        ifd: Vec<Ifd>,
+        exif: Option<Ifd>,
+        gps: Option<Ifd>,
+        makernote: Option<Ifd>,
+        tags: HashMap<String,Vec<TagData> >,
+        /// Source of images not opened from a file
+        source: Option<Arc<dyn RawSource>>,
+        trace: Option<Trace>,
+        /// Set while parsing only
+        handlers: Option<TagHandlers>,
+        warnings: Vec<Warning>,
+        /// Memory allocated for tag values, limited by MAX_VALUE_BYTES
+        value_bytes: usize,
+        raw_format: Option<RawFormat>
+    }
+
+/// IFD chains are not followed further
+const MAX_IFDS: usize = 32;
+
+/// Limit for the memory of all tag values, the bytes read and the
+/// TagData they are decoded to, files claiming more are rejected
+const MAX_VALUE_BYTES: usize = 16 << 20;
+
+/// Entry counts above this are taken as read in the wrong byte order
+const MAX_ENTRIES: u16 = 255;
+
+
+pub fn open(path: String) -> Result<RawImage,RawFileError>{
+
+    let source = Arc::new(try!(FileSource::open(&path)));
+    let mut image: RawImage = Default::default();
+    image.file_name = path;
+    try!(image.parse(&mut SourceReader::new(source)));
+    Ok(image)
+}
+
+/// Opens a raw file and records a trace of the parse, see RawImage::trace
+pub fn open_traced(path: String) -> Result<RawImage,RawFileError>{
+    let source = Arc::new(try!(FileSource::open(&path)));
+    let mut image: RawImage = Default::default();
+    image.file_name = path;
+    image.trace = Some(Trace::default());
+    try!(image.parse(&mut SourceReader::new(source)));
+    Ok(image)
+}
+
+/// Opens a raw file and calls the handlers for the tags read
+///
+/// The handlers see every tag of a known type, before it is converted,
+/// and are dropped once the file is parsed.
+pub fn open_with_handlers(path: String, handlers: TagHandlers) -> Result<RawImage,RawFileError>{
+    let source = Arc::new(try!(FileSource::open(&path)));
+    let mut image: RawImage = Default::default();
+    image.file_name = path;
+    image.handlers = Some(handlers);
+    let parsed = image.parse(&mut SourceReader::new(source));
+    image.handlers = None;
+    try!(parsed);
+    Ok(image)
+}
+
+/// Opens a raw file held in memory, name is used as its file name
+///
+/// Images opened from memory can be read but not modified in place.
+pub fn open_bytes(data: Vec<u8>, name: String) -> Result<RawImage,RawFileError>{
+    open_source(Arc::new(data),name)
+}
+
+/// Opens a raw file from any storage backend, name is used as its file name
+///
+/// Images opened from a source can be read but not modified in place.
+pub fn open_source(source: Arc<dyn RawSource>, name: String) -> Result<RawImage,RawFileError>{
+    let mut image: RawImage = Default::default();
+    image.file_name = name;
+    image.source = Some(source);
+    let mut f = try!(image.reader());
+    try!(image.parse(&mut f));
+    Ok(image)
+}
+
+/// Bytes of a stream buffered by open_stream, enough for the metadata of
+/// CR2 files
+pub const STREAM_LIMIT: u64 = 4 << 20;
+
+/// Parses the metadata of a raw file from a stream that cannot seek, e.g.
+/// stdin, buffering at most STREAM_LIMIT bytes of it
+///
+/// Previews and raw data beyond the limit cannot be read, use open_reader
+/// for those. The header of the raw data is not read, it usually sits at
+/// the end of the file and would buffer the whole limit, so the size of
+/// the image comes from the tags.
+pub fn open_stream<R: Read + Send + 'static>(r: R, name: String) -> Result<RawImage,RawFileError>{
+    let mut image: RawImage = Default::default();
+    image.file_name = name;
+    image.source = Some(Arc::new(StreamSource::new(r,STREAM_LIMIT)));
+    let mut f = try!(image.reader());
+    try!(image.parse_metadata(&mut f));
+    Ok(image)
+}
+
+/// Opens a raw file from a reader, e.g. an archive entry or a network
+/// stream. The whole file is read into memory.
+pub fn open_reader<R: Read>(mut r: R, name: String) -> Result<RawImage,RawFileError>{
+    let mut data = Vec::new();
+    try!(r.read_to_end(&mut data));
+    open_bytes(data,name)
+}
+
+trait Transmute {
+    fn to<T:Copy>(&self) -> Option<T>;
+}
+
+impl Transmute for [u8] {
+    fn to<T: Copy>(&self) -> Option<T> {
+        let tlen: usize = mem::size_of::<T>();
+        if self.len() == tlen
+        {
+            let val = self.as_ptr()  as *const T;
+            return Some(unsafe{ptr::read_unaligned(val)});
+        }
+        None
+    }
+}
+
+/// Dumps the header fields and all tags by IFD, in file order
+impl fmt::Debug for RawImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("RawImage");
+        d.field("file_name",&self.file_name).field("byte_order",&self.byte_order)
+         .field("raw_offset",&self.raw_offset).field("raw_format",&self.raw_format);
+        for (name,_,ifd) in self.ifds() {
+            let mut tags: Vec<(&String,&Vec<TagData>)> = ifd.tags.iter().collect();
+            tags.sort_by_key(|t| ifd.pos.get(t.0).map(|p| p.entry));
+            d.field(&name,&tags);
+        }
+        d.field("warnings",&self.warnings).finish()
+    }
+}
+
+/// One line summary: file name, format, camera, dimensions and capture
+/// time, unknown parts are left out
+impl fmt::Display for RawImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,"{}: CR2",self.file_name));
+        if let Some(m) = self.model() {
+            try!(write!(f,", {}",m));
+        }
+        if let (Some(w),Some(h)) = (self.width(),self.height()) {
+            try!(write!(f,", {}x{}",w,h));
+        }
+        if let Some(t) = self.capture_time() {
+            try!(write!(f,", {}",datetime::format_exif(t)));
+        }
+        Ok(())
+    }
+}
+
+/// Location of compressed raw data, for decoders outside the crate
+#[derive(Debug,Clone,PartialEq)]
+pub struct RawRanges {
+    /// Code of the compression tag (0x103) as written, e.g. 6 for the
+    /// lossless JPEG of CR2
+    pub compression: u32,
+    /// Offset and length of each strip or tile, as stored in the file
+    pub layout: Layout,
+    /// CR2 slicing of the lossless JPEG as (slices, slice width, last
+    /// slice width), the decoded rows are split into vertical slices
+    pub slices: Option<(u32,u32,u32)>
+}
+
+/// A capture held by a file, see RawImage::frames
+#[derive(Clone,Copy)]
+pub struct Frame<'a> {
+    image: &'a RawImage,
+    index: usize,
+    /// Index of the IFD of the frame in the chain
+    ifd: usize
+}
+
+impl<'a> Frame<'a> {
+    /// Position of the frame in the file, 0 for the first capture
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    fn ifd(&self) -> &'a Ifd {
+        &self.image.ifd[self.ifd]
+    }
+
+    fn u32_tag(&self, name: &str) -> Option<u32> {
+        self.ifd().tags.get(name).and_then(|v| v.first()).and_then(|d| d.as_u32())
+    }
+
+    fn primary(&self) -> bool {
+        self.ifd().offset == self.image.raw_offset
+    }
+
+    /// Width in pixels, from the IFD of the frame or for the raw data of
+    /// a CR2 as RawImage::width
+    pub fn width(&self) -> Option<u32> {
+        if self.primary() { self.image.width() } else { self.u32_tag("width") }
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        if self.primary() { self.image.height() } else { self.u32_tag("height") }
+    }
+
+    pub fn bits_per_sample(&self) -> Option<u16> {
+        if self.primary() { self.image.bits_per_sample() } else { self.u32_tag("bits_per_sample").map(|b| b as u16) }
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.u32_tag("compression").map(Compression::from)
+    }
+
+    pub fn layout(&self) -> Option<Layout> {
+        layout(self.ifd())
+    }
+
+    /// Byte ranges and compression of the data, nothing is read. None
+    /// without strips or tiles or compression tag.
+    pub fn ranges(&self) -> Option<RawRanges> {
+        let slices = self.ifd().tags.get("strip_cr2_slice").and_then(|v| match v.iter().map(|d| d.as_u32()).collect::<Option<Vec<u32>>>() {
+            Some(ref s) if s.len() == 3 => Some((s[0],s[1],s[2])),
+            _ => None
+        });
+        match (self.u32_tag("compression"),self.layout()) {
+            (Some(c),Some(l)) => Some(RawRanges{compression: c, layout: l, slices: slices}),
+            _ => None
+        }
+    }
+
+    /// The compressed strips or tiles of the frame in order, the input of
+    /// a decoder
+    ///
+    /// JPEG XL compressed frames are rejected, no decoder reads them yet.
+    pub fn read_data(&self) -> Result<Vec<u8>,RawFileError> {
+        if self.compression() == Some(Compression::JpegXl) {
+            return Err(RawFileError::NotImplemented("JPEG XL compressed data".to_string()));
+        }
+        let segments = match self.layout() {
+            Some(Layout::Strips(s)) => s,
+            Some(Layout::Tiles{tiles,..}) => tiles,
+            None => return Err(RawFileError::format(FormatErrorKind::Missing, format!("Frame {} without strips or tiles",self.index)))
+        };
+        let mut f = try!(self.image.reader());
+        let mut data = Vec::new();
+        for (offset,len) in segments {
+            try!(f.seek(io::SeekFrom::Start(offset)));
+            let start = data.len();
+            try!((&mut f).take(len).read_to_end(&mut data));
+            if ((data.len() - start) as u64) < len {
+                return Err(RawFileError::format(FormatErrorKind::Truncated, "Frame data beyond end of file").context(Some(offset),None,None));
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Parsed metadata of an image, detached from its source
+///
+/// It holds no file handle or in-memory file, so it can be cached, sent
+/// between threads and serialized. The accessors of RawImage are
+/// available through Deref; reading previews or raw data opens the file
+/// by name.
+#[derive(Debug,Clone)]
+pub struct RawMetadata(RawImage);
+
+impl RawMetadata {
+    /// Serializes the metadata, see sidecar
+    pub fn to_bytes(&self) -> Vec<u8> {
+        sidecar::encode(&self.0)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<RawMetadata,RawFileError> {
+        sidecar::decode(data).map(RawMetadata)
+    }
+
+    pub fn into_image(self) -> RawImage {
+        self.0
+    }
+}
+
+impl ::std::ops::Deref for RawMetadata {
+    type Target = RawImage;
+
+    fn deref(&self) -> &RawImage {
+        &self.0
+    }
+}
+
+impl<'a> RawImage {
+    /// Path the image was opened from, or the name given for images not
+    /// opened from a file
+    pub fn file_name(&self) -> &Path {
+        Path::new(&self.file_name)
+    }
+
+    /// Offset of the raw data from the CR2 header
+    pub fn raw_offset(&self) -> u64 {
+        self.raw_offset as u64
+    }
+
+    /// Parses the file again, e.g. after it changed on disk
+    ///
+    /// Images opened from memory or another source are parsed from it
+    /// again. On errors the image is left as it was.
+    pub fn reload(&mut self) -> Result<(),RawFileError> {
+        let mut image: RawImage = Default::default();
+        image.file_name = self.file_name.clone();
+        image.source = self.source.clone();
+        image.trace = self.trace.as_ref().map(|_| Trace::default());
+        let mut f = try!(image.reader());
+        try!(image.parse(&mut f));
+        *self = image;
+        Ok(())
+    }
+
+    /// Owned snapshot of the metadata without the source
+    pub fn metadata(&self) -> RawMetadata {
+        let mut image = self.clone();
+        image.source = None;
+        image.trace = None;
+        RawMetadata(image)
+    }
+
+    /// Renames the image, e.g. after the file was moved
+    pub(crate) fn set_file_name(&mut self, name: String) {
+        self.file_name = name;
+    }
+
+    /// White level of the sensor
+    pub fn white_level(&self) -> Option<u32> {
+        self.camera().map(|c| c.white_level)
+    }
+
+    /// Black level of the sensor
+    pub fn black_level(&self) -> Option<u32> {
+        self.camera().map(|c| c.black_level)
+    }
+
+    /// Area of the sensor that is exposed to light
+    pub fn active_area(&self) -> Option<Area> {
+        self.camera().and_then(|c| c.active_area)
+    }
+
+    /// Area of the sensor in the camera JPEG
+    ///
+    /// Includes crops set on the camera, e.g. a 16:9 aspect ratio. Use
+    /// active_area to get the full sensor area instead.
+    pub fn default_crop(&self) -> Option<Area> {
+        match (self.aspect_info(),self.active_area()) {
+            // the crop is read from the file, None if it overflows
+            (Some(a),Some(active)) => active.top.checked_add(a.top).and_then(|top| active.left.checked_add(a.left)
+                .and_then(|left| match (top.checked_add(a.height),left.checked_add(a.width)) {
+                    (Some(bottom),Some(right)) => Some(Area{top: top, left: left, bottom: bottom, right: right}),
+                    _ => None
+                })),
+            _ => self.camera().and_then(|c| c.default_crop)
+        }
+    }
+
+    /// Aspect ratio or crop set on the camera, None if the full sensor
+    /// is used
+    pub fn aspect_info(&self) -> Option<AspectInfo> {
+        self.makernote_tag("aspect_info").and_then(|v| AspectInfo::parse(&v.iter().filter_map(|d| d.as_u32()).collect::<Vec<_>>()))
+    }
+
+    /// Colors of the top left 2x2 pixels, 0 = red, 1 = green, 2 = blue
+    pub fn cfa_pattern(&self) -> Option<[u8; 4]> {
+        self.camera().and_then(|c| c.cfa_pattern)
+    }
+
+    /// XYZ to camera color matrix for D65
+    pub fn color_matrix(&self) -> Option<[f64; 9]> {
+        self.camera().and_then(|c| c.color_matrix)
+    }
+
+    /// Sensor temperature in degrees Celsius, only written by newer EOS models
+    pub fn camera_temperature(&self) -> Option<i32> {
+        match self.makernote_tag("shot_info").and_then(|v| v.get(12)) {
+            Some(&TagData::Unsigned(t)) if t != 0 => Some(t as i32 - 128),
+            _ => None
+        }
+    }
+
+    /// Firmware version of the camera, e.g. "1.1.3"
+    pub fn firmware_version(&self) -> Option<&str> {
+        match self.makernote_tag("firmware_version").and_then(|v| v.first()) {
+            Some(&TagData::Strg(ref s)) => {
+                Some(s.trim_start_matches("Firmware").trim_start_matches(" Version").trim())
+            },
+            _ => None
+        }
+    }
+
+    /// AF area mode and AF points of the shot
+    pub fn af_info(&self) -> Option<AfInfo> {
+        self.makernote_u16("af_info").and_then(|v| AfInfo::parse(&v))
+    }
+
+    /// Camera attitude from the electronic level, only written by newer
+    /// models
+    pub fn level(&self) -> Option<Level> {
+        self.makernote_tag("level_info").and_then(|v| Level::parse(&v.iter().filter_map(|d| match *d {
+            TagData::Signed(s) => Some(s),
+            TagData::Unsigned(u) => Some(u as i32),
+            _ => None
+        }).collect::<Vec<_>>()))
+    }
+
+    /// Focus distance range (lower, upper) in meters
+    pub fn focus_distance(&self) -> Option<(f64,f64)> {
+        self.makernote_u16("file_info").and_then(|v| canon::focus_distance(&v))
+    }
+
+    /// Capture settings from the EXIF IFD and the MakerNote
+    pub fn exif_summary(&self) -> ExifSummary {
+        let value = |name: &str| self.exif_tag(name).and_then(|v| v.first());
+        let mut flash: Option<Flash> = value("flash").and_then(|d| d.as_u32()).map(From::from);
+        let shot_info = self.makernote_u16("shot_info").unwrap_or_default();
+        if let (Some(f), Some(&c)) = (flash.as_mut(), shot_info.get(15)) {
+            f.compensation = Some(exif::canon_ev(c as i16));
+        }
+        let settings = self.makernote_u16("camera_settings").unwrap_or_default();
+        ExifSummary {
+            exposure_time: value("exposure_time").and_then(|d| d.as_f64()),
+            f_number: value("f_number").and_then(|d| d.as_f64()),
+            iso: value("iso").and_then(|d| d.as_u32()),
+            focal_length: value("focal_length").and_then(|d| d.as_f64()),
+            exposure_compensation: value("exposure_bias").and_then(|d| d.as_f64()),
+            date_time_original: match value("date_time_original") {
+                Some(&TagData::Strg(ref s)) => Some(s.clone()),
+                _ => None
+            },
+            exposure_program: value("exposure_program").and_then(|d| d.as_u32()).map(From::from),
+            exposure_mode: value("exposure_mode").and_then(|d| d.as_u32()).map(From::from),
+            metering_mode: value("metering_mode").and_then(|d| d.as_u32()).map(From::from),
+            flash: flash,
+            drive_mode: if settings.len() > 5 {
+                Some(DriveMode::from_canon(settings[5],settings[2]))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Name of the photographer
+    pub fn artist(&self) -> Option<&str> {
+        self.string_tag("artist")
+    }
+
+    /// Copyright notice
+    pub fn copyright(&self) -> Option<&str> {
+        self.string_tag("copyright")
+    }
+
+    /// Writes the artist tag to the file
+    pub fn set_artist(&mut self, artist: &str) -> Result<(),RawFileError> {
+        self.write_string_tag(0x13b,artist)
+    }
+
+    /// Writes the copyright tag to the file
+    pub fn set_copyright(&mut self, copyright: &str) -> Result<(),RawFileError> {
+        self.write_string_tag(0x8298,copyright)
+    }
+
+    /// Writes a string tag of IFD0 back to the file
+    ///
+    /// The file is changed with an Editor, so it is replaced as a whole
+    /// and stays intact if writing fails. The image is read again from
+    /// the new file.
+    fn write_string_tag(&mut self, id: u16, value: &str) -> Result<(),RawFileError> {
+        if self.source.is_some() {
+            return Err(RawFileError::NotImplemented("Writing images not opened from a file".to_string()));
+        }
+        try!(Editor::new(&self.file_name).set(Location::Ifd0,id,Value::Ascii(value.to_string())).save());
+        *self = try!(open(self.file_name.clone()));
+        Ok(())
+    }
+
+    /// Returns a copy of the image without the metadata the policy removes
+    pub fn stripped_metadata(&self, policy: &PrivacyPolicy) -> RawImage {
+        let mut image = self.clone();
+        for (kind,name) in policy.tags() {
+            if let Some(ifd) = image.sub_ifd_mut(kind) {
+                ifd.tags.remove(name);
+                ifd.pos.remove(name);
+            }
+        }
+        if policy.gps {
+            image.gps = None;
+            image.ifd[0].tags.remove("gps_ifd");
+            image.ifd[0].pos.remove("gps_ifd");
+        }
+        image
+    }
+
+    /// Writes a copy of the file without the metadata the policy removes
+    ///
+    /// The removed values are overwritten with zeros, the GPS IFD is
+    /// emptied. The structure of the file is kept, so all offsets stay valid.
+    /// The path may be the file of the image, it is replaced once the copy
+    /// is written.
+    pub fn save_stripped(&self, path: &str, policy: &PrivacyPolicy) -> Result<(),RawFileError> {
+        // write a copy and rename it, so the source is not truncated before
+        // it is read
+        let tmp = format!("{}.tmp",path);
+        let res = self.write_stripped(&tmp,policy).and_then(|_| fs::rename(&tmp,path).map_err(RawFileError::from));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+
+    fn write_stripped(&self, path: &str, policy: &PrivacyPolicy) -> Result<(),RawFileError> {
+        let mut src = try!(self.reader());
+        let mut f = try!(File::create(path));
+        try!(io::copy(&mut src,&mut f));
+        let mut clear: Vec<TagPos> = Vec::new();
+        for (kind,name) in policy.tags() {
+            if let Some(p) = self.sub_ifd(kind).and_then(|i| i.pos.get(name)) {
+                clear.push(*p);
+            }
+        }
+        if let (true, Some(gps)) = (policy.gps, self.gps.as_ref()) {
+            clear.extend(gps.pos.values().cloned());
+            // zero the entry count, the entries and the next IFD pointer,
+            // which then follows the count
+            let n = try!(self.entry_count(gps));
+            try!(f.seek(io::SeekFrom::Start(gps.offset as u64)));
+            try!(f.write_all(&vec![0u8; 2 + n as usize * 12 + 4]));
+        }
+        for p in clear {
+            let size = type_size(p.tagtype).unwrap_or(1) * p.count;
+            try!(f.seek(io::SeekFrom::Start(p.offset)));
+            try!(f.write_all(&vec![0u8; if size < 4 { 4 } else { size }]));
+        }
+        try!(f.flush());
+        Ok(())
+    }
+
+    /// Large JPEG preview stored in IFD0
+    pub fn preview(&self) -> Result<Vec<u8>,RawFileError> {
+        self.read_jpeg(0,"strip_offset","strip_byte_count")
+    }
+
+    /// Small JPEG thumbnail stored in IFD1
+    pub fn thumbnail(&self) -> Result<Vec<u8>,RawFileError> {
+        self.read_jpeg(1,"thumbnail_offset","thumbnail_length")
+    }
+
+    /// Offset and length of the preview in the file
+    pub fn preview_range(&self) -> Option<(u64,u64)> {
+        self.jpeg_range(0,"strip_offset","strip_byte_count")
+    }
+
+    /// Offset and length of the thumbnail in the file
+    pub fn thumbnail_range(&self) -> Option<(u64,u64)> {
+        self.jpeg_range(1,"thumbnail_offset","thumbnail_length")
+    }
+
+    /// Preview carrying the orientation of the image, so that viewers
+    /// show it upright
+    pub fn upright_preview(&self) -> Result<Vec<u8>,RawFileError> {
+        let jpeg = try!(self.preview());
+        jpeg::set_orientation(&jpeg,self.orientation().unwrap_or(1))
+    }
+
+    /// Thumbnail carrying the orientation of the image, see upright_preview
+    pub fn upright_thumbnail(&self) -> Result<Vec<u8>,RawFileError> {
+        let jpeg = try!(self.thumbnail());
+        jpeg::set_orientation(&jpeg,self.orientation().unwrap_or(1))
+    }
+
+    /// EXIF orientation, 1 is upright, 6 and 8 are rotated by 90 degrees
+    pub fn orientation(&self) -> Option<u16> {
+        self.u32_tag("orientation").map(|o| o as u16)
+    }
+
+    /// Offset and length of a JPEG image referenced by an offset and a
+    /// length tag
+    fn jpeg_range(&self, index: usize, offset: &str, length: &str) -> Option<(u64,u64)> {
+        let value = |name| self.ifd.get(index).and_then(|i| i.tags.get(name))
+            .and_then(|v| v.first()).and_then(|d| d.as_u32());
+        match (value(offset),value(length)) {
+            (Some(o),Some(l)) => Some((o as u64,l as u64)),
+            _ => None
+        }
+    }
+
+    /// Reads a JPEG image referenced by an offset and a length tag
+    fn read_jpeg(&self, index: usize, offset: &str, length: &str) -> Result<Vec<u8>,RawFileError> {
+        let (offset,length) = match self.jpeg_range(index,offset,length) {
+            Some(r) => r,
+            None => return Err(RawFileError::format(FormatErrorKind::Missing, format!("No JPEG in IFD{}",index)))
+        };
+        let mut f = try!(self.reader());
+        if offset + length > try!(f.seek(io::SeekFrom::End(0))) {
+            return Err(RawFileError::format(FormatErrorKind::Truncated, format!("IFD{} JPEG beyond end of file",index)).context(Some(offset),None,None));
+        }
+        try!(f.seek(io::SeekFrom::Start(offset)));
+        let mut jpeg = vec![0u8; length as usize];
+        try!(f.read_exact(&mut jpeg));
+        if !jpeg.starts_with(&[0xff,0xd8]) {
+            return Err(RawFileError::format(FormatErrorKind::BadMagic, format!("IFD{} data is not a JPEG",index)));
+        }
+        Ok(jpeg)
+    }
+
+    /// Lens model from the EXIF IFD or the MakerNote
+    pub fn lens(&self) -> Option<&str> {
+        match self.exif_tag("lens_model").or_else(|| self.makernote_tag("lens_model")).and_then(|v| v.first()) {
+            Some(&TagData::Strg(ref s)) if !s.is_empty() => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn make(&self) -> Option<&str> {
+        self.string_tag("make")
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.string_tag("model")
+    }
+
+    /// Width of the raw data in pixels, including the masked borders
+    ///
+    /// Taken from the header of the raw data, or else from the camera
+    /// database. IFD0 holds the size of the JPEG preview, it is used only
+    /// for unknown cameras.
+    pub fn width(&self) -> Option<u32> {
+        self.raw_format.map(|r| r.width)
+            .or_else(|| self.camera().and_then(|c| c.sensor_size).map(|s| s.0))
+            .or_else(|| self.u32_tag("width"))
+    }
+
+    /// Height of the raw data in pixels, see width
+    pub fn height(&self) -> Option<u32> {
+        self.raw_format.map(|r| r.height)
+            .or_else(|| self.camera().and_then(|c| c.sensor_size).map(|s| s.1))
+            .or_else(|| self.u32_tag("height"))
+    }
+
+    /// Bits per sample of the raw data
+    pub fn bits_per_sample(&self) -> Option<u16> {
+        self.raw_format.map(|r| r.bits)
+            .or_else(|| self.raw_ifd().and_then(|i| i.tags.get("bits_per_sample")).and_then(|v| v.first())
+                     .and_then(|d| d.as_u32()).map(|b| b as u16))
+    }
+
+    /// Compression of the raw data
+    pub fn compression(&self) -> Option<Compression> {
+        self.raw_ifd().and_then(|i| i.tags.get("compression")).and_then(|v| v.first())
+            .and_then(|d| d.as_u32()).map(Compression::from)
+    }
+
+    fn u32_tag(&self, name: &str) -> Option<u32> {
+        self.tag(name).and_then(|v| v.first()).and_then(|d| d.as_u32())
+    }
+
+    fn raw_ifd(&self) -> Option<&Ifd> {
+        self.ifd.iter().find(|i| i.offset == self.raw_offset)
+    }
+
+    /// Strips or tiles of the raw data, tiles are used by DNG and some
+    /// medium format files
+    pub fn raw_layout(&self) -> Option<Layout> {
+        self.raw_ifd().and_then(layout)
+    }
+
+    /// The captures held by the file, each with its own dimensions and
+    /// data. A CR2 holds one; previews and thumbnails are no frames.
+    pub fn frames(&self) -> Vec<Frame<'_>> {
+        self.ifd.iter().position(|i| i.offset == self.raw_offset)
+            .map(|i| Frame{image: self, index: 0, ifd: i}).into_iter().collect()
+    }
+
+    /// Reads len bytes at offset, fewer at the end of the file
+    pub fn read_range(&self, offset: u64, len: u64) -> Result<Vec<u8>,RawFileError> {
+        let mut f = try!(self.reader());
+        try!(f.seek(io::SeekFrom::Start(offset)));
+        let mut data = Vec::new();
+        try!(f.take(len).read_to_end(&mut data));
+        Ok(data)
+    }
+
+    /// Byte ranges and compression of the raw data of the first frame,
+    /// see Frame::ranges
+    pub fn raw_ranges(&self) -> Option<RawRanges> {
+        self.frames().first().and_then(|f| f.ranges())
+    }
+
+    /// Offsets and sizes of the strips or tiles of the raw IFD
+    pub(crate) fn raw_segments(&self) -> Vec<(u64,u64)> {
+        match self.raw_layout() {
+            Some(Layout::Strips(s)) => s,
+            Some(Layout::Tiles{tiles,..}) => tiles,
+            None => Vec::new()
+        }
+    }
+
+    /// SHA-256 of the raw sensor data
+    ///
+    /// Only the compressed raw strips or tiles are hashed, editing the metadata
+    /// does not change the hash.
+    pub fn content_hash(&self) -> Result<[u8; 32],RawFileError> {
+        let mut h = Sha256::default();
+        try!(self.hash_raw(|b| h.update(b)));
+        Ok(h.finish())
+    }
+
+    /// Feeds the raw strips or tiles to a hash
+    fn hash_raw<F: FnMut(&[u8])>(&self, update: F) -> Result<(),RawFileError> {
+        let strips = self.raw_segments();
+        if strips.is_empty() {
+            return Err(RawFileError::format(FormatErrorKind::Missing, "No raw data strips or tiles"));
+        }
+        self.hash_ranges(&strips,update)
+    }
+
+    /// Feeds ranges of the file to a hash, they have to lie within it
+    fn hash_ranges<F: FnMut(&[u8])>(&self, ranges: &[(u64,u64)], mut update: F) -> Result<(),RawFileError> {
+        let mut f = try!(self.reader());
+        let mut buf = vec![0u8; 1 << 16];
+        for &(offset,len) in ranges {
+            try!(f.seek(io::SeekFrom::Start(offset)));
+            let mut left = len as usize;
+            while left > 0 {
+                let n = if left < buf.len() { left } else { buf.len() };
+                try!(f.read_exact(&mut buf[..n]));
+                update(&buf[..n]);
+                left -= n;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checksum of the raw sensor data only, to detect bit rot in the
+    /// image data of archived files. With Sha256 it is the content hash.
+    pub fn raw_checksum(&self, algorithm: Algorithm) -> Result<Vec<u8>,RawFileError> {
+        Ok(try!(self.raw_checksums(&[algorithm])).remove(0))
+    }
+
+    /// Checksums of the raw sensor data with several algorithms, the
+    /// data is read once
+    pub fn raw_checksums(&self, algorithms: &[Algorithm]) -> Result<Vec<Vec<u8>>,RawFileError> {
+        let mut hashers: Vec<Hasher> = algorithms.iter().map(|&a| Hasher::new(a)).collect();
+        try!(self.hash_raw(|b| for h in hashers.iter_mut() { h.update(b) }));
+        Ok(hashers.into_iter().map(Hasher::finish).collect())
+    }
+
+    /// Checksum of the whole file
+    pub fn file_checksum(&self, algorithm: Algorithm) -> Result<Vec<u8>,RawFileError> {
+        Ok(try!(self.file_checksums(&[algorithm])).remove(0))
+    }
+
+    /// Checksums of the whole file with several algorithms, the file is
+    /// read once
+    pub fn file_checksums(&self, algorithms: &[Algorithm]) -> Result<Vec<Vec<u8>>,RawFileError> {
+        let mut hashers: Vec<Hasher> = algorithms.iter().map(|&a| Hasher::new(a)).collect();
+        let len = try!(self.len());
+        try!(self.hash_ranges(&[(0,len)],|b| for h in hashers.iter_mut() { h.update(b) }));
+        Ok(hashers.into_iter().map(Hasher::finish).collect())
+    }
+
+    /// All tags as (IFD name, tag name, formatted values)
+    pub(crate) fn tag_list(&self) -> Vec<(String,String,String)> {
+        let mut list = Vec::new();
+        for (name,_,ifd) in self.ifds() {
+            let mut tags: Vec<(&String,&Vec<TagData>)> = ifd.tags.iter().collect();
+            tags.sort_by_key(|t| ifd.pos.get(t.0).map(|p| p.id));
+            for (tag,values) in tags {
+                list.push((name.clone(),tag.clone(),format_values(values)));
+            }
         }
-    } 
-}
+        list
+    }
 
-    impl From<io::Error> for RawFileError {
-        fn from(e: io::Error) -> RawFileError {
-            RawFileError::Io(e)
+    /// Values of a tag by IFD and tag name as in tag lists, e.g. "exif"
+    /// and "iso"; unknown tags are named by their hex id, e.g. "0x9999"
+    pub fn tag_values(&self, ifd: &str, tag: &str) -> Option<&[TagData]> {
+        self.ifds().into_iter().find(|i| i.0 == ifd).and_then(|i| i.2.tags.get(tag)).map(|v| v.as_slice())
+    }
+
+    /// All IFDs with their names as in tag lists and their namespaces
+    pub(crate) fn ifds(&self) -> Vec<(String,IfdKind,&Ifd)> {
+        let mut ifds: Vec<(String,IfdKind,&Ifd)> = self.ifd.iter().enumerate()
+            .map(|(i,ifd)| (format!("ifd{}",i),IfdKind::Tiff,ifd)).collect();
+        for &(name,kind,ifd) in &[("exif",IfdKind::Exif,&self.exif),("gps",IfdKind::Gps,&self.gps),
+                                  ("makernote",IfdKind::Canon,&self.makernote)] {
+            if let Some(ref ifd) = *ifd {
+                ifds.push((name.to_string(),kind,ifd));
+            }
         }
+        ifds
     }
 
-    impl From<str::Utf8Error> for RawFileError {
-        fn from(e: str::Utf8Error) -> RawFileError {
-            RawFileError::Utf8(e)
+    /// Byte order and entry count of an IFD as the parser takes them
+    fn ifd_header(&self, ifd: &Ifd) -> Result<(ByteOrder,u16),RawFileError> {
+        match *try!(self.read_range(ifd.offset as u64,2)) {
+            [a,b] => Ok(ifd_order(self.byte_order,[a,b])),
+            _ => Err(RawFileError::format(FormatErrorKind::Truncated, "IFD beyond end of file").context(Some(ifd.offset as u64),None,None))
         }
     }
 
-    #[derive(Debug)]
-    enum TagData {
-        Unsigned(u32),
-        Signed(i32),
-        U64(u64),
-        I64(i64),
-        Strg(String),
-        Float(f64)
+    /// Number of entries of an IFD as stored in the file. Ifd::pos leaves
+    /// out entries of unknown type and repeated ids, so it may hold fewer.
+    pub(crate) fn entry_count(&self, ifd: &Ifd) -> Result<u16,RawFileError> {
+        self.ifd_header(ifd).map(|h| h.1)
     }
 
-    struct Ifd {
-        offset: usize,
-        tags: HashMap<String, Vec<TagData>>
-            
-    
+    /// Reads an IFD again from the file, e.g. after its values changed
+    ///
+    /// The IFD is expected at the same offset. Returns false and keeps
+    /// the old IFD if it now points to other data, e.g. other sub IFDs,
+    /// strips or a next IFD, then the whole file has to be parsed again.
+    pub(crate) fn reread_ifd(&mut self, name: &str) -> Result<bool,RawFileError> {
+        let (kind,old) = match self.ifds().into_iter().find(|i| i.0 == name) {
+            Some((_,kind,ifd)) => (kind,ifd.clone()),
+            None => return Ok(false)
+        };
+        let warnings = self.warnings.len();
+        let mut f = try!(self.reader());
+        let (ifd,next) = try!(self.read_ifd_at(&mut f,old.offset,kind,name,true));
+        let index = if kind == IfdKind::Tiff { name[3..].parse::<usize>().ok() } else { None };
+        let same = |tag: &str| old.tags.get(tag) == ifd.tags.get(tag);
+        let structure = ["exif_ifd","gps_ifd","compression","strip_offset","strip_byte_count","tile_offset",
+                         "tile_byte_count","tile_width","tile_length","thumbnail_offset","thumbnail_length"];
+        let unchanged = structure.iter().all(|t| same(t))
+            && old.pos.get("maker_note").map(|p| p.offset) == ifd.pos.get("maker_note").map(|p| p.offset)
+            && index.map(|i| next == self.ifd.get(i+1).map(|n| n.offset).unwrap_or(0)).unwrap_or(true);
+        if !unchanged {
+            self.warnings.truncate(warnings);
+            return Ok(false);
+        }
+        // warnings of the old IFD are replaced by those just recorded
+        let new = self.warnings.split_off(warnings);
+        self.warnings.retain(|w| w.ifd != name);
+        self.warnings.extend(new);
+        match (kind,index) {
+            (IfdKind::Tiff,Some(i)) => self.ifd[i] = ifd,
+            (IfdKind::Exif,_) => self.exif = Some(ifd),
+            (IfdKind::Gps,_) => self.gps = Some(ifd),
+            (IfdKind::Canon,_) => self.makernote = Some(ifd),
+            _ => return Ok(false)
+        }
+        Ok(true)
     }
 
-    #[derive(Default)]
-    pub struct RawImage {
-        pub file_name:  Box<String>,
-        byte_order: ByteOrder,
-        pub raw_offset: usize,
-        ifd: Vec<Ifd>,
-        tags: HashMap<String,Vec<TagData> >
+    /// Byte order, raw offset and lossless JPEG format (width, height,
+    /// bits) of the image, the state besides the IFDs saved by sidecar
+    pub(crate) fn layout_state(&self) -> (bool,usize,Option<(u32,u32,u16)>) {
+        (self.byte_order == ByteOrder::Motorola, self.raw_offset,
+         self.raw_format.map(|r| (r.width,r.height,r.bits)))
     }
 
+    /// Rebuilds an image from the state saved by sidecar, data beyond the
+    /// metadata is read from the file by name
+    pub(crate) fn from_state(file_name: String, big_endian: bool, raw_offset: usize,
+                             raw_format: Option<(u32,u32,u16)>, ifds: Vec<(IfdKind,Ifd)>) -> RawImage {
+        let mut image: RawImage = Default::default();
+        image.file_name = file_name;
+        image.byte_order = if big_endian { ByteOrder::Motorola } else { ByteOrder::Intel };
+        image.raw_offset = raw_offset;
+        image.raw_format = raw_format.map(|(w,h,b)| RawFormat { width: w, height: h, bits: b });
+        for (kind,ifd) in ifds {
+            match kind {
+                IfdKind::Tiff => image.ifd.push(ifd),
+                IfdKind::Exif => image.exif = Some(ifd),
+                IfdKind::Gps => image.gps = Some(ifd),
+                IfdKind::Canon => image.makernote = Some(ifd)
+            }
+        }
+        image
+    }
 
-pub fn open(path: String) -> Result<RawImage,RawFileError>{
+    /// Length of the file
+    pub(crate) fn len(&self) -> Result<u64,RawFileError> {
+        let mut f = try!(self.reader());
+        Ok(try!(f.seek(io::SeekFrom::End(0))))
+    }
 
-    let mut file = try!(File::open(&path));
-    let mut image: RawImage = Default::default();
-    image.file_name = Box::new(String::from(path));
-    try!(image.read_header(&mut file));
-    let mut i=0;
-    while image.ifd.len() > i {
-        try!(image.read_ifd(&mut file,i,true));
-        i += 1;
+    fn sub_ifd(&self, kind: IfdKind) -> Option<&Ifd> {
+        match kind {
+            IfdKind::Tiff => self.ifd.first(),
+            IfdKind::Exif => self.exif.as_ref(),
+            IfdKind::Gps => self.gps.as_ref(),
+            IfdKind::Canon => self.makernote.as_ref()
+        }
     }
-    Ok(image)
-}
 
-trait Transmute {
-    fn to<T:Copy>(&self) -> Option<T>;
-}
+    fn sub_ifd_mut(&mut self, kind: IfdKind) -> Option<&mut Ifd> {
+        match kind {
+            IfdKind::Tiff => self.ifd.first_mut(),
+            IfdKind::Exif => self.exif.as_mut(),
+            IfdKind::Gps => self.gps.as_mut(),
+            IfdKind::Canon => self.makernote.as_mut()
+        }
+    }
 
-impl Transmute for [u8] {
-    fn to<T: Copy>(&self) -> Option<T> {
-        let tlen: usize = mem::size_of::<T>();
-        if self.len() == tlen
-        {
-            let val = self.as_ptr()  as *const T;;
-            return Some(unsafe{(*val)});
+    /// Capture time as set on the camera clock, time zone unknown
+    pub fn capture_time(&self) -> Option<i64> {
+        self.exif_tag("date_time_original").or_else(|| self.tag("date_time"))
+            .and_then(|v| match v.first() {
+                Some(&TagData::Strg(ref s)) => datetime::parse_exif(s),
+                _ => None
+            })
+    }
+
+    /// Looks up the camera model in the camera database
+    fn camera(&self) -> Option<Camera> {
+        self.model().and_then(cameras::lookup)
+    }
+
+    /// Returns the values of the first tag with this name in the IFD chain
+    fn tag(&self, name: &str) -> Option<&Vec<TagData>> {
+        self.ifd.iter().filter_map(|i| i.tags.get(name)).next()
+    }
+
+    fn exif_tag(&self, name: &str) -> Option<&Vec<TagData>> {
+        self.exif.as_ref().and_then(|i| i.tags.get(name))
+    }
+
+    fn makernote_tag(&self, name: &str) -> Option<&Vec<TagData>> {
+        self.makernote.as_ref().and_then(|i| i.tags.get(name))
+    }
+
+    /// Values of a MakerNote record of 16 bit integers
+    fn makernote_u16(&self, name: &str) -> Option<Vec<u16>> {
+        self.makernote_tag(name).map(|v| v.iter().filter_map(|d| match *d {
+            TagData::Unsigned(u) => Some(u as u16),
+            _ => None
+        }).collect())
+    }
+
+    pub(crate) fn string_tag(&self, name: &str) -> Option<&str> {
+        match self.tag(name).and_then(|v| v.first()) {
+            Some(&TagData::Strg(ref s)) => Some(s),
+            _ => None
         }
-        None
     }
-}
 
+    /// Trace of the parse if the image was opened with open_traced
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
 
-impl<'a> RawImage {
-    fn read_header(&mut self,f: &mut File) -> Result<(),RawFileError> {
+    fn record(&mut self, event: Event) {
+        if let Some(ref mut t) = self.trace {
+            t.events.push(event);
+        }
+    }
+
+    /// Non-fatal anomalies found while parsing, e.g. values truncated by
+    /// the end of the file, skipped tags or unknown compression
+    ///
+    /// Images loaded from a sidecar have none. See validate for a full
+    /// check against the specification.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Keeps a warning, it is also recorded as anomaly in the trace
+    fn warn(&mut self, ifd: &str, tag: Option<u16>, offset: u64, msg: String) {
+        self.record(Event::Anomaly{offset: offset, msg: msg.clone()});
+        self.warnings.push(Warning{ifd: ifd.to_string(), tag: tag, offset: offset, msg: msg});
+    }
+
+    /// Opens the source of the image for reading
+    fn reader(&self) -> Result<SourceReader,RawFileError> {
+        match self.source {
+            Some(ref s) => Ok(SourceReader::new(s.clone())),
+            None => Ok(SourceReader::new(Arc::new(try!(FileSource::open(&self.file_name)))))
+        }
+    }
+
+    /// Reads the metadata and the header of the raw data
+    fn parse<R: Read + Seek>(&mut self, f: &mut R) -> Result<(),RawFileError> {
+        try!(self.parse_metadata(f));
+        self.raw_format = try!(self.read_raw_format(f));
+        Ok(())
+    }
+
+    /// Reads the header, the IFD chain and the sub IFDs
+    fn parse_metadata<R: Read + Seek>(&mut self, f: &mut R) -> Result<(),RawFileError> {
+        try!(self.read_header(f));
+        let mut i=0;
+        while self.ifd.len() > i {
+            try!(self.read_ifd(f,i,true));
+            i += 1;
+        }
+        try!(self.read_sub_ifds(f));
+        let msg = match self.compression() {
+            Some(Compression::Other(c)) => Some(format!("unknown compression {}",c)),
+            Some(Compression::JpegXl) => Some("JPEG XL compressed data is not supported".to_string()),
+            _ => None
+        };
+        if let (Some(msg),Some(ifd)) = (msg,self.raw_ifd()) {
+            let (name,offset) = (format!("ifd{}",self.ifd.iter().position(|i| i.offset == ifd.offset).unwrap_or(0)),
+                                 ifd.pos.get("compression").map(|p| p.entry).unwrap_or(0));
+            self.warn(&name,Some(0x103),offset,msg);
+        }
+        Ok(())
+    }
+
+    /// Reads the frame header of the lossless JPEG raw data
+    ///
+    /// The width in the header counts pixels per component, the raw
+    /// data is as wide as the product of both.
+    fn read_raw_format<R: Read + Seek>(&self, f: &mut R) -> Result<Option<RawFormat>,RawFileError> {
+        // tiles carry a header each, with the size of the tile
+        let offset = match self.raw_layout() {
+            Some(Layout::Strips(ref s)) if !s.is_empty() => s[0].0,
+            _ => return Ok(None)
+        };
+        try!(f.seek(io::SeekFrom::Start(offset)));
+        let mut head = Vec::new();
+        try!(f.take(1024).read_to_end(&mut head));
+        if !head.starts_with(&[0xff,0xd8]) {
+            return Ok(None);
+        }
+        // walk the marker segments up to the lossless frame header
+        let mut i = 2;
+        while i + 4 <= head.len() && head[i] == 0xff {
+            let len = u16::from_be_bytes([head[i+2],head[i+3]]) as usize;
+            if head[i+1] == 0xc3 {
+                return Ok(head.get(i+4..i+10).map(|h| RawFormat {
+                    bits: h[0] as u16,
+                    height: u16::from_be_bytes([h[1],h[2]]) as u32,
+                    width: u16::from_be_bytes([h[3],h[4]]) as u32 * h[5] as u32
+                }));
+            }
+            i += 2 + len;
+        }
+        Ok(None)
+    }
+
+    fn read_header<R: Read + Seek>(&mut self,f: &mut R) -> Result<(),RawFileError> {
         if 0 != try!(f.seek(::std::io::SeekFrom::Start(0))) { 
             return Err(RawFileError::Seek(0)) 
         } ;
@@ -129,23 +1536,20 @@ impl<'a> RawImage {
         match s {
             "II" => self.byte_order = ByteOrder::Intel,
             "MM" => self.byte_order = ByteOrder::Motorola,
-            _    => return Err(RawFileError::FileFormat("Unknown byte order ".to_string()+s)) 
+            _    => return Err(RawFileError::format(FormatErrorKind::BadMagic, "Unknown byte order ".to_string()+s).context(Some(0),None,None))
         }
-        if s != "II" { 
-            return Err(RawFileError::NotImplemented("Only Intel Byte Order supported!".to_string())) 
-        };
-    
-        if head[2..4].to::<u16>().unwrap() != 0x002a { 
-            return Err(RawFileError::FileFormat("Tiff Magic mismatch".to_string()))
+        let order = self.byte_order;
+        if order.read::<u16>(&head[2..4]).unwrap() != 0x002a { 
+            return Err(RawFileError::format(FormatErrorKind::BadMagic, "Tiff Magic mismatch").context(Some(2),None,None))
         };
         
         let mut to = [ 0u8; 4];        // Tiff Offset
         to.clone_from_slice(&head[4..8]);
-        self.ifd.push(Ifd{offset: head[4..8].to::<u32>().unwrap() as usize,tags: HashMap::new()});
+        self.ifd.push(Ifd::new(order.read::<u32>(&head[4..8]).unwrap() as usize));
     
         let cm = &head[8..10];         // CR2 Magic
         if try!(str::from_utf8(&cm)) != "CR" { 
-            return Err(RawFileError::FileFormat("CR2 Magic mismatch".to_string()));
+            return Err(RawFileError::format(FormatErrorKind::BadMagic, "CR2 Magic mismatch").context(Some(8),None,None));
         }
     
         let cmaj = &head[10..11];        // CR2 Major
@@ -155,48 +1559,54 @@ impl<'a> RawImage {
                         "CR2 Version {}.{} not supported",cmaj[0],cmin[0])));
         }
     
-        self.raw_offset = head[12..16].to::<u32>().unwrap() as usize;
+        self.raw_offset = order.read::<u32>(&head[12..16]).unwrap() as usize;
         Ok(())
     }
 
-    fn read_tag(&mut self, f: &mut File) -> Result<(),RawFileError>{
+    /// Reads the IFD entry at the position of f, name is the IFD name used
+    /// in warnings
+    fn read_tag<R: Read + Seek>(&mut self, f: &mut R, kind: IfdKind, order: ByteOrder, name: &str) -> Result<(String,Vec<TagData>,TagPos),RawFileError>{
+        let entry = try!(f.seek(io::SeekFrom::Current(0)));
         let mut tag = [0u8; 12];
         try!(f.read(&mut tag));
-        let tagid = tag[0..2].to::<u16>().unwrap();
-        let tagtype = tag[2..4].to::<u16>().unwrap();
-        let valcount = tag[4..8].to::<u32>().unwrap() as usize; 
+        let tagid = order.read::<u16>(&tag[0..2]).unwrap();
+        let tagtype = order.read::<u16>(&tag[2..4]).unwrap();
+        let valcount = order.read::<u32>(&tag[4..8]).unwrap() as usize; 
         let mut data: Vec<u8> = From::from(&tag[8..12]);
-        let tagname = match tagid {
-            0x100 => "width",
-            0x101 => "height",
-            0x102 => "bits_per_sample",
-            0x103 => "compression",
-            0x10f => "make",
-            0x110 => "model",
-            0x111 => "strip_offset",
-            0x112 => "orientation",
-            0x117 => "strip_byte_count",
-            0x11a => "x_resolution",
-            0x11b => "y_resolution",
-            0x128 => "res_unit",
-            0x132 => "date_time",
-            0xc640 => "strip_cr2_slice",
-            _ => "???"
-        };
-        let valsize: usize = match tagtype {
-            1|2|6|7 => 1,
-            3|8 => 2,
-            4|9|11 => 4,
-            5|10|12 => 8,
-            _ => 0
+        let mut pos = TagPos{id: tagid, tagtype: tagtype, count: valcount, entry: entry, offset: entry+8};
+        let valsize = match type_size(tagtype) {
+            Some(s) => s,
+            None => return Err(RawFileError::TypeError(tagtype))
         };
-        if valsize*valcount > 4
+        let size = valsize as u64 * valcount as u64;
+        if size > 4
         {   
-            let offset = tag[8..12].to::<u32>().unwrap();
-            let mut f = try!(File::open(self.file_name.deref()));
-            try!(f.seek(io::SeekFrom::Start(offset as u64)));
-            data = vec![0u8; (valsize * valcount) as usize];
-            try!(f.read(&mut data));
+            let offset = order.read::<u32>(&tag[8..12]).unwrap();
+            pos.offset = offset as u64;
+            let next = try!(f.seek(io::SeekFrom::Current(0)));
+            // the count is not trusted, only the rest of the file is read
+            let len = try!(f.seek(io::SeekFrom::End(0)));
+            let avail = len.saturating_sub(offset as u64) / valsize as u64 * valsize as u64;
+            let alloc = if size < avail { size } else { avail } as usize;
+            // every element becomes a TagData, only strings stay as bytes
+            let decoded = if tagtype == 2 { alloc } else { alloc / valsize * mem::size_of::<TagData>() };
+            self.value_bytes += alloc + decoded;
+            if self.value_bytes > MAX_VALUE_BYTES {
+                return Err(RawFileError::format(FormatErrorKind::TooLarge, format!("Tag values exceed {} bytes",MAX_VALUE_BYTES))
+                           .context(Some(offset as u64),None,Some(tagid)));
+            }
+            data = vec![0u8; alloc];
+            let read = f.seek(io::SeekFrom::Start(offset as u64)).and_then(|_| f.read(&mut data));
+            let n = try!(read.map_err(|e| RawFileError::from(e).context(Some(offset as u64),None,Some(tagid))));
+            if (n as u64) < size {
+                self.warn(name,Some(tagid),offset as u64,format!(
+                    "value of tag 0x{:04x} truncated, {} of {} bytes",tagid,n,size));
+            }
+            try!(f.seek(io::SeekFrom::Start(next)));
+        }
+        if let Some(ref h) = self.handlers {
+            let len = ::std::cmp::min(size,data.len() as u64) as usize;
+            h.call(&RawTag{kind: kind, id: tagid, tagtype: tagtype, count: valcount, offset: pos.offset, data: &data[..len]});
         }
         let mut d : Vec<TagData> = Vec::new();
         let mut s:  String = String::new(); 
@@ -209,44 +1619,253 @@ impl<'a> RawImage {
             match tagtype {
                 1|7 => d.push(TagData::Unsigned(w.to::<u8>().unwrap() as u32)),
                 2 => s.push(w.to::<u8>().unwrap() as char),
-                3 => d.push(TagData::Unsigned(w.to::<u16>().unwrap() as u32)),
-                4 => d.push(TagData::Unsigned(w.to::<u32>().unwrap())),
-                5 => d.push(TagData::U64(w.to::<u64>().unwrap())),
+                3 => d.push(TagData::Unsigned(order.read::<u16>(w).unwrap() as u32)),
+                4 => d.push(TagData::Unsigned(order.read::<u32>(w).unwrap())),
+                5 => d.push(TagData::U64(order.read_rational(w).unwrap())),
                 6 => d.push(TagData::Signed(w.to::<i8>().unwrap() as i32)),
-                8 => d.push(TagData::Signed(w.to::<i16>().unwrap() as i32)),
-                9 => d.push(TagData::Signed(w.to::<i32>().unwrap())),
-                10 => d.push(TagData::I64(w.to::<i64>().unwrap())),
-                11 => d.push(TagData::Float(w.to::<f32>().unwrap() as f64)),
-                12 => d.push(TagData::Float(w.to::<f64>().unwrap())),
+                8 => d.push(TagData::Signed(order.read::<i16>(w).unwrap() as i32)),
+                9 => d.push(TagData::Signed(order.read::<i32>(w).unwrap())),
+                10 => d.push(TagData::I64(order.read_rational(w).unwrap() as i64)),
+                11 => d.push(TagData::Float(order.read::<f32>(w).unwrap() as f64)),
+                12 => d.push(TagData::Float(order.read::<f64>(w).unwrap())),
                 _ => return Err(RawFileError::TypeError(tagtype))
             }    
         }
-        println!("name: {:20} id: {:0>4x}",tagname,tagid);
-        Ok(())
+        if tagtype == 2 {
+            let trimmed = s.trim_end_matches('\0');
+            if s.len() > trimmed.len() + 1 {
+                self.warn(name,Some(tagid),pos.offset,format!(
+                    "string of tag 0x{:04x} padded with {} NUL bytes",tagid,s.len() - trimmed.len()));
+            }
+            d.push(TagData::Strg(String::from(trimmed)));
+        }
+        Ok((tag_key(kind,tagid),d,pos))
+    }
+
+fn read_ifd<R: Read + Seek>(&mut self,f: &mut R, index: usize,read_tags:bool) -> Result<usize,RawFileError>{
+    let offset = self.ifd[index].offset;
+    let (ifd,io) = try!(self.read_ifd_at(f,offset,IfdKind::Tiff,&format!("ifd{}",index),read_tags));
+    self.ifd[index] = ifd;
+    if io != 0 {
+        if self.ifd.iter().any(|i| i.offset == io) {
+            self.warn(&format!("ifd{}",index),None,offset as u64,format!("IFD chain loops back to 0x{:x}",io));
+        } else if self.ifd.len() >= MAX_IFDS {
+            self.warn(&format!("ifd{}",index),None,offset as u64,format!("IFD chain longer than {}",MAX_IFDS));
+        } else {
+            self.ifd.push(Ifd::new(io))
+        }
     }
+    Ok(io)
+}
 
-fn read_ifd(&mut self,f: &mut File, index: usize,read_tags:bool) -> Result<usize,RawFileError>{
-    let mut pos = try!(f.seek(io::SeekFrom::Start(self.ifd[index].offset as u64)));
+/// Reads the IFD at offset, returns it with the offset of the next IFD
+/// The name is used in errors and traces
+fn read_ifd_at<R: Read + Seek>(&mut self,f: &mut R, offset: usize, kind: IfdKind, name: &str, read_tags:bool) -> Result<(Ifd,usize),RawFileError>{
+    self.read_ifd_entries(f,offset,kind,name,read_tags).map_err(|e| e.context(Some(offset as u64),Some(name),None))
+}
+
+fn read_ifd_entries<R: Read + Seek>(&mut self,f: &mut R, offset: usize, kind: IfdKind, name: &str, read_tags:bool) -> Result<(Ifd,usize),RawFileError>{
+    let mut ifd = Ifd::new(offset);
+    let mut pos = try!(f.seek(io::SeekFrom::Start(offset as u64)));
     let mut na=[0u8; 2];
     try!(f.read(&mut na));
-    let n = na.to::<u16>().unwrap();
+    let (order,n) = ifd_order(self.byte_order,na);
+    if order != self.byte_order {
+        self.warn(name,None,offset as u64,format!("{} in {:?} byte order",name,order));
+    }
+    let event = self.trace.as_ref().map(|t| t.events.len());
+    self.record(Event::Ifd{name: name.to_string(), offset: offset as u64, entries: n, next: 0});
     if read_tags {
-        for n in 0..n {
-            let r = self.read_tag(f);
+        for i in 0..n {
+            let entry = pos + 2 + i as u64 * 12;
+            match self.read_tag(f,kind,order,name).map_err(|e| e.context(Some(entry),None,None)) {
+                Ok((tag,data,p)) => {
+                    self.record(Event::Tag{ifd: name.to_string(), id: p.id, tagtype: p.tagtype, count: p.count,
+                        offset: p.offset, size: type_size(p.tagtype).unwrap_or(1) * p.count});
+                    ifd.tags.insert(tag.clone(),data);
+                    ifd.pos.insert(tag,p);
+                },
+                // tags of unknown type are skipped
+                Err(RawFileError::TypeError(t)) => self.warn(name,None,entry,
+                    format!("tag of unknown type {} skipped",t)),
+                Err(e) => return Err(e)
+            }
         }
     }
     pos=pos+n as u64 *12+2;
     let mut ioa = [0u8; 4];
     try!(f.seek(io::SeekFrom::Start(pos)));
     try!(f.read(&mut ioa));
-    let io = ioa.to::<u32>().unwrap() as usize;
-    if io != 0 {
-        self.ifd.push(Ifd{offset: io,tags: HashMap::new()})
+    let io = order.read::<u32>(&ioa).unwrap() as usize;
+    if let (Some(i),Some(t)) = (event,self.trace.as_mut()) {
+        if let Event::Ifd{ref mut next,..} = t.events[i] {
+            *next = io as u64;
+        }
     }
-    Ok(io)
+    Ok((ifd,io))
+}
+
+/// Reads the EXIF and GPS IFDs and the Canon MakerNote referenced from IFD0
+fn read_sub_ifds<R: Read + Seek>(&mut self,f: &mut R) -> Result<(),RawFileError>{
+    let pointer = |image: &RawImage, name: &str| match image.tag(name).and_then(|v| v.first()) {
+        Some(&TagData::Unsigned(o)) if o != 0 => Some(o as usize),
+        _ => None
+    };
+    if let Some(o) = pointer(self,"gps_ifd") {
+        let (ifd,_) = try!(self.read_ifd_at(f,o,IfdKind::Gps,"gps",true));
+        self.gps = Some(ifd);
+    }
+    let exif = match pointer(self,"exif_ifd") {
+        Some(o) => o,
+        None => return Ok(())
+    };
+    let (ifd,_) = try!(self.read_ifd_at(f,exif,IfdKind::Exif,"exif",true));
+    // Canon MakerNote offsets are relative to the start of the file
+    let makernote = ifd.pos.get("maker_note").map(|p| p.offset as usize);
+    self.exif = Some(ifd);
+    if let Some(o) = makernote {
+        let (ifd,_) = try!(self.read_ifd_at(f,o,IfdKind::Canon,"makernote",true));
+        self.makernote = Some(ifd);
+    }
+    Ok(())
+}
+
+}
+
+/// Byte order and entry count of an IFD from the bytes of the count
+///
+/// Some bodies write the MakerNote in the other byte order than the
+/// container, an implausible entry count is taken as a sign of that.
+fn ifd_order(order: ByteOrder, count: [u8; 2]) -> (ByteOrder,u16) {
+    let n = order.read::<u16>(&count).unwrap();
+    let swapped = order.swapped().read::<u16>(&count).unwrap();
+    if n > MAX_ENTRIES && swapped <= MAX_ENTRIES { (order.swapped(),swapped) } else { (order,n) }
+}
 
+/// Formats tag values for display, rationals are shown as fractions
+fn format_values(values: &[TagData]) -> String {
+    let v: Vec<String> = values.iter().map(|d| d.to_string()).collect();
+    v.join(" ")
 }
 
+/// Strips or tiles of the image data of an IFD
+fn layout(ifd: &Ifd) -> Option<Layout> {
+    let segments = |offsets: &str, counts: &str| match (ifd.tags.get(offsets),ifd.tags.get(counts)) {
+        (Some(o),Some(c)) => Some(o.iter().zip(c.iter())
+            .filter_map(|(o,c)| match (o.as_u32(),c.as_u32()) {
+                (Some(o),Some(c)) => Some((o as u64,c as u64)),
+                _ => None
+            }).collect()),
+        _ => None
+    };
+    let size = |name: &str| ifd.tags.get(name).and_then(|v| v.first()).and_then(|d| d.as_u32());
+    if let (Some(tiles),Some(width),Some(length)) = (segments("tile_offset","tile_byte_count"),size("tile_width"),size("tile_length")) {
+        return Some(Layout::Tiles{width: width, length: length, tiles: tiles});
+    }
+    segments("strip_offset","strip_byte_count").map(Layout::Strips)
+}
+
+/// Size of a single value of a TIFF type
+pub(crate) fn type_size(tagtype: u16) -> Option<usize> {
+    match tagtype {
+        1|2|6|7 => Some(1),
+        3|8 => Some(2),
+        4|9|11 => Some(4),
+        5|10|12 => Some(8),
+        _ => None
+    }
+}
+
+/// Key of a tag in Ifd::tags, the name or the hex id of unknown tags
+pub(crate) fn tag_key(kind: IfdKind, id: u16) -> String {
+    match tag_name(kind,id) {
+        "" => format!("0x{:04x}",id),
+        n  => n.to_string()
+    }
+}
+
+/// Name of a tag id within its IFD namespace, empty if unknown
+pub(crate) fn tag_name(kind: IfdKind, id: u16) -> &'static str {
+    match kind {
+        IfdKind::Tiff => match id {
+            0x100 => "width",
+            0x101 => "height",
+            0x102 => "bits_per_sample",
+            0x103 => "compression",
+            0x10f => "make",
+            0x110 => "model",
+            0x111 => "strip_offset",
+            0x112 => "orientation",
+            0x117 => "strip_byte_count",
+            0x11a => "x_resolution",
+            0x11b => "y_resolution",
+            0x128 => "res_unit",
+            0x132 => "date_time",
+            0x13b => "artist",
+            0x142 => "tile_width",
+            0x143 => "tile_length",
+            0x144 => "tile_offset",
+            0x145 => "tile_byte_count",
+            0x201 => "thumbnail_offset",
+            0x202 => "thumbnail_length",
+            0x8298 => "copyright",
+            0x8769 => "exif_ifd",
+            0x8825 => "gps_ifd",
+            0xc640 => "strip_cr2_slice",
+            _ => ""
+        },
+        IfdKind::Exif => match id {
+            0x829a => "exposure_time",
+            0x829d => "f_number",
+            0x8822 => "exposure_program",
+            0x8827 => "iso",
+            0x9003 => "date_time_original",
+            0x9004 => "date_time_digitized",
+            0x9204 => "exposure_bias",
+            0x9207 => "metering_mode",
+            0x9209 => "flash",
+            0x920a => "focal_length",
+            0x927c => "maker_note",
+            0xa002 => "pixel_x_dimension",
+            0xa003 => "pixel_y_dimension",
+            0xa430 => "camera_owner_name",
+            0xa431 => "body_serial_number",
+            0xa434 => "lens_model",
+            0xa435 => "lens_serial_number",
+            0xa402 => "exposure_mode",
+            _ => ""
+        },
+        IfdKind::Gps => match id {
+            0x0000 => "gps_version_id",
+            0x0001 => "latitude_ref",
+            0x0002 => "latitude",
+            0x0003 => "longitude_ref",
+            0x0004 => "longitude",
+            0x0005 => "altitude_ref",
+            0x0006 => "altitude",
+            0x0007 => "time_stamp",
+            0x001d => "date_stamp",
+            _ => ""
+        },
+        IfdKind::Canon => match id {
+            0x0001 => "camera_settings",
+            0x0002 => "focal_length",
+            0x0004 => "shot_info",
+            0x0006 => "image_type",
+            0x0007 => "firmware_version",
+            0x0009 => "owner_name",
+            0x000c => "serial_number",
+            0x000d => "camera_info",
+            0x0010 => "model_id",
+            0x0026 => "af_info",
+            0x0093 => "file_info",
+            0x0095 => "lens_model",
+            0x0096 => "internal_serial_number",
+            0x009a => "aspect_info",
+            0x4059 => "level_info",
+            _ => ""
+        }
+    }
 }
 
 #[test]
@@ -263,3 +1882,465 @@ fn test_u8_array_to_int() {
     assert_eq!(0x0202020202020202,a[0..8].to::<i64>().unwrap());
 }
 
+
+/// Entry points for fuzzing the parser stages with arbitrary bytes
+#[cfg(feature = "fuzzing")]
+pub mod fuzz {
+    use std::io::Cursor;
+    use super::{RawImage,Ifd,IfdKind,ByteOrder};
+
+    pub fn read_header(data: &[u8]) {
+        let mut image = RawImage::default();
+        let _ = image.read_header(&mut Cursor::new(data));
+    }
+
+    /// Reads the IFD chain starting at offset 0
+    pub fn read_ifd(data: &[u8]) {
+        let mut image = RawImage::default();
+        image.ifd.push(Ifd::new(0));
+        let mut f = Cursor::new(data);
+        let mut i = 0;
+        while i < image.ifd.len() && image.read_ifd(&mut f,i,true).is_ok() {
+            i += 1;
+        }
+    }
+
+    /// Reads an IFD entry at offset 0 in all namespaces
+    pub fn read_tag(data: &[u8]) {
+        for &kind in &[IfdKind::Tiff,IfdKind::Exif,IfdKind::Gps,IfdKind::Canon] {
+            for &order in &[ByteOrder::Intel,ByteOrder::Motorola] {
+                let mut image = RawImage::default();
+                let _ = image.read_tag(&mut Cursor::new(data),kind,order,"fuzz");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_untrusted_lengths() {
+    // IFD0 at 16 with one tag claiming 4G longs, next IFD pointer to itself
+    let mut f = b"II*\0\x10\0\0\0CR\x02\0\0\0\0\0".to_vec();
+    f.extend_from_slice(&[1,0, 0x11,1, 4,0, 0xff,0xff,0xff,0xff, 0,0,0,0, 0x10,0,0,0]);
+    let image = open_bytes(f.clone(),"loop".to_string()).unwrap();
+    assert_eq!(1,image.ifd.len());
+    assert_eq!(Some(8),image.ifd[0].tags.get("strip_offset").map(|v| v.len()));
+    // pseudo random corruption must not panic
+    let mut x = 0x2545f491u32;
+    for _ in 0..2000 {
+        let mut g = f.clone();
+        for _ in 0..4 {
+            x ^= x << 13; x ^= x >> 17; x ^= x << 5;
+            let i = 4 + x as usize % (g.len() - 4);
+            g[i] = (x >> 8) as u8;
+        }
+        let _ = open_bytes(g,"random".to_string()).map(|i| (i.preview(),i.exif_summary(),i.content_hash()));
+    }
+    // a megabyte of bytes decodes to many times that in TagData
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let build = |n| Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::bytes(0x02bc, 1, &vec![0u8; n]))).build();
+    assert!(open_bytes(build(64 << 10),"small".to_string()).is_ok());
+    assert!(open_bytes(build(1 << 20),"large".to_string()).is_err());
+}
+
+#[test]
+fn test_raw_format() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    // SOI, DHT stub, lossless SOF3: 14 bits, 3516 lines, 2 components of 2784 pixels
+    let strip = [0xff,0xd8, 0xff,0xc4,0,4,0,0, 0xff,0xc3,0,14,14,0x0d,0xbc,0x0a,0xe0,2,1,0x11,0,2,0x11,0];
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x10f,"Canon")).tag(Tag::short(0x100,&[5472])).tag(Tag::short(0x101,&[3648])))
+        .ifd(SynthIfd::new().tag(Tag::short(0x103,&[6])).strip(&strip))
+        .raw_ifd(1).build();
+    let image = open_bytes(f,"dims".to_string()).unwrap();
+    assert_eq!(Some("Canon"),image.make());
+    assert_eq!((Some(5568),Some(3516),Some(14)),(image.width(),image.height(),image.bits_per_sample()));
+    assert_eq!(Some(Compression::OldJpeg),image.compression());
+    assert_eq!(Compression::JpegXl,Compression::from(52546));
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110,"Canon EOS 6D")))
+        .ifd(SynthIfd::new().tag(Tag::short(0x103,&[52546])).strip(&[1,2,3]))
+        .raw_ifd(1).build();
+    let image = open_bytes(f,"jxl".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"),image.model());
+    assert_eq!("JPEG XL compressed data is not supported",image.warnings()[0].msg);
+    let e = image.frames().iter().find(|f| f.compression() == Some(Compression::JpegXl)).unwrap().read_data().unwrap_err();
+    assert_eq!("Feature not Implemented: JPEG XL compressed data",e.to_string());
+    assert_eq!(Compression::Other(65537),Compression::from(65537));
+    let f = Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::short(0x100,&[5472])).tag(Tag::short(0x101,&[3648]))).build();
+    let image = open_bytes(f,"ifd0".to_string()).unwrap();
+    assert_eq!((Some(5472),Some(3648),None),(image.width(),image.height(),image.compression()));
+    let f = Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::ascii(0x110,"Canon EOS 6D"))
+                                  .tag(Tag::short(0x100,&[5472])).tag(Tag::short(0x101,&[3648]))).build();
+    let image = open_bytes(f,"camera".to_string()).unwrap();
+    assert_eq!((Some(5568),Some(3708)),(image.width(),image.height()));
+    assert_eq!(Some([0,1,1,2]),image.cfa_pattern());
+}
+
+#[test]
+fn test_error_context() {
+    let mut head = b"II*\0\x10\0\0\0CR\x02\0\0\0\0\0".to_vec();
+    head[2] = 0x2b;
+    match open_bytes(head,"bad".to_string()) {
+        Err(RawFileError::FileFormat{offset,..}) => assert_eq!(Some(2),offset),
+        _ => panic!("Tiff magic not checked")
+    }
+    let e = RawFileError::Io(io::Error::new(io::ErrorKind::UnexpectedEof,"eof"))
+        .context(Some(0x20),None,Some(0x110)).context(Some(0x10),Some("ifd0"),None);
+    assert_eq!("File format error: Unexpected end of file at offset 0x20 in ifd0 tag 0x0110",e.to_string());
+}
+
+#[test]
+fn test_byte_order() {
+    // big endian IFD0 with the model and a rational, the EXIF IFD is little endian
+    let mut f = b"MM\0\x2a\0\0\0\x10CR\x02\0\0\0\0\0\0\x03".to_vec();
+    f.extend_from_slice(b"\x01\x10\0\x02\0\0\0\x04EOS\0");
+    f.extend_from_slice(b"\x01\x1a\0\x05\0\0\0\x01\0\0\0\x3a");
+    f.extend_from_slice(b"\x87\x69\0\x04\0\0\0\x01\0\0\0\x42\0\0\0\0");
+    f.extend_from_slice(b"\0\0\0\x48\0\0\0\x01");
+    f.extend_from_slice(b"\x01\0\x27\x88\x03\0\x01\0\0\0\x20\x03\0\0\0\0\0\0");
+    let image = open_bytes(f,"mm".to_string()).unwrap();
+    assert_eq!(Some("EOS"),image.model());
+    assert_eq!(Some(72 | 1 << 32),image.tag("x_resolution").and_then(|v| v.first()).and_then(|d| match *d { TagData::U64(r) => Some(r), _ => None }));
+    assert_eq!(Some(800),image.exif_summary().iso);
+}
+
+#[test]
+fn test_tiles() {
+    use synth::{Cr2Builder,Ifd as SynthIfd};
+    let f = Cr2Builder::new().ifd(SynthIfd::new())
+        .ifd(SynthIfd::new().tiles(256,256,&[&[1,2,3],&[4,5]])).raw_ifd(1).build();
+    let image = open_bytes(f,"tiles".to_string()).unwrap();
+    match image.raw_layout() {
+        Some(Layout::Tiles{width,length,tiles}) => {
+            assert_eq!((256,256),(width,length));
+            assert_eq!(vec![3,2],tiles.iter().map(|t| t.1).collect::<Vec<_>>());
+        },
+        l => panic!("unexpected layout {:?}",l)
+    }
+    assert!(image.content_hash().is_ok());
+    assert!(::validate::validate(&image).unwrap().is_empty());
+}
+
+#[test]
+fn test_upright_preview() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::short(0x112,&[8])).strip(b"\xff\xd8\xff\xda\0\x02\xff\xd9")).build();
+    let image = open_bytes(f,"upright".to_string()).unwrap();
+    assert_eq!(Some(8),image.orientation());
+    let jpeg = image.upright_preview().unwrap();
+    assert_eq!(b"\xff\xd8\xff\xe1\0\x22Exif\0\0II*\0",&jpeg[..16]);
+    assert_eq!(8,jpeg[30]);
+}
+
+#[test]
+fn test_aspect_crop() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::ascii(0x110,"Canon EOS 6D")))
+        .makernote(SynthIfd::new().tag(Tag::long(0x009a,&[7,5472,3072,0,288]))).build();
+    let image = open_bytes(f,"aspect".to_string()).unwrap();
+    assert_eq!(Some(Area{top: 326, left: 72, bottom: 3398, right: 5544}),image.default_crop());
+    assert_eq!(Some(Area{top: 38, left: 72, bottom: 3686, right: 5544}),image.active_area());
+    let f = Cr2Builder::new().ifd(SynthIfd::new().tag(Tag::ascii(0x110,"Canon EOS 6D")))
+        .makernote(SynthIfd::new().tag(Tag::long(0x009a,&[7,5472,0xffff_fff0,0,288]))).build();
+    assert_eq!(None,open_bytes(f,"aspect".to_string()).unwrap().default_crop());
+}
+
+#[test]
+fn test_tag_handlers() {
+    use std::sync::Mutex;
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::short(0x9999, &[1, 2, 3])).strip(&[0xff, 0xd8]))
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-handlers-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, f).unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (a, b) = (seen.clone(), seen.clone());
+    let handlers = TagHandlers::new()
+        .tag(0x9999, move |t| a.lock().unwrap().push((t.id, t.data.to_vec())))
+        .ifd(IfdKind::Exif, move |t| b.lock().unwrap().push((t.id, Vec::new())));
+    let image = open_with_handlers(path.to_str().unwrap().to_string(), handlers).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(vec![(0x9999, vec![1, 0, 2, 0, 3, 0])], *seen.lock().unwrap());
+    ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_warnings() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D\0\0\0")).strip(&[0xff, 0xd8]))
+        .build();
+    let image = open_bytes(f, "a".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    let w = image.warnings();
+    assert_eq!(1, w.len(), "{:?}", w);
+    assert_eq!(("ifd0", Some(0x110)), (w[0].ifd.as_str(), w[0].tag));
+    assert!(w[0].msg.contains("padded with 4 NUL bytes"));
+}
+
+#[test]
+fn test_error_code() {
+    let e = RawFileError::format(FormatErrorKind::Invalid, "bad");
+    assert_eq!(3, e.code() as u32);
+    assert_eq!(Some(ErrorCode::FileFormat), ErrorCode::from_code(e.code() as u32));
+    for c in 1..7 {
+        assert_eq!(c, ErrorCode::from_code(c).unwrap() as u32);
+    }
+    assert_eq!(None, ErrorCode::from_code(0));
+}
+
+#[test]
+fn test_error_details() {
+    struct Short;
+    impl ErrorMessages for Short {
+        fn message(&self, code: ErrorCode, details: &[(&'static str,String)]) -> String {
+            let d: Vec<String> = details.iter().map(|&(k,ref v)| format!("{}={}",k,v)).collect();
+            format!("E{} {}",code as u32,d.join(" "))
+        }
+    }
+    let e = RawFileError::format(FormatErrorKind::BadMagic, "Tiff Magic mismatch").context(Some(2),Some("ifd0"),None);
+    assert_eq!("E3 kind=bad_magic msg=Tiff Magic mismatch offset=0x2 ifd=ifd0", e.render(&Short));
+    let image = open_bytes(b"II*\0\x10\0\0\0CR\x02\0\0\0\0\0\0\0".to_vec(),"empty".to_string()).unwrap();
+    assert_eq!(("kind","missing".to_string()), image.preview().unwrap_err().details()[0]);
+    assert_eq!(vec![("type","13".to_string())], RawFileError::TypeError(13).details());
+}
+
+#[test]
+fn test_tag_data() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::short(0x9999, &[1, 2])).strip(&[0xff, 0xd8]))
+        .build();
+    let image = open_bytes(f, "a".to_string()).unwrap();
+    assert_eq!(Some(&[TagData::Unsigned(1), TagData::Unsigned(2)][..]), image.tag_values("ifd0", "0x9999"));
+    assert_eq!(Some("Canon EOS 6D"), image.tag_values("ifd0", "model").and_then(|v| v[0].as_str()));
+    assert_eq!(None, image.tag_values("exif", "model"));
+    let r = TagData::I64(-1i32 as u32 as i64 | 4 << 32);
+    assert_eq!(("-1/4".to_string(), Some(-0.25), None), (r.to_string(), r.as_f64(), r.as_i64()));
+    assert_eq!(Some(-3), TagData::Signed(-3).as_i64());
+}
+
+#[test]
+fn test_display() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::short(0x100, &[64])).tag(Tag::short(0x101, &[48]))
+             .tag(Tag::ascii(0x132, "2020:01:02 03:04:05")).strip(&[0xff, 0xd8]))
+        .build();
+    let image = open_bytes(f, "a.cr2".to_string()).unwrap();
+    assert_eq!("a.cr2: CR2, Canon EOS 6D, 5568x3708, 2020:01:02 03:04:05", image.to_string());
+    let debug = format!("{:?}", image);
+    assert!(debug.starts_with("RawImage { file_name: \"a.cr2\", byte_order: Intel"), "{}", debug);
+    assert!(debug.contains("ifd0: [(\"width\", [Unsigned(64)])"), "{}", debug);
+}
+
+#[test]
+fn test_reload() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let build = |model| Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, model)).strip(&[0xff, 0xd8]))
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-reload-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, build("Canon EOS 6D")).unwrap();
+    let mut image = open(path.to_str().unwrap().to_string()).unwrap();
+    let metadata = image.metadata();
+    ::std::fs::write(&path, build("Canon EOS 5D")).unwrap();
+    image.reload().unwrap();
+    assert_eq!(Some("Canon EOS 5D"), image.model());
+    let copy = ::std::thread::spawn(move || RawMetadata::from_bytes(&metadata.to_bytes()).unwrap()).join().unwrap();
+    assert_eq!(Some("Canon EOS 6D"), copy.model());
+    ::std::fs::remove_file(&path).unwrap();
+    assert!(image.reload().is_err());
+    assert_eq!(Some("Canon EOS 5D"), image.model());
+}
+
+#[test]
+fn test_frames() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .ifd(SynthIfd::new().tag(Tag::short(0x103, &[6])).strip(&[1, 2, 3]))
+        .raw_ifd(1)
+        .build();
+    let image = open_bytes(f, "a".to_string()).unwrap();
+    let frames = image.frames();
+    assert_eq!(1, frames.len());
+    assert_eq!((0, Some(Compression::OldJpeg)), (frames[0].index(), frames[0].compression()));
+    assert_eq!(vec![1, 2, 3], frames[0].read_data().unwrap());
+}
+
+#[test]
+fn test_raw_ranges() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .ifd(SynthIfd::new().tag(Tag::short(0x103, &[6])).tag(Tag::short(0xc640, &[2, 1728, 1792])).strip(&[1, 2, 3]))
+        .raw_ifd(1)
+        .build();
+    let image = open_bytes(f.clone(), "a".to_string()).unwrap();
+    let r = image.raw_ranges().unwrap();
+    assert_eq!((6, Some((2, 1728, 1792))), (r.compression, r.slices));
+    match r.layout {
+        Layout::Strips(ref s) => assert_eq!(&[1, 2, 3], &f[s[0].0 as usize..(s[0].0 + s[0].1) as usize]),
+        _ => panic!("{:?}", r)
+    }
+}
+
+#[test]
+fn test_checksums() {
+    use hash::{self,Xxh64};
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .ifd(SynthIfd::new().tag(Tag::short(0x103, &[6])).strip(&[1, 2, 3]))
+        .raw_ifd(1)
+        .build();
+    let image = open_bytes(f.clone(), "a".to_string()).unwrap();
+    assert_eq!(image.content_hash().unwrap().to_vec(), image.raw_checksum(Algorithm::Sha256).unwrap());
+    let mut h = Xxh64::default();
+    h.update(&[1, 2, 3]);
+    assert_eq!(h.finish().to_vec(), image.raw_checksum(Algorithm::Xxh64).unwrap());
+    let mut h = Sha256::default();
+    h.update(&f);
+    assert_eq!(hash::to_hex(&h.finish()), hash::to_hex(&image.file_checksum(Algorithm::Sha256).unwrap()));
+    let both = [Algorithm::Xxh64, Algorithm::Sha256];
+    let sums: Vec<Vec<u8>> = both.iter().map(|&a| image.file_checksum(a).unwrap()).collect();
+    assert_eq!(sums, image.file_checksums(&both).unwrap());
+    let sums: Vec<Vec<u8>> = both.iter().map(|&a| image.raw_checksum(a).unwrap()).collect();
+    assert_eq!(sums, image.raw_checksums(&both).unwrap());
+}
+
+#[test]
+fn test_open_stream() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .build();
+    let image = open_stream(io::Cursor::new(f), "-".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(vec![0xff, 0xd8, 0xff, 0xd9], image.preview().unwrap());
+    // the stream is not read up to the raw data
+    struct Metadata(io::Cursor<Vec<u8>>, u64);
+    impl Read for Metadata {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.position() >= self.1 {
+                return Err(io::Error::new(io::ErrorKind::Other, "read beyond the metadata"));
+            }
+            let n = ::std::cmp::min(buf.len() as u64, self.1 - self.0.position()) as usize;
+            self.0.read(&mut buf[..n])
+        }
+    }
+    // the raw data follows the metadata, as in camera files
+    let header = [0xff, 0xd8, 0xff, 0xc3, 0, 8, 14, 0x0d, 0xbc, 0x0a, 0xe0, 2];
+    let mut f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::short(0x100, &[5472])).tag(Tag::short(0x101, &[3648])))
+        .ifd(SynthIfd::new().strip(&header))
+        .raw_ifd(1).build();
+    let raw = f.len() as u64;
+    let entry = open_bytes(f.clone(), "f".to_string()).unwrap().ifd[1].pos["strip_offset"].offset as usize;
+    f[entry..entry + 4].copy_from_slice(&(raw as u32).to_le_bytes());
+    f.extend_from_slice(&header);
+    assert_eq!(Some(3516), open_bytes(f.clone(), "f".to_string()).unwrap().height());
+    let image = open_stream(Metadata(io::Cursor::new(f), raw), "-".to_string()).unwrap();
+    assert_eq!((Some(5472), Some(3648)), (image.width(), image.height()));
+}
+
+#[test]
+fn test_save_stripped() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8]))
+        .ifd(SynthIfd::new().thumbnail(&[0xff, 0xd8, 1]))
+        .exif(SynthIfd::new().tag(Tag::ascii(0xa430, "Jane Doe")))
+        .gps(SynthIfd::new().tag(Tag::bytes(0x0000, 1, &[2, 3, 0, 0]))
+             .tag(Tag::rational(0x0002, &[(52, 1), (31, 1), (1234, 100)])))
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-stripped-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, f).unwrap();
+    let name = path.to_str().unwrap().to_string();
+    let image = open(name.clone()).unwrap();
+    assert_eq!(2, image.gps.as_ref().unwrap().tags.len());
+    // stripping in place replaces the file only once the copy is written
+    image.save_stripped(&name, &PrivacyPolicy::default()).unwrap();
+    let data = ::std::fs::read(&path).unwrap();
+    assert!(!data.windows(8).any(|w| w == b"Jane Doe"));
+    let stripped = open(name.clone()).unwrap();
+    let gps = stripped.gps.as_ref().unwrap();
+    assert!(gps.tags.is_empty());
+    assert_eq!(vec![0u8; 6], stripped.read_range(gps.offset as u64, 6).unwrap());
+    assert_eq!(2, stripped.ifd.len());
+    assert_eq!(Some("Canon EOS 6D"), stripped.model());
+    assert_eq!(vec![0xff, 0xd8, 1], stripped.thumbnail().unwrap());
+    // a big endian GPS IFD in a little endian file is cleared by its own count
+    let mut f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")))
+        .gps(SynthIfd::new().tag(Tag::bytes(0x0000, 1, &[2, 3, 0, 0])).tag(Tag::ascii(0x0001, "N")))
+        .ifd(SynthIfd::new().thumbnail(&[0xff, 0xd8, 1]))
+        .build();
+    let o = open_bytes(f.clone(), name.clone()).unwrap().gps.unwrap().offset;
+    f[o..o + 2].reverse();
+    for e in (o + 2..o + 2 + 2 * 12).step_by(12) {
+        f[e..e + 2].reverse();
+        f[e + 2..e + 4].reverse();
+        f[e + 4..e + 8].reverse();
+    }
+    ::std::fs::write(&path, &f).unwrap();
+    let image = open(name.clone()).unwrap();
+    assert_eq!(2, image.gps.as_ref().unwrap().tags.len());
+    image.save_stripped(&name, &PrivacyPolicy::default()).unwrap();
+    let data = ::std::fs::read(&path).unwrap();
+    assert_eq!(vec![0u8; 2 + 2 * 12 + 4], data[o..o + 30].to_vec());
+    assert_eq!(f[o + 30..].to_vec(), data[o + 30..].to_vec());
+    assert_eq!(vec![0xff, 0xd8, 1], open(name).unwrap().thumbnail().unwrap());
+    ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_set_artist() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::ascii(0x13b, "Old")).strip(&[0xff, 0xd8]))
+        .ifd(SynthIfd::new().thumbnail(&[0xff, 0xd8, 1]))
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-artist-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, f).unwrap();
+    let name = path.to_str().unwrap().to_string();
+    let mut image = open(name.clone()).unwrap();
+    image.set_artist("A. Photographer").unwrap();
+    image.set_copyright("CC BY").unwrap();
+    assert_eq!((Some("A. Photographer"), Some("CC BY")), (image.artist(), image.copyright()));
+    let reopened = open(name.clone()).unwrap();
+    assert_eq!((Some("A. Photographer"), Some("CC BY")), (reopened.artist(), reopened.copyright()));
+    assert_eq!(Some("Canon EOS 6D"), reopened.model());
+    assert_eq!(vec![0xff, 0xd8, 1], reopened.thumbnail().unwrap());
+    // a rejected value leaves the file unchanged
+    let data = ::std::fs::read(&path).unwrap();
+    assert!(image.set_artist("A\0B").is_err());
+    assert_eq!(data, ::std::fs::read(&path).unwrap());
+    assert_eq!(Some("A. Photographer"), image.artist());
+    ::std::fs::remove_file(&path).unwrap();
+    let mut bytes = open_bytes(data, name).unwrap();
+    assert!(bytes.set_copyright("x").is_err());
+}
+
+#[test]
+fn test_open_traced() {
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::new(0x100, 13, 1, vec![0; 4])))
+        .ifd(SynthIfd::new())
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-traced-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, f).unwrap();
+    let image = open_traced(path.to_str().unwrap().to_string()).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+    let trace = image.trace().unwrap();
+    assert_eq!(vec![
+        Event::Ifd { name: "ifd0".to_string(), offset: 16, entries: 2, next: 60 },
+        Event::Anomaly { offset: 18, msg: "tag of unknown type 13 skipped".to_string() },
+        Event::Tag { ifd: "ifd0".to_string(), id: 0x110, tagtype: 2, count: 13, offset: 46, size: 13 },
+        Event::Ifd { name: "ifd1".to_string(), offset: 60, entries: 0, next: 0 }
+    ], trace.events);
+    assert_eq!(image.warnings().len(), trace.anomalies().len());
+}
@@ -0,0 +1,176 @@
+/// Seconds since 1970-01-01 00:00:00, leap seconds are ignored
+pub fn timestamp(year: i64, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> i64 {
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    days * 86400 + hour as i64 * 3600 + min as i64 * 60 + sec as i64
+}
+
+/// Splits a timestamp into year, month, day, hour, minute and second
+pub fn from_timestamp(t: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = if t >= 0 { t / 86400 } else { (t - 86399) / 86400 };
+    let secs = t - days * 86400;
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, (secs / 3600) as u32, (secs % 3600 / 60) as u32, (secs % 60) as u32)
+}
+
+/// Parses the numeric fields of a date, separators are ignored
+fn fields(s: &str, n: usize) -> Option<Vec<u32>> {
+    let v: Vec<u32> = s.split(|c: char| !c.is_ascii_digit())
+        .filter(|f| !f.is_empty())
+        .take(n)
+        .map(|f| f.parse().unwrap_or(0))
+        .collect();
+    if v.len() == n { Some(v) } else { None }
+}
+
+/// Parses an EXIF date "YYYY:MM:DD HH:MM:SS", the result is in the
+/// (unknown) time zone of the camera clock
+pub fn parse_exif(s: &str) -> Option<i64> {
+    let f = match fields(s, 6) {
+        Some(f) => f,
+        None => return None
+    };
+    if f[0] == 0 || f[1] == 0 || f[1] > 12 || f[2] == 0 || f[2] > 31 {
+        return None;
+    }
+    Some(timestamp(f[0] as i64, f[1], f[2], f[3], f[4], f[5]))
+}
+
+/// Formats a timestamp as EXIF date
+pub fn format_exif(t: i64) -> String {
+    let (y, mo, d, h, mi, s) = from_timestamp(t);
+    format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}", y, mo, d, h, mi, s)
+}
+
+/// Parses an ISO 8601 date as used by GPX and XMP, returns UTC
+///
+/// Fractional seconds are dropped, dates without time zone are taken as UTC.
+pub fn parse_iso8601(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, zone) = match s.rfind(|c| c == 'Z' || c == '+' || c == '-') {
+        Some(i) if i > 10 => (&s[..i], &s[i..]),
+        _ => (s, "")
+    };
+    let date = date.split('.').next().unwrap_or(date);
+    let f = match fields(date, 6) {
+        Some(f) => f,
+        None => return None
+    };
+    let mut t = timestamp(f[0] as i64, f[1], f[2], f[3], f[4], f[5]);
+    if zone.len() > 1 {
+        let z = match fields(&zone[1..], 2) {
+            Some(z) => z,
+            None => return None
+        };
+        let offset = (z[0] * 3600 + z[1] * 60) as i64;
+        t += if zone.starts_with('-') { offset } else { -offset };
+    }
+    Some(t)
+}
+
+/// Formats a UTC timestamp as ISO 8601 date in the time zone with the
+/// given offset in seconds
+pub fn format_iso8601(t: i64, offset: i64) -> String {
+    let (y, mo, d, h, mi, s) = from_timestamp(t + offset);
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        let a = offset.abs();
+        format!("{}{:02}:{:02}", if offset < 0 { '-' } else { '+' }, a / 3600, a % 3600 / 60)
+    };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}", y, mo, d, h, mi, s, zone)
+}
+
+/// Parses a time zone offset "+HH:MM", "-HHMM" or "+HH", returns seconds
+pub fn parse_offset(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None
+    };
+    let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || (digits.len() != 2 && digits.len() != 4) {
+        return None;
+    }
+    let h: i64 = digits[..2].parse().unwrap_or(0);
+    let m: i64 = if digits.len() == 4 { digits[2..].parse().unwrap_or(0) } else { 0 };
+    if m >= 60 {
+        return None;
+    }
+    Some(sign * (h * 3600 + m * 60))
+}
+
+/// Correction of a wrongly set camera clock
+///
+/// The shift is added to the camera time, the result is the local time
+/// in the time zone with the given UTC offset. Both are in seconds.
+#[derive(Debug,Clone,Copy,PartialEq,Default)]
+pub struct ClockCorrection {
+    pub shift: i64,
+    pub utc_offset: i64
+}
+
+impl ClockCorrection {
+    /// Corrected local time of a camera timestamp
+    pub fn local(&self, camera_time: i64) -> i64 {
+        camera_time + self.shift
+    }
+
+    /// Corrected UTC time of a camera timestamp
+    pub fn utc(&self, camera_time: i64) -> i64 {
+        self.local(camera_time) - self.utc_offset
+    }
+
+    /// Corrected time as ISO 8601 date with time zone
+    pub fn format(&self, camera_time: i64) -> String {
+        format_iso8601(self.utc(camera_time), self.utc_offset)
+    }
+}
+
+#[test]
+fn test_timestamp() {
+    assert_eq!(0, timestamp(1970, 1, 1, 0, 0, 0));
+    assert_eq!(1464171072, timestamp(2016, 5, 25, 10, 11, 12));
+    assert_eq!((2016, 2, 29, 23, 59, 59), from_timestamp(timestamp(2016, 2, 29, 23, 59, 59)));
+    assert_eq!((1969, 12, 31, 23, 0, 0), from_timestamp(-3600));
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Some(1464171072), parse_exif("2016:05:25 10:11:12"));
+    assert_eq!(None, parse_exif("0000:00:00 00:00:00"));
+    assert_eq!(Some(1464171072), parse_iso8601("2016-05-25T10:11:12Z"));
+    assert_eq!(Some(1464171072), parse_iso8601("2016-05-25T12:11:12.250+02:00"));
+    assert_eq!(Some(1464171072), parse_iso8601("2016-05-25T05:11:12-05:00"));
+    assert_eq!("2016-05-25T12:11:12+02:00", format_iso8601(1464171072, 7200));
+    assert_eq!("2016:05:25 10:11:12", format_exif(1464171072));
+}
+
+#[test]
+fn test_clock_correction() {
+    assert_eq!(Some(-5400), parse_offset("-01:30"));
+    assert_eq!(Some(7200), parse_offset("+02"));
+    assert_eq!(Some(34200), parse_offset("+0930"));
+    assert_eq!(None, parse_offset("02:00"));
+    assert_eq!(None, parse_offset("+2:00"));
+    // camera set to CET while the picture was taken in Tokyo
+    let c = ClockCorrection { shift: 8 * 3600, utc_offset: 9 * 3600 };
+    let t = parse_exif("2016:05:25 10:11:12").unwrap();
+    assert_eq!("2016:05:25 18:11:12", format_exif(c.local(t)));
+    assert_eq!("2016-05-25T18:11:12+09:00", c.format(t));
+}
@@ -0,0 +1,87 @@
+use std::sync::{Arc,RwLock};
+use cr2::{self,RawFileError,RawImage};
+use identify::{self,FileKind};
+use source::{RawSource,FileSource};
+
+/// A reader for a format the crate does not support itself, registered
+/// at runtime with register
+///
+/// Decoders are asked before the built-in formats, so they can also
+/// take over a format, e.g. a container wrapping a CR2.
+pub trait RawDecoder: Send + Sync {
+    /// Identifies the file from its first bytes, None if the decoder does
+    /// not read it. At most 512 bytes are given, fewer for short files.
+    fn sniff(&self, head: &[u8]) -> Option<FileKind>;
+
+    /// Opens a file the decoder identified, name is used as its file name
+    fn open(&self, source: Arc<dyn RawSource>, name: String) -> Result<RawImage,RawFileError>;
+}
+
+static DECODERS: RwLock<Vec<Arc<dyn RawDecoder>>> = RwLock::new(Vec::new());
+
+/// Registers a decoder, decoders registered later are asked first
+pub fn register(decoder: Arc<dyn RawDecoder>) {
+    DECODERS.write().unwrap_or_else(|e| e.into_inner()).insert(0, decoder);
+}
+
+/// The first registered decoder reading a file, with its kind
+pub(crate) fn sniff(head: &[u8]) -> Option<(Arc<dyn RawDecoder>, FileKind)> {
+    let decoders = DECODERS.read().unwrap_or_else(|e| e.into_inner());
+    decoders.iter().filter_map(|d| d.sniff(head).map(|k| (d.clone(), k))).next()
+}
+
+/// Opens a file from any storage backend with a registered decoder, or
+/// as CR2 if none reads it
+pub fn open_source(source: Arc<dyn RawSource>, name: String) -> Result<RawImage,RawFileError> {
+    let mut head = vec![0; identify::HEAD as usize];
+    let n = try!(source.read_at(0, &mut head));
+    match sniff(&head[..n]) {
+        Some((decoder, _)) => decoder.open(source, name),
+        None => cr2::open_source(source, name)
+    }
+}
+
+/// Opens a file with a registered decoder, or as CR2 if none reads it
+///
+/// CR2 files are opened as by cr2::open, so they can be modified in place.
+pub fn open(path: String) -> Result<RawImage,RawFileError> {
+    let source = Arc::new(try!(FileSource::open(&path)));
+    let mut head = vec![0; identify::HEAD as usize];
+    let n = try!(source.read_at(0, &mut head));
+    match sniff(&head[..n]) {
+        Some((decoder, _)) => decoder.open(source, path),
+        None => cr2::open(path)
+    }
+}
+
+#[test]
+fn test_register() {
+    use identify::Format;
+    use synth::{Cr2Builder,Ifd,Tag};
+    // a container prefixing a CR2 with a 4 byte signature
+    struct Wrapped;
+    impl RawDecoder for Wrapped {
+        fn sniff(&self, head: &[u8]) -> Option<FileKind> {
+            if head.starts_with(b"WRAP") {
+                Some(FileKind { format: Format::Unknown, vendor: Some("Test".to_string()), version: None })
+            } else {
+                None
+            }
+        }
+
+        fn open(&self, source: Arc<dyn RawSource>, name: String) -> Result<RawImage,RawFileError> {
+            let mut data = vec![0; try!(source.len()) as usize];
+            try!(source.read_at(0, &mut data));
+            cr2::open_bytes(data.split_off(4), name)
+        }
+    }
+    let cr2 = Cr2Builder::new().ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8])).build();
+    let mut wrapped = b"WRAP".to_vec();
+    wrapped.extend_from_slice(&cr2);
+    assert!(open_source(Arc::new(wrapped.clone()), "w".to_string()).is_err());
+    register(Arc::new(Wrapped));
+    let image = open_source(Arc::new(wrapped.clone()), "w".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(Some("Test".to_string()), identify::identify_bytes(&wrapped).vendor);
+    assert!(open_source(Arc::new(cr2), "c".to_string()).is_ok());
+}
@@ -0,0 +1,71 @@
+use cr2::RawImage;
+
+/// Kind of difference of a tag
+#[derive(Debug,Clone,PartialEq)]
+pub enum Change {
+    /// Only present in the second file
+    Added(String),
+    /// Only present in the first file
+    Removed(String),
+    /// Different values, first and second file
+    Changed(String, String)
+}
+
+/// Difference of a single tag
+#[derive(Debug,Clone,PartialEq)]
+pub struct TagDiff {
+    /// IFD of the tag: ifd0, ifd1, ..., exif, gps or makernote
+    pub ifd: String,
+    pub tag: String,
+    pub change: Change
+}
+
+/// Compares the tags of two files
+///
+/// The MakerNote blob of the EXIF IFD is skipped, its tags are compared
+/// one by one instead.
+pub fn diff(a: &RawImage, b: &RawImage) -> Vec<TagDiff> {
+    let skip = |t: &(String, String, String)| !(t.0 == "exif" && t.1 == "maker_note");
+    let ta: Vec<_> = a.tag_list().into_iter().filter(&skip).collect();
+    let tb: Vec<_> = b.tag_list().into_iter().filter(&skip).collect();
+    let mut d = Vec::new();
+    for &(ref ifd, ref tag, ref va) in &ta {
+        match tb.iter().find(|t| t.0 == *ifd && t.1 == *tag) {
+            Some(t) if t.2 == *va => {},
+            Some(t) => d.push(TagDiff { ifd: ifd.clone(), tag: tag.clone(), change: Change::Changed(va.clone(), t.2.clone()) }),
+            None => d.push(TagDiff { ifd: ifd.clone(), tag: tag.clone(), change: Change::Removed(va.clone()) })
+        }
+    }
+    for &(ref ifd, ref tag, ref vb) in &tb {
+        if !ta.iter().any(|t| t.0 == *ifd && t.1 == *tag) {
+            d.push(TagDiff { ifd: ifd.clone(), tag: tag.clone(), change: Change::Added(vb.clone()) });
+        }
+    }
+    d
+}
+
+#[test]
+fn test_diff() {
+    use cr2;
+    use synth::{Cr2Builder,Ifd,Tag};
+    let a = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::ascii(0x13b, "Jane")).strip(&[0xff, 0xd8]))
+        .exif(Ifd::new().tag(Tag::short(0x8827, &[100])))
+        .makernote(Ifd::new().tag(Tag::short(0x0001, &[1, 2])))
+        .build();
+    let b = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::ascii(0x8298, "CC BY")).strip(&[0xff, 0xd8]))
+        .exif(Ifd::new().tag(Tag::short(0x8827, &[1600])))
+        .makernote(Ifd::new().tag(Tag::short(0x0001, &[1, 3])))
+        .build();
+    let a = cr2::open_bytes(a, "a".to_string()).unwrap();
+    let b = cr2::open_bytes(b, "b".to_string()).unwrap();
+    let d = |ifd: &str, tag: &str, change| TagDiff { ifd: ifd.to_string(), tag: tag.to_string(), change: change };
+    // the MakerNote blob differs too, only its tags are reported
+    assert_eq!(vec![d("ifd0", "artist", Change::Removed("Jane".to_string())),
+                    d("exif", "iso", Change::Changed("100".to_string(), "1600".to_string())),
+                    d("makernote", "camera_settings", Change::Changed("1 2".to_string(), "1 3".to_string())),
+                    d("ifd0", "copyright", Change::Added("CC BY".to_string()))], diff(&a, &b));
+    assert_eq!(Change::Added("Jane".to_string()), diff(&b, &a)[3].change);
+    assert!(diff(&a, &a).is_empty());
+}
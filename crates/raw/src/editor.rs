@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self,File};
+use std::io::{Read,Write};
+use std::path::{Path,PathBuf};
+use cr2::{self,RawFileError,FormatErrorKind};
+use source;
+use ifd::writer::{self,ByteOrder};
+pub use ifd::writer::Value;
+
+/// IFDs the editor can change
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Location {
+    Ifd0,
+    Exif,
+    Gps
+}
+
+/// Changes tags of a TIFF based raw file
+///
+/// The changed IFDs are written as copies to the end of the file, the
+/// old ones and their values are left in place and no other data moves,
+/// so offsets into the MakerNote stay valid. The new file is checked by
+/// parsing it, then written to a temporary file that replaces the
+/// original, which is intact if writing fails.
+#[derive(Debug,Clone)]
+pub struct Editor {
+    path: PathBuf,
+    edits: Vec<(Location, u16, Value)>,
+    backup: bool
+}
+
+impl Editor {
+    pub fn new<P: AsRef<Path>>(path: P) -> Editor {
+        Editor { path: path.as_ref().to_path_buf(), edits: Vec::new(), backup: false }
+    }
+
+    /// Sets or adds a tag, later edits of the same tag take precedence
+    pub fn set(mut self, location: Location, id: u16, value: Value) -> Editor {
+        self.edits.push((location, id, value));
+        self
+    }
+
+    /// Keeps a copy of the original with .bak appended to its name
+    pub fn backup(mut self, backup: bool) -> Editor {
+        self.backup = backup;
+        self
+    }
+
+    /// Path of the backup, IMG_0001.CR2 -> IMG_0001.CR2.bak
+    pub fn backup_path(&self) -> PathBuf {
+        with_suffix(&self.path, ".bak")
+    }
+
+    /// Writes the edits to the file
+    pub fn save(&self) -> Result<(),RawFileError> {
+        let mut data = Vec::new();
+        try!(try!(File::open(&self.path)).read_to_end(&mut data));
+        let data = try!(self.apply(data));
+        if self.backup {
+            try!(fs::copy(&self.path, self.backup_path()));
+        }
+        source::replace_file(&self.path, |f| f.write_all(&data).map_err(RawFileError::from))
+    }
+
+    /// Applies the edits to the data of a file
+    fn apply(&self, mut data: Vec<u8>) -> Result<Vec<u8>,RawFileError> {
+        if !data.starts_with(b"II") {
+            return Err(RawFileError::NotImplemented("Editing big endian files".to_string()));
+        }
+        let name = self.path.to_string_lossy().into_owned();
+        let image = try!(cr2::open_bytes(data.clone(), name.clone()));
+        let offset = |ifd: &str| image.ifds().iter().find(|i| i.0 == ifd).map(|i| i.2.offset);
+        let mut pointers = Vec::new();
+        for &(location, ifd, pointer) in &[(Location::Exif, "exif", 0x8769), (Location::Gps, "gps", 0x8825)] {
+            let entries = try!(self.entries(&mut data, location));
+            if entries.is_empty() {
+                continue;
+            }
+            let old = match offset(ifd) {
+                Some(o) => o,
+                None => return Err(RawFileError::format(FormatErrorKind::Missing, format!("File has no {} IFD", ifd)))
+            };
+            let new = try!(rewrite(&mut data, old, entries));
+            pointers.push(writer::entry(pointer, 4, 1, new.to_le_bytes(), ByteOrder::Intel));
+        }
+        let mut entries = try!(self.entries(&mut data, Location::Ifd0));
+        entries.extend(pointers);
+        if !entries.is_empty() {
+            let old = offset("ifd0").unwrap_or(0);
+            let new = try!(rewrite(&mut data, old, entries));
+            data[4..8].copy_from_slice(&new.to_le_bytes());
+        }
+        // the result has to parse like the original
+        try!(cr2::open_bytes(data.clone(), name));
+        Ok(data)
+    }
+
+    /// Entries for the edits of an IFD, values longer than 4 bytes are
+    /// appended to data
+    fn entries(&self, data: &mut Vec<u8>, location: Location) -> Result<Vec<[u8; 12]>,RawFileError> {
+        let mut entries = BTreeMap::new();
+        for &(_, id, ref value) in self.edits.iter().filter(|e| e.0 == location) {
+            let (tagtype, count, bytes) = try!(value.encode(ByteOrder::Intel));
+            let mut field = [0u8; 4];
+            if bytes.len() <= 4 {
+                field[..bytes.len()].copy_from_slice(&bytes);
+            } else {
+                field = try!(append(data, &bytes)).to_le_bytes();
+            }
+            entries.insert(id, writer::entry(id, tagtype, count, field, ByteOrder::Intel));
+        }
+        Ok(entries.into_iter().map(|e| e.1).collect())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut p: OsString = path.as_os_str().to_owned();
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+/// Appends data at a word boundary, returns its offset
+fn append(data: &mut Vec<u8>, value: &[u8]) -> Result<u32,RawFileError> {
+    if data.len() % 2 == 1 {
+        data.push(0);
+    }
+    let offset = data.len();
+    if offset + value.len() > u32::max_value() as usize {
+        return Err(RawFileError::format(FormatErrorKind::TooLarge, "File exceeds 4 GB"));
+    }
+    data.extend_from_slice(value);
+    Ok(offset as u32)
+}
+
+/// Appends a copy of the IFD at offset with the entries replaced or
+/// added, returns the offset of the copy
+fn rewrite(data: &mut Vec<u8>, offset: usize, new: Vec<[u8; 12]>) -> Result<u32,RawFileError> {
+    let n = match data.get(offset..offset + 2) {
+        Some(b) => u16::from_le_bytes([b[0], b[1]]) as usize,
+        None => return Err(RawFileError::format(FormatErrorKind::Truncated, "IFD beyond end of file"))
+    };
+    let end = offset + 2 + n * 12;
+    if end + 4 > data.len() {
+        return Err(RawFileError::format(FormatErrorKind::Truncated, "IFD beyond end of file"));
+    }
+    let id = |e: &[u8]| u16::from_le_bytes([e[0], e[1]]);
+    let mut entries: Vec<Vec<u8>> = data[offset + 2..end].chunks(12)
+        .filter(|e| !new.iter().any(|n| id(&n[..]) == id(e))).map(|e| e.to_vec()).collect();
+    entries.extend(new.iter().map(|e| e.to_vec()));
+    entries.sort_by_key(|e| id(e));
+    let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+    for e in entries {
+        ifd.extend_from_slice(&e);
+    }
+    ifd.extend_from_slice(&data[end..end + 4]);
+    append(data, &ifd)
+}
+
+#[test]
+fn test_apply() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x13b, "Old")).strip(&[1, 2, 3]))
+        .ifd(Ifd::new())
+        .exif(Ifd::new().tag(Tag::short(0x8827, &[100])))
+        .build();
+    let e = Editor::new("a.cr2")
+        .set(Location::Ifd0, 0x13b, Value::Ascii("First".to_string()))
+        .set(Location::Ifd0, 0x13b, Value::Ascii("A. Photographer".to_string()))
+        .set(Location::Ifd0, 0x8298, Value::Ascii("CC BY".to_string()))
+        .set(Location::Exif, 0x8827, Value::Short(vec![1600]))
+        .set(Location::Exif, 0x9204, Value::SRational(vec![(-1, 3)]));
+    let image = cr2::open_bytes(e.apply(f.clone()).unwrap(), "a.cr2".to_string()).unwrap();
+    assert_eq!((Some("A. Photographer"), Some("CC BY")), (image.artist(), image.copyright()));
+    assert_eq!((Some(1600), Some(-1.0 / 3.0)), (image.exif_summary().iso, image.exif_summary().exposure_compensation));
+    assert_eq!(Some("Canon"), image.make());
+    assert_eq!(2, image.ifds().iter().filter(|i| i.0.starts_with("ifd")).count());
+    let gps = Editor::new("a.cr2").set(Location::Gps, 1, Value::Ascii("N".to_string()));
+    assert!(gps.apply(f).is_err());
+}
+
+#[test]
+fn test_save() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x13b, "Old")).strip(&[1, 2, 3]))
+        .build();
+    let dir = ::std::env::temp_dir().join(format!("rraw-editor-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.cr2");
+    fs::write(&path, &f).unwrap();
+    let open = |p: &Path| cr2::open(p.to_str().unwrap().to_string()).unwrap();
+    let e = Editor::new(&path).set(Location::Ifd0, 0x13b, Value::Ascii("New".to_string())).backup(true);
+    e.save().unwrap();
+    assert_eq!(Some("New"), open(&path).artist());
+    assert_eq!(f, fs::read(e.backup_path()).unwrap());
+    // a failed edit leaves the file as it was and no temporary file
+    let written = fs::read(&path).unwrap();
+    assert!(Editor::new(&path).set(Location::Gps, 1, Value::Ascii("N".to_string())).save().is_err());
+    assert_eq!(written, fs::read(&path).unwrap());
+    let mut names: Vec<String> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    assert_eq!(vec!["a.cr2", "a.cr2.bak"], names);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,188 @@
+/// Exposure program (EXIF tag 0x8822)
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ExposureProgram {
+    NotDefined,
+    Manual,
+    Normal,
+    AperturePriority,
+    ShutterPriority,
+    Creative,
+    Action,
+    Portrait,
+    Landscape,
+    Other(u32)
+}
+
+impl From<u32> for ExposureProgram {
+    fn from(v: u32) -> ExposureProgram {
+        match v {
+            0 => ExposureProgram::NotDefined,
+            1 => ExposureProgram::Manual,
+            2 => ExposureProgram::Normal,
+            3 => ExposureProgram::AperturePriority,
+            4 => ExposureProgram::ShutterPriority,
+            5 => ExposureProgram::Creative,
+            6 => ExposureProgram::Action,
+            7 => ExposureProgram::Portrait,
+            8 => ExposureProgram::Landscape,
+            v => ExposureProgram::Other(v)
+        }
+    }
+}
+
+/// Exposure mode (EXIF tag 0xa402)
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ExposureMode {
+    Auto,
+    Manual,
+    AutoBracket,
+    Other(u32)
+}
+
+impl From<u32> for ExposureMode {
+    fn from(v: u32) -> ExposureMode {
+        match v {
+            0 => ExposureMode::Auto,
+            1 => ExposureMode::Manual,
+            2 => ExposureMode::AutoBracket,
+            v => ExposureMode::Other(v)
+        }
+    }
+}
+
+/// Metering mode (EXIF tag 0x9207)
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum MeteringMode {
+    Unknown,
+    Average,
+    CenterWeightedAverage,
+    Spot,
+    MultiSpot,
+    Pattern,
+    Partial,
+    Other(u32)
+}
+
+impl From<u32> for MeteringMode {
+    fn from(v: u32) -> MeteringMode {
+        match v {
+            0 => MeteringMode::Unknown,
+            1 => MeteringMode::Average,
+            2 => MeteringMode::CenterWeightedAverage,
+            3 => MeteringMode::Spot,
+            4 => MeteringMode::MultiSpot,
+            5 => MeteringMode::Pattern,
+            6 => MeteringMode::Partial,
+            v => MeteringMode::Other(v)
+        }
+    }
+}
+
+/// Flash firing mode, bits 3 and 4 of the EXIF flash tag
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FlashMode {
+    Unknown,
+    On,
+    Off,
+    Auto
+}
+
+/// Flash state (EXIF tag 0x9209)
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Flash {
+    pub fired: bool,
+    pub mode: FlashMode,
+    pub red_eye_reduction: bool,
+    /// Flash exposure compensation in EV, from the MakerNote
+    pub compensation: Option<f64>
+}
+
+impl From<u32> for Flash {
+    fn from(v: u32) -> Flash {
+        Flash {
+            fired: v & 0x01 != 0,
+            mode: match (v >> 3) & 0x03 {
+                1 => FlashMode::On,
+                2 => FlashMode::Off,
+                3 => FlashMode::Auto,
+                _ => FlashMode::Unknown
+            },
+            red_eye_reduction: v & 0x40 != 0,
+            compensation: None
+        }
+    }
+}
+
+/// Drive mode, from the Canon camera settings
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum DriveMode {
+    Single,
+    Continuous,
+    ContinuousLow,
+    ContinuousHigh,
+    SelfTimer,
+    Other(u16)
+}
+
+impl DriveMode {
+    /// Decodes the continuous drive and self timer fields of the camera settings
+    pub(crate) fn from_canon(continuous: u16, self_timer: u16) -> DriveMode {
+        match continuous {
+            0 if self_timer != 0 => DriveMode::SelfTimer,
+            0 => DriveMode::Single,
+            1 => DriveMode::Continuous,
+            4 => DriveMode::ContinuousLow,
+            5 => DriveMode::ContinuousHigh,
+            v => DriveMode::Other(v)
+        }
+    }
+}
+
+/// The commonly used capture settings of a shot
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct ExifSummary {
+    /// Exposure time in seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// Focal length in mm
+    pub focal_length: Option<f64>,
+    /// Exposure compensation in EV
+    pub exposure_compensation: Option<f64>,
+    pub date_time_original: Option<String>,
+    pub exposure_program: Option<ExposureProgram>,
+    pub exposure_mode: Option<ExposureMode>,
+    pub metering_mode: Option<MeteringMode>,
+    pub flash: Option<Flash>,
+    pub drive_mode: Option<DriveMode>
+}
+
+/// Converts Canon's EV encoding, 1/3 stops are stored as 0x0c and 0x14
+pub(crate) fn canon_ev(v: i16) -> f64 {
+    let sign = if v < 0 { -1.0 } else { 1.0 };
+    let v = (v as i32).abs();
+    let frac = match v & 0x1f {
+        0x0c => 32.0 / 3.0,
+        0x14 => 64.0 / 3.0,
+        f => f as f64
+    };
+    sign * ((v & !0x1f) as f64 + frac) / 32.0
+}
+
+#[test]
+fn test_flash() {
+    let f = Flash::from(0x19);
+    assert!(f.fired);
+    assert_eq!(FlashMode::Auto, f.mode);
+    assert!(!f.red_eye_reduction);
+    assert_eq!(FlashMode::Off, Flash::from(0x10).mode);
+}
+
+#[test]
+fn test_canon_ev() {
+    assert_eq!(0.0, canon_ev(0));
+    assert_eq!(1.0, canon_ev(32));
+    assert_eq!(-0.5, canon_ev(-16));
+    assert!((canon_ev(0x2c) - 4.0 / 3.0).abs() < 1e-9);
+    assert!((canon_ev(-0x14) + 2.0 / 3.0).abs() < 1e-9);
+}
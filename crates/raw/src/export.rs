@@ -0,0 +1,97 @@
+use cr2::{RawImage,RawFileError,FormatErrorKind};
+use ifd::writer::{self,ByteOrder,Ifd,Value};
+use jpeg;
+use zip::crc32;
+
+/// Tags of IFD0 copied to exported images: make, model, orientation,
+/// date and time, artist and copyright
+const IFD0_TAGS: &[u16] = &[0x10f, 0x110, 0x112, 0x132, 0x13b, 0x8298];
+
+/// EXIF tags not copied: the MakerNote, whose offsets would break, and
+/// the interoperability pointer
+const EXIF_SKIPPED: &[u16] = &[0x927c, 0xa005];
+
+/// Copies the tags of an IFD of the image, filtered by id
+fn copy(image: &RawImage, name: &str, keep: &dyn Fn(u16) -> bool) -> Ifd {
+    let mut ifd = Ifd::new();
+    if let Some(i) = image.ifds().into_iter().find(|i| i.0 == name).map(|i| i.2) {
+        for (tag, p) in &i.pos {
+            if let Some(v) = i.tags.get(tag).filter(|_| keep(p.id)).and_then(|d| Value::from_tag(p.tagtype, d)) {
+                ifd = ifd.tag(p.id, v);
+            }
+        }
+    }
+    ifd
+}
+
+/// IFD0 with EXIF and optionally GPS IFD describing an image developed
+/// from the raw file: camera, lens, capture settings, date and orientation
+///
+/// Exporters writing TIFF files can add their own tags to it.
+pub fn exif_ifd(image: &RawImage, gps: bool) -> Ifd {
+    let mut ifd0 = copy(image, "ifd0", &|id| IFD0_TAGS.contains(&id));
+    let exif = copy(image, "exif", &|id| !EXIF_SKIPPED.contains(&id));
+    if !exif.is_empty() {
+        ifd0 = ifd0.sub_ifd(0x8769, exif);
+    }
+    let gps = if gps { copy(image, "gps", &|_| true) } else { Ifd::new() };
+    if !gps.is_empty() {
+        ifd0 = ifd0.sub_ifd(0x8825, gps);
+    }
+    ifd0
+}
+
+/// EXIF data of exif_ifd as TIFF structure, as embedded in JPEG and PNG
+pub fn exif(image: &RawImage, gps: bool) -> Result<Vec<u8>,RawFileError> {
+    writer::to_tiff(&[exif_ifd(image, gps)], ByteOrder::Intel)
+}
+
+/// Adds the EXIF data of the raw file to an exported JPEG
+pub fn embed_jpeg(jpeg: &[u8], image: &RawImage, gps: bool) -> Result<Vec<u8>,RawFileError> {
+    jpeg::set_exif(jpeg, &try!(exif(image, gps)))
+}
+
+/// Adds the EXIF data of the raw file to an exported PNG as eXIf chunk
+/// after the header chunk
+pub fn embed_png(png: &[u8], image: &RawImage, gps: bool) -> Result<Vec<u8>,RawFileError> {
+    // signature and IHDR chunk of 13 bytes
+    const HEADER: usize = 8 + 8 + 13 + 4;
+    if png.len() < HEADER || &png[..8] != b"\x89PNG\r\n\x1a\n" || &png[12..16] != b"IHDR" {
+        return Err(RawFileError::format(FormatErrorKind::BadMagic, "Not a PNG"));
+    }
+    let tiff = try!(exif(image, gps));
+    let mut chunk = b"eXIf".to_vec();
+    chunk.extend_from_slice(&tiff);
+    let mut out = png[..HEADER].to_vec();
+    out.extend_from_slice(&(tiff.len() as u32).to_be_bytes());
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&crc32(&chunk).to_be_bytes());
+    out.extend_from_slice(&png[HEADER..]);
+    Ok(out)
+}
+
+#[test]
+fn test_exif() {
+    use cr2;
+    use synth::{Cr2Builder,Ifd as SynthIfd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(SynthIfd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::short(0x112, &[6])).tag(Tag::long(0x100, &[5472])))
+        .exif(SynthIfd::new().tag(Tag::short(0x8827, &[3200])).tag(Tag::srational(0x9204, &[(-2, 3)])))
+        .gps(SynthIfd::new().tag(Tag::ascii(0x0001, "N")))
+        .makernote(SynthIfd::new().tag(Tag::ascii(0x0095, "EF50mm f/1.8 STM")))
+        .build();
+    let image = cr2::open_bytes(f, "exif".to_string()).unwrap();
+    // EXIF data is a TIFF without CR2 header, the parser wants one
+    let mut tiff = b"II*\0\0\0\0\0CR\x02\0\0\0\0\0".to_vec();
+    let offset = exif_ifd(&image, false).write(&mut tiff, ByteOrder::Intel).unwrap();
+    tiff[4..8].copy_from_slice(&offset.to_le_bytes());
+    let copy = cr2::open_bytes(tiff, "copy".to_string()).unwrap();
+    assert_eq!((Some("Canon EOS 6D"), Some(6)), (copy.model(), copy.orientation()));
+    assert!(copy.ifds()[0].2.tags.get("width").is_none());
+    assert_eq!((Some(3200), Some(-2.0 / 3.0)), (copy.exif_summary().iso, copy.exif_summary().exposure_compensation));
+    assert!(copy.ifds().iter().all(|i| i.0 != "gps" && i.0 != "makernote"));
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\0\0\0\0\x3a\x7e\x9b\x55\0\0\0\0IEND\xae\x42\x60\x82";
+    let out = embed_png(png, &image, true).unwrap();
+    assert_eq!(b"eXIfII*\0", &out[37..45]);
+    assert!(out.ends_with(b"IEND\xae\x42\x60\x82"));
+}
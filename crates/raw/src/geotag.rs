@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use cr2::{RawImage,RawFileError,FormatErrorKind};
+use datetime;
+use xmp;
+
+/// Track points further apart than this (seconds) are not interpolated
+pub const MAX_GAP: i64 = 300;
+
+/// A point of a GPS track
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct TrackPoint {
+    /// UTC timestamp
+    pub time: i64,
+    pub lat: f64,
+    pub lon: f64,
+    /// Elevation in meters
+    pub ele: Option<f64>
+}
+
+/// Value of an attribute in a start tag
+fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    for q in &['"', '\''] {
+        let key = format!("{}={}", name, q);
+        if let Some(i) = tag.find(&key) {
+            let v = &tag[i + key.len()..];
+            return v.find(*q).map(|e| &v[..e]);
+        }
+    }
+    None
+}
+
+/// Text of the first child element with this name
+fn child<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = match element.find(&open) {
+        Some(s) => s + open.len(),
+        None => return None
+    };
+    element[start..].find(&format!("</{}>", name)).map(|e| element[start..start + e].trim())
+}
+
+/// Reads the track points of a GPX document, sorted by time
+///
+/// Points without a time stamp can not be matched and are skipped.
+pub fn parse_gpx(gpx: &str) -> Result<Vec<TrackPoint>,RawFileError> {
+    let mut track = Vec::new();
+    let mut rest = gpx;
+    while let Some(start) = rest.find("<trkpt") {
+        rest = &rest[start..];
+        let head = match rest.find('>') {
+            Some(h) => &rest[..h],
+            None => return Err(RawFileError::format(FormatErrorKind::Truncated, "Unterminated trkpt in GPX"))
+        };
+        // a self-closing point ends with its start tag
+        let end = if head.ends_with('/') { Some(head.len()) } else { rest.find("</trkpt>") };
+        let end = match end {
+            Some(e) => e,
+            None => return Err(RawFileError::format(FormatErrorKind::Truncated, "Unterminated trkpt in GPX"))
+        };
+        let element = &rest[..end];
+        rest = &rest[end..];
+        let coord = |name| attr(head, name).and_then(|v| v.trim().parse::<f64>().ok());
+        let (lat, lon) = match (coord("lat"), coord("lon")) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Err(RawFileError::format(FormatErrorKind::Missing, "trkpt without lat/lon in GPX"))
+        };
+        if let Some(time) = child(element, "time").and_then(datetime::parse_iso8601) {
+            track.push(TrackPoint {
+                time: time,
+                lat: lat,
+                lon: lon,
+                ele: child(element, "ele").and_then(|e| e.parse().ok())
+            });
+        }
+    }
+    track.sort_by_key(|p| p.time);
+    Ok(track)
+}
+
+/// Reads a GPX file
+pub fn read_gpx(path: &Path) -> Result<Vec<TrackPoint>,RawFileError> {
+    let mut gpx = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut gpx));
+    parse_gpx(&gpx)
+}
+
+/// Position at a UTC time, interpolated between the neighbouring points
+pub fn locate(track: &[TrackPoint], time: i64) -> Option<TrackPoint> {
+    let i = match track.binary_search_by_key(&time, |p| p.time) {
+        Ok(i) => return Some(track[i]),
+        Err(i) => i
+    };
+    let near = |p: &TrackPoint| (p.time - time).abs() <= MAX_GAP;
+    match (track.get(i.wrapping_sub(1)), track.get(i)) {
+        (Some(a), Some(b)) if b.time - a.time <= MAX_GAP => {
+            let f = (time - a.time) as f64 / (b.time - a.time) as f64;
+            Some(TrackPoint {
+                time: time,
+                lat: a.lat + (b.lat - a.lat) * f,
+                lon: a.lon + (b.lon - a.lon) * f,
+                ele: match (a.ele, b.ele) {
+                    (Some(x), Some(y)) => Some(x + (y - x) * f),
+                    (e, None) | (None, e) => e
+                }
+            })
+        },
+        (Some(a), Some(b)) => {
+            let p = if time - a.time < b.time - time { a } else { b };
+            if near(p) { Some(*p) } else { None }
+        },
+        (Some(p), None) | (None, Some(p)) if near(p) => Some(*p),
+        _ => None
+    }
+}
+
+/// Formats a coordinate as XMP GPS coordinate "DDD,MM.mmmmmmK"
+fn xmp_coordinate(v: f64, pos: char, neg: char) -> String {
+    let a = v.abs();
+    format!("{},{:.6}{}", a.trunc(), a.fract() * 60.0, if v < 0.0 { neg } else { pos })
+}
+
+/// Matches the capture time of an image with a track and writes the
+/// position to the XMP sidecar of the image
+///
+/// utc_offset is the offset of the camera clock to UTC in seconds, e.g.
+/// 7200 for a camera set to CEST. Returns the position, or None if the
+/// image was not taken during the track.
+pub fn geotag(image: &RawImage, track: &[TrackPoint], utc_offset: i64) -> Result<Option<TrackPoint>,RawFileError> {
+    let taken = match image.exif_summary().date_time_original.as_ref().and_then(|d| datetime::parse_exif(d)) {
+        Some(t) => t - utc_offset,
+        None => return Err(RawFileError::format(FormatErrorKind::Missing, "No capture time"))
+    };
+    let p = match locate(track, taken) {
+        Some(p) => p,
+        None => return Ok(None)
+    };
+    let mut props = vec![
+        ("exif:GPSVersionID", "2.2.0.0".to_string()),
+        ("exif:GPSLatitude", xmp_coordinate(p.lat, 'N', 'S')),
+        ("exif:GPSLongitude", xmp_coordinate(p.lon, 'E', 'W')),
+        ("exif:GPSTimeStamp", datetime::format_iso8601(taken, 0))
+    ];
+    if let Some(e) = p.ele {
+        props.push(("exif:GPSAltitude", format!("{}/10", (e.abs() * 10.0).round())));
+        props.push(("exif:GPSAltitudeRef", if e < 0.0 { "1" } else { "0" }.to_string()));
+    }
+    try!(xmp::update(&xmp::sidecar_path(image.file_name()), &props));
+    Ok(Some(p))
+}
+
+#[test]
+fn test_parse_gpx() {
+    let gpx = "<gpx><trk><trkseg>
+        <trkpt lat=\"48.5\" lon=\"-11.25\"><ele>500</ele><time>2016-05-25T10:01:00Z</time></trkpt>
+        <trkpt lon='-11.0' lat='48.0'><time>2016-05-25T10:00:00Z</time></trkpt>
+        <trkpt lat=\"1\" lon=\"1\"/>
+        </trkseg></trk></gpx>";
+    let t = parse_gpx(gpx).unwrap();
+    assert_eq!(2, t.len());
+    assert_eq!(48.0, t[0].lat);
+    assert_eq!(Some(500.0), t[1].ele);
+    // a point without time before a timed one is skipped on its own
+    let gpx = "<trkpt lat=\"1\" lon=\"1\"/><trkpt lat=\"2\" lon=\"3\"><time>2016-05-25T10:00:00Z</time></trkpt>";
+    let t = parse_gpx(gpx).unwrap();
+    assert_eq!(1, t.len());
+    assert_eq!((2.0, 3.0), (t[0].lat, t[0].lon));
+    assert!(parse_gpx("<trkpt lat=\"1\"><time>2016-05-25T10:00:00Z</time></trkpt>").is_err());
+}
+
+#[test]
+fn test_locate() {
+    let p = |time, lat| TrackPoint { time: time, lat: lat, lon: 0.0, ele: None };
+    let track = [p(0, 10.0), p(100, 20.0), p(1000, 30.0)];
+    assert_eq!(15.0, locate(&track, 50).unwrap().lat);
+    assert_eq!(20.0, locate(&track, 300).unwrap().lat);
+    assert!(locate(&track, 550).is_none());
+    assert_eq!(30.0, locate(&track, 1200).unwrap().lat);
+    assert!(locate(&track, -400).is_none());
+    assert_eq!("11,15.000000W", xmp_coordinate(-11.25, 'E', 'W'));
+}
@@ -0,0 +1,50 @@
+// Regression tests against checked-in reference output
+//
+// Every file in tests/golden is opened and summarized, the summary is
+// compared to the .golden file next to it. Run the tests with
+// RRAW_BLESS=1 set to write the summaries of new or changed files.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use cr2;
+use hash;
+
+/// Hash of the raw data and all tags, or the error opening the file
+fn summary(path: &Path) -> String {
+    let image = match cr2::open(path.to_string_lossy().into_owned()) {
+        Ok(i) => i,
+        Err(e) => return format!("error {}\n", e)
+    };
+    let mut s = match image.content_hash() {
+        Ok(h) => format!("content_hash {}\n", hash::to_hex(&h)),
+        Err(e) => format!("content_hash error {}\n", e)
+    };
+    for (ifd, tag, values) in image.tag_list() {
+        s.push_str(&format!("{} {} {}\n", ifd, tag, values));
+    }
+    s
+}
+
+#[test]
+fn test_golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let bless = env::var_os("RRAW_BLESS").is_some();
+    let mut files: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path())
+        .filter(|p| p.extension().map(|e| e != "golden").unwrap_or(false)).collect();
+    files.sort();
+    let mut failed = Vec::new();
+    for f in files {
+        let golden = f.with_extension("golden");
+        let s = summary(&f);
+        if bless {
+            fs::write(&golden, &s).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        if let Some((e, a)) = expected.lines().chain(Some("<end>")).zip(s.lines().chain(Some("<end>"))).find(|l| l.0 != l.1) {
+            failed.push(format!("{}:\n  expected {}\n  got      {}", f.display(), e, a));
+        }
+    }
+    assert!(failed.is_empty(), "golden files differ, run with RRAW_BLESS=1 if the change is intended\n{}", failed.join("\n"));
+}
@@ -0,0 +1,222 @@
+use cr2::{self,RawImage,RawFileError};
+use exif::{DriveMode,ExposureMode};
+
+/// Shots further apart than this (seconds) never belong to the same group
+pub const MAX_GAP: i64 = 10;
+
+/// Shots of a burst are at most this far apart (seconds)
+pub const BURST_GAP: i64 = 1;
+
+/// Kind of a group of shots
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum GroupKind {
+    /// Different exposures of the same scene, for HDR
+    Bracket,
+    /// Continuous drive
+    Burst,
+    /// Same exposure taken in quick succession, e.g. panorama or focus stack
+    Sequence
+}
+
+/// Indices of the files of a group, in capture order
+#[derive(Debug,Clone,PartialEq)]
+pub struct Group {
+    pub kind: GroupKind,
+    pub members: Vec<usize>
+}
+
+/// The metadata used for grouping
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Shot {
+    /// Capture time, see RawImage::capture_time
+    pub time: i64,
+    /// Exposure compensation in EV
+    pub exposure_compensation: f64,
+    pub exposure_mode: Option<ExposureMode>,
+    pub drive_mode: Option<DriveMode>
+}
+
+impl Shot {
+    /// Shot of an image, None if the image has no capture time
+    pub fn from_image(image: &RawImage) -> Option<Shot> {
+        let e = image.exif_summary();
+        image.capture_time().map(|t| Shot {
+            time: t,
+            exposure_compensation: e.exposure_compensation.unwrap_or(0.0),
+            exposure_mode: e.exposure_mode,
+            drive_mode: e.drive_mode
+        })
+    }
+
+    fn continuous(&self) -> bool {
+        match self.drive_mode {
+            Some(DriveMode::Continuous) | Some(DriveMode::ContinuousLow) |
+            Some(DriveMode::ContinuousHigh) => true,
+            _ => false
+        }
+    }
+}
+
+/// Classifies a run of shots taken shortly after each other
+fn kind(run: &[&(usize, Shot)]) -> GroupKind {
+    let first = run[0].1;
+    if run.iter().any(|s| s.1.exposure_mode == Some(ExposureMode::AutoBracket) ||
+                          s.1.exposure_compensation != first.exposure_compensation) {
+        GroupKind::Bracket
+    } else if run.iter().all(|s| s.1.continuous()) &&
+              run.windows(2).all(|w| w[1].1.time - w[0].1.time <= BURST_GAP) {
+        GroupKind::Burst
+    } else {
+        GroupKind::Sequence
+    }
+}
+
+/// Groups shots into brackets, bursts and sequences
+///
+/// Shots not taken within MAX_GAP of another shot are not part of any group.
+pub fn group(shots: &[Shot]) -> Vec<Group> {
+    let mut sorted: Vec<(usize, Shot)> = shots.iter().cloned().enumerate().collect();
+    sorted.sort_by_key(|s| s.1.time);
+    let mut groups = Vec::new();
+    let mut run: Vec<&(usize, Shot)> = Vec::new();
+    for s in &sorted {
+        if let Some(last) = run.last().cloned() {
+            if s.1.time - last.1.time > MAX_GAP {
+                if run.len() > 1 {
+                    groups.push(Group { kind: kind(&run), members: run.iter().map(|s| s.0).collect() });
+                }
+                run.clear();
+            }
+        }
+        run.push(s);
+    }
+    if run.len() > 1 {
+        groups.push(Group { kind: kind(&run), members: run.iter().map(|s| s.0).collect() });
+    }
+    groups
+}
+
+/// Time and attitude of the camera at a shot
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Pose {
+    /// Capture time, see RawImage::capture_time
+    pub time: i64,
+    /// Roll and pitch in degrees from the electronic level
+    pub roll: Option<f64>,
+    pub pitch: Option<f64>,
+    /// EXIF orientation
+    pub orientation: Option<u16>
+}
+
+impl Pose {
+    /// Pose of an image, None if the image has no capture time
+    pub fn from_image(image: &RawImage) -> Option<Pose> {
+        let level = image.level();
+        image.capture_time().map(|t| Pose {
+            time: t,
+            roll: level.map(|l| l.roll),
+            pitch: level.map(|l| l.pitch),
+            orientation: image.orientation()
+        })
+    }
+
+    /// Clockwise rotation in degrees, from the level or else from the
+    /// orientation in steps of 90 degrees
+    fn rotation(&self, level: bool) -> f64 {
+        match (level, self.roll, self.orientation) {
+            (true, Some(r), _) => r,
+            (_, _, Some(6)) => 90.0,
+            (_, _, Some(3)) => 180.0,
+            (_, _, Some(8)) => 270.0,
+            _ => 0.0
+        }
+    }
+}
+
+/// Position of a shot in a stack or panorama
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Placement {
+    /// Index of the shot in the input
+    pub index: usize,
+    /// Rotation relative to the first shot in degrees, clockwise
+    pub rotation: f64,
+    /// Tilt relative to the first shot in degrees, if known for both
+    pub pitch: Option<f64>
+}
+
+/// Orders the shots of a stack by capture time and estimates their
+/// rotation relative to the first shot, to prime stitching tools
+///
+/// The level is used if all shots have one, otherwise the orientation.
+pub fn pre_align(poses: &[Pose]) -> Vec<Placement> {
+    let mut order: Vec<usize> = (0..poses.len()).collect();
+    order.sort_by_key(|&i| poses[i].time);
+    let level = poses.iter().all(|p| p.roll.is_some());
+    let first = match order.first() {
+        Some(&i) => poses[i],
+        None => return Vec::new()
+    };
+    order.into_iter().map(|i| {
+        let p = poses[i];
+        let r = (p.rotation(level) - first.rotation(level) + 540.0) % 360.0 - 180.0;
+        Placement {
+            index: i,
+            rotation: r,
+            pitch: match (p.pitch, first.pitch) { (Some(a), Some(b)) => Some(a - b), _ => None }
+        }
+    }).collect()
+}
+
+/// Opens the files and groups them, files without capture time are
+/// not part of any group
+pub fn group_files(paths: &[&str]) -> Result<Vec<Group>,RawFileError> {
+    let mut shots = Vec::new();
+    let mut index = Vec::new();
+    for (i, p) in paths.iter().enumerate() {
+        if let Some(s) = Shot::from_image(&try!(cr2::open(p.to_string()))) {
+            shots.push(s);
+            index.push(i);
+        }
+    }
+    let mut groups = group(&shots);
+    for g in &mut groups {
+        for m in &mut g.members {
+            *m = index[*m];
+        }
+    }
+    Ok(groups)
+}
+
+#[test]
+fn test_group() {
+    let shot = |time, ev, drive| Shot { time: time, exposure_compensation: ev,
+                                         exposure_mode: None, drive_mode: Some(drive) };
+    let shots = [
+        shot(100, 0.0, DriveMode::Single),
+        shot(0, 0.0, DriveMode::Single),
+        shot(1, -2.0, DriveMode::Single),
+        shot(2, 2.0, DriveMode::Single),
+        shot(50, 0.0, DriveMode::ContinuousHigh),
+        shot(50, 0.0, DriveMode::ContinuousHigh),
+        shot(51, 0.0, DriveMode::ContinuousHigh),
+        shot(200, 0.0, DriveMode::Single),
+        shot(205, 0.0, DriveMode::Single),
+        shot(212, 0.0, DriveMode::Single),
+    ];
+    let g = group(&shots);
+    assert_eq!(3, g.len());
+    assert_eq!(Group { kind: GroupKind::Bracket, members: vec![1, 2, 3] }, g[0]);
+    assert_eq!(Group { kind: GroupKind::Burst, members: vec![4, 5, 6] }, g[1]);
+    assert_eq!(Group { kind: GroupKind::Sequence, members: vec![7, 8, 9] }, g[2]);
+}
+
+#[test]
+fn test_pre_align() {
+    let pose = |time, roll, orientation| Pose { time: time, roll: roll, pitch: roll.map(|_| 1.0), orientation: Some(orientation) };
+    let p = pre_align(&[pose(2, Some(-178.0), 1), pose(1, Some(179.0), 1), pose(3, Some(-170.0), 1)]);
+    assert_eq!(vec![1, 0, 2], p.iter().map(|p| p.index).collect::<Vec<_>>());
+    assert!((p[1].rotation - 3.0).abs() < 1e-9 && (p[2].rotation - 11.0).abs() < 1e-9);
+    assert_eq!(Some(0.0), p[1].pitch);
+    let p = pre_align(&[pose(1, Some(0.0), 1), pose(2, None, 6)]);
+    assert_eq!((90.0, None), (p[1].rotation, p[1].pitch));
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use cr2::{self,RawFileError};
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+/// SHA-256 of a byte stream
+pub struct Sha256 {
+    state: [u32; 8],
+    block: Vec<u8>,
+    len: u64
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256 {
+            state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            block: Vec::with_capacity(64),
+            len: 0
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = ::std::cmp::min(64 - self.block.len(), data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == 64 {
+                self.compress();
+                self.block.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.block.push(0x80);
+        if self.block.len() > 56 {
+            self.block.resize(64, 0);
+            self.compress();
+            self.block.clear();
+        }
+        self.block.resize(56, 0);
+        self.block.extend_from_slice(&bits.to_be_bytes());
+        self.compress();
+        let mut out = [0u8; 32];
+        for (i, s) in self.state.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&s.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([self.block[i * 4], self.block[i * 4 + 1],
+                                       self.block[i * 4 + 2], self.block[i * 4 + 3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let mut h = self.state;
+        for i in 0..64 {
+            let s1 = h[4].rotate_right(6) ^ h[4].rotate_right(11) ^ h[4].rotate_right(25);
+            let ch = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let t1 = h[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = h[0].rotate_right(2) ^ h[0].rotate_right(13) ^ h[0].rotate_right(22);
+            let maj = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            let t2 = s0.wrapping_add(maj);
+            h = [t1.wrapping_add(t2), h[0], h[1], h[2], h[3].wrapping_add(t1), h[4], h[5], h[6]];
+        }
+        for i in 0..8 {
+            self.state[i] = self.state[i].wrapping_add(h[i]);
+        }
+    }
+}
+
+const P1: u64 = 0x9e3779b185ebca87;
+const P2: u64 = 0xc2b2ae3d27d4eb4f;
+const P3: u64 = 0x165667b19e3779f9;
+const P4: u64 = 0x85ebca77c2b2ae63;
+const P5: u64 = 0x27d4eb2f165667c5;
+
+fn xxh_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(P2)).rotate_left(31).wrapping_mul(P1)
+}
+
+fn xxh_merge(acc: u64, v: u64) -> u64 {
+    (acc ^ xxh_round(0, v)).wrapping_mul(P1).wrapping_add(P4)
+}
+
+fn u64_le(b: &[u8]) -> u64 {
+    let mut v = [0u8; 8];
+    v.copy_from_slice(&b[..8]);
+    u64::from_le_bytes(v)
+}
+
+/// XXH64 of a byte stream with seed 0, much faster than SHA-256 but not
+/// cryptographic
+pub struct Xxh64 {
+    acc: [u64; 4],
+    block: Vec<u8>,
+    len: u64
+}
+
+impl Default for Xxh64 {
+    fn default() -> Xxh64 {
+        Xxh64 {
+            acc: [P1.wrapping_add(P2), P2, 0, 0u64.wrapping_sub(P1)],
+            block: Vec::with_capacity(32),
+            len: 0
+        }
+    }
+}
+
+impl Xxh64 {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = ::std::cmp::min(32 - self.block.len(), data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == 32 {
+                for i in 0..4 {
+                    self.acc[i] = xxh_round(self.acc[i], u64_le(&self.block[i * 8..]));
+                }
+                self.block.clear();
+            }
+        }
+    }
+
+    pub fn finish(self) -> [u8; 8] {
+        let a = self.acc;
+        let mut h = if self.len >= 32 {
+            let h = a[0].rotate_left(1).wrapping_add(a[1].rotate_left(7))
+                .wrapping_add(a[2].rotate_left(12)).wrapping_add(a[3].rotate_left(18));
+            a.iter().fold(h, |h, &v| xxh_merge(h, v))
+        } else {
+            P5
+        };
+        h = h.wrapping_add(self.len);
+        let mut rest = &self.block[..];
+        while rest.len() >= 8 {
+            h = (h ^ xxh_round(0, u64_le(rest))).rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let k = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64;
+            h = (h ^ k.wrapping_mul(P1)).rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h = (h ^ (b as u64).wrapping_mul(P5)).rotate_left(11).wrapping_mul(P1);
+        }
+        h ^= h >> 33;
+        h = h.wrapping_mul(P2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(P3);
+        h ^= h >> 32;
+        h.to_be_bytes()
+    }
+}
+
+/// Hash algorithms for checksums
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Xxh64
+}
+
+/// A running hash of either algorithm
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Xxh64(Xxh64)
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::default()),
+            Algorithm::Xxh64 => Hasher::Xxh64(Xxh64::default())
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Sha256(ref mut h) => h.update(data),
+            Hasher::Xxh64(ref mut h) => h.update(data)
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finish().to_vec(),
+            Hasher::Xxh64(h) => h.finish().to_vec()
+        }
+    }
+}
+
+/// Lower case hex representation of a hash
+pub fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Groups files with the same raw data, returns the indices of all groups
+/// with more than one file
+pub fn duplicates(paths: &[&str]) -> Result<Vec<Vec<usize>>,RawFileError> {
+    let mut groups: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+    for (i, p) in paths.iter().enumerate() {
+        let image = try!(cr2::open(p.to_string()));
+        groups.entry(try!(image.content_hash())).or_insert_with(Vec::new).push(i);
+    }
+    let mut d: Vec<Vec<usize>> = groups.into_iter().map(|g| g.1).filter(|g| g.len() > 1).collect();
+    d.sort();
+    Ok(d)
+}
+
+#[test]
+fn test_sha256() {
+    let hash = |s: &str| { let mut h = Sha256::default(); h.update(s.as_bytes()); to_hex(&h.finish()) };
+    assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", hash(""));
+    assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", hash("abc"));
+    assert_eq!("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+               hash("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"));
+    let mut h = Sha256::default();
+    for _ in 0..1000 { h.update(&[b'a'; 1000]); }
+    assert_eq!("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0", to_hex(&h.finish()));
+}
+
+#[test]
+fn test_xxh64() {
+    let hash = |data: &[u8]| {
+        let mut h = Xxh64::default();
+        h.update(data);
+        to_hex(&h.finish())
+    };
+    assert_eq!("ef46db3751d8e999", hash(b""));
+    assert_eq!("44bc2cf5ad770999", hash(b"abc"));
+    assert_eq!("fbcea83c8a378bf1", hash(b"Nobody inspects the spammish repetition"));
+    // streaming gives the same result as a single update
+    let data: Vec<u8> = (0..100u8).collect();
+    let mut h = Xxh64::default();
+    h.update(&data[..7]);
+    h.update(&data[7..]);
+    assert_eq!(hash(&data), to_hex(&h.finish()));
+}
+
+#[test]
+fn test_duplicates() {
+    use std::fs;
+    use synth::{Cr2Builder,Ifd,Tag};
+    let build = |artist: &str, preview: &[u8], raw: &[u8]| Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x13b, artist)).strip(preview))
+        .ifd(Ifd::new().tag(Tag::short(0x103, &[6])).strip(raw))
+        .raw_ifd(1)
+        .build();
+    let dir = ::std::env::temp_dir().join(format!("rraw-duplicates-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // copies with other metadata and preview, the raw data moves
+    let files = [build("A", &[0xff, 0xd8], &[1, 2, 3]),
+                 build("Jane Doe", &[0xff, 0xd8, 0xff, 0xd9], &[1, 2, 3]),
+                 build("A", &[0xff, 0xd8], &[1, 2, 4])];
+    let mut paths = Vec::new();
+    for (i, f) in files.iter().enumerate() {
+        let p = dir.join(format!("{}.cr2", i));
+        fs::write(&p, f).unwrap();
+        paths.push(p.to_str().unwrap().to_string());
+    }
+    let hash = |p: &str| cr2::open(p.to_string()).unwrap().content_hash().unwrap();
+    let raw = |p: &str| cr2::open(p.to_string()).unwrap().raw_segments();
+    assert_ne!(raw(&paths[0]), raw(&paths[1]));
+    assert_eq!(hash(&paths[0]), hash(&paths[1]));
+    assert_ne!(hash(&paths[0]), hash(&paths[2]));
+    let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+    assert_eq!(vec![vec![0, 1]], duplicates(&paths).unwrap());
+    assert!(duplicates(&paths[1..]).unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::{self,Read,Write};
+use std::net::{TcpStream,ToSocketAddrs};
+use std::str;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+use cr2::{self,RawImage,RawFileError};
+use source::RawSource;
+
+/// Size of the ranges requested from the server
+const BLOCK: u64 = 64 * 1024;
+
+/// Limit for connecting and for each read and write, a stalled server
+/// fails the read instead of blocking it
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response accepted, servers ignoring ranges send the whole
+/// file, raw files stay well below this
+const MAX_RESPONSE: u64 = 256 << 20;
+
+/// A file on an HTTP server
+///
+/// The file is fetched in blocks with range requests, so reading the
+/// metadata of a raw file transfers only the blocks holding the header
+/// and the IFDs. Fetched blocks are kept and shared by all clones.
+/// Only plain http URLs are supported.
+#[derive(Clone)]
+pub struct HttpReader {
+    /// host:port
+    host: String,
+    path: String,
+    len: u64,
+    blocks: Arc<Mutex<HashMap<u64,Vec<u8>>>>
+}
+
+fn invalid(s: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, s)
+}
+
+/// Splits an http URL into host:port and path
+fn parse_url(url: &str) -> Option<(String,String)> {
+    let rest = match url.get(..7) {
+        Some(s) if s.eq_ignore_ascii_case("http://") => &url[7..],
+        _ => return None
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/")
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    Some((host, path.to_string()))
+}
+
+/// Connects to host:port, trying each of its addresses
+fn connect(host: &str) -> io::Result<TcpStream> {
+    let mut err = io::Error::new(io::ErrorKind::NotFound, format!("No address for {}", host));
+    for addr in try!(host.to_socket_addrs()) {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(s) => {
+                try!(s.set_read_timeout(Some(TIMEOUT)));
+                try!(s.set_write_timeout(Some(TIMEOUT)));
+                return Ok(s);
+            },
+            Err(e) => err = e
+        }
+    }
+    Err(err)
+}
+
+/// Reads a response of at most limit bytes
+fn read_response<R: Read>(r: R, limit: u64) -> io::Result<Vec<u8>> {
+    let mut response = Vec::new();
+    try!(r.take(limit + 1).read_to_end(&mut response));
+    if response.len() as u64 > limit {
+        return Err(invalid(format!("HTTP response exceeds {} bytes", limit)));
+    }
+    Ok(response)
+}
+
+/// Decodes a body sent with chunked transfer encoding
+fn dechunk(mut body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line = match body.windows(2).position(|w| w == b"\r\n") {
+            Some(i) => i,
+            None => return Err(invalid("Bad chunk".to_string()))
+        };
+        let size = str::from_utf8(&body[..line]).ok()
+            .and_then(|s| usize::from_str_radix(s.split(';').next().unwrap_or("").trim(), 16).ok());
+        let size = match size {
+            Some(s) => s,
+            None => return Err(invalid("Bad chunk size".to_string()))
+        };
+        if size == 0 {
+            return Ok(out);
+        }
+        match body.get(line + 2..line + 2 + size) {
+            Some(c) => out.extend_from_slice(c),
+            None => return Err(invalid("Chunk ends early".to_string()))
+        }
+        body = &body[(line + 4 + size).min(body.len())..];
+    }
+}
+
+/// Parses a response, returns the status, the total length of the file
+/// if the response is partial, and the body
+fn parse_response(response: &[u8]) -> io::Result<(u16, Option<u64>, Vec<u8>)> {
+    let end = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(e) => e,
+        None => return Err(invalid("Incomplete HTTP response".to_string()))
+    };
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next().and_then(|l| l.split_whitespace().nth(1)).and_then(|s| s.parse().ok());
+    let status = match status {
+        Some(s) => s,
+        None => return Err(invalid("Bad HTTP status line".to_string()))
+    };
+    let (mut total, mut chunked) = (None, false);
+    for l in lines {
+        let mut kv = l.splitn(2, ':');
+        let (k, v) = (kv.next().unwrap_or("").trim(), kv.next().unwrap_or("").trim());
+        if k.eq_ignore_ascii_case("content-range") {
+            // bytes 0-65535/41000000
+            total = v.rsplit('/').next().and_then(|t| t.parse().ok());
+        } else if k.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = v.eq_ignore_ascii_case("chunked");
+        }
+    }
+    let body = &response[end + 4..];
+    let body = if chunked { try!(dechunk(body)) } else { body.to_vec() };
+    Ok((status, total, body))
+}
+
+impl HttpReader {
+    /// Opens a file on a server, the first block is fetched to learn its length
+    pub fn open(url: &str) -> io::Result<HttpReader> {
+        let (host, path) = match parse_url(url) {
+            Some(u) => u,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Not an http URL: {}", url)))
+        };
+        let mut r = HttpReader { host: host, path: path, len: 0,
+                                 blocks: Arc::new(Mutex::new(HashMap::new())) };
+        r.len = try!(r.fetch(0));
+        Ok(r)
+    }
+
+    /// Requests a block and stores it, returns the length of the file
+    fn fetch(&self, block: u64) -> io::Result<u64> {
+        let start = block * BLOCK;
+        let mut s = try!(connect(&self.host));
+        try!(write!(s, "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: close\r\n\r\n",
+                    self.path, self.host, start, start + BLOCK - 1));
+        let response = try!(read_response(&mut s, MAX_RESPONSE));
+        let (status, total, body) = try!(parse_response(&response));
+        let mut blocks = self.blocks.lock().unwrap();
+        match status {
+            206 => {
+                blocks.insert(block, body);
+                match total {
+                    Some(t) => Ok(t),
+                    None => Err(invalid("Partial response without length".to_string()))
+                }
+            },
+            // the server ignores ranges and sends the whole file
+            200 => {
+                for (i, b) in body.chunks(BLOCK as usize).enumerate() {
+                    blocks.insert(i as u64, b.to_vec());
+                }
+                Ok(body.len() as u64)
+            },
+            // range starts at the end of the file
+            416 if block == 0 => Ok(0),
+            s => Err(io::Error::new(io::ErrorKind::Other, format!("HTTP status {}", s)))
+        }
+    }
+}
+
+impl RawSource for HttpReader {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() && offset + (n as u64) < self.len {
+            let pos = offset + n as u64;
+            let block = pos / BLOCK;
+            if !self.blocks.lock().unwrap().contains_key(&block) {
+                try!(self.fetch(block));
+            }
+            let blocks = self.blocks.lock().unwrap();
+            let data = match blocks.get(&block).and_then(|d| d.get((pos - block * BLOCK) as usize..)) {
+                Some(d) if !d.is_empty() => d,
+                _ => return Err(invalid(format!("Block {} missing in response", block)))
+            };
+            let m = ::std::cmp::min(buf.len() - n, data.len());
+            buf[n..n + m].copy_from_slice(&data[..m]);
+            n += m;
+        }
+        Ok(n)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+/// Opens a raw file on an HTTP server
+///
+/// Only the parts of the file that are used are transferred, reading the
+/// metadata and the previews does not download the raw data.
+pub fn open_url(url: &str) -> Result<RawImage,RawFileError> {
+    let r = try!(HttpReader::open(url));
+    cr2::open_source(Arc::new(r), url.to_string())
+}
+
+#[test]
+fn test_parse_response() {
+    assert_eq!(Some(("example.com:80".to_string(), "/a/b.cr2".to_string())), parse_url("http://example.com/a/b.cr2"));
+    assert_eq!(Some(("host:8080".to_string(), "/".to_string())), parse_url("HTTP://host:8080"));
+    assert_eq!(None, parse_url("https://example.com/a.cr2"));
+    let r = parse_response(b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/1000\r\n\r\nII*\0").unwrap();
+    assert_eq!((206, Some(1000), b"II*\0".to_vec()), r);
+    let r = parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nII\r\n2;x\r\n*\0\r\n0\r\n\r\n").unwrap();
+    assert_eq!((200, None, b"II*\0".to_vec()), r);
+}
+
+#[test]
+fn test_exchange() {
+    use std::net::TcpListener;
+    use synth::{Cr2Builder,Ifd,Tag};
+    let mut preview = vec![0xff, 0xd8];
+    preview.resize(200000, 7);
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&preview))
+        .build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let url = format!("http://{}/a.cr2", addr);
+    let file = f.clone();
+    let server = ::std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for s in listener.incoming() {
+            let mut s = s.unwrap();
+            let mut request = Vec::new();
+            let mut b = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") && s.read(&mut b).unwrap() == 1 {
+                request.push(b[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            if request.starts_with("QUIT") {
+                return requests;
+            }
+            let range = request.lines().find(|l| l.starts_with("Range: bytes=")).unwrap()[13..].to_string();
+            let mut r = range.split('-').map(|n| n.parse::<usize>().unwrap());
+            let start = r.next().unwrap();
+            let end = ::std::cmp::min(r.next().unwrap() + 1, file.len());
+            write!(s, "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                   start, end - 1, file.len(), end - start).unwrap();
+            s.write_all(&file[start..end]).unwrap();
+            requests.push(range);
+        }
+        requests
+    });
+    let image = open_url(&url).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(preview, image.preview().unwrap());
+    let mut quit = TcpStream::connect(addr).unwrap();
+    quit.write_all(b"QUIT\r\n\r\n").unwrap();
+    // each block is requested once
+    let requests = server.join().unwrap();
+    let blocks = (f.len() as u64 + BLOCK - 1) / BLOCK;
+    assert_eq!(blocks as usize, requests.len());
+    assert_eq!(format!("0-{}", BLOCK - 1), requests[0]);
+    // responses beyond the limit are rejected
+    assert!(read_response(&[0u8; 100][..], 99).is_err());
+    assert_eq!(100, read_response(&[0u8; 100][..], 100).unwrap().len());
+}
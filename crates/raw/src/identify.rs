@@ -0,0 +1,160 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self,Read};
+use std::path::Path;
+use decoder;
+
+/// Number of bytes identify reads
+pub(crate) const HEAD: u64 = 512;
+
+/// File formats told apart by identify
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Format {
+    Cr2,
+    Cr3,
+    Crw,
+    Nef,
+    Arw,
+    Dng,
+    Orf,
+    Rw2,
+    Raf,
+    Pef,
+    /// Other TIFF based files
+    Tiff,
+    Jpeg,
+    Unknown
+}
+
+/// Format, vendor and format version of a file
+#[derive(Debug,Clone,PartialEq)]
+pub struct FileKind {
+    pub format: Format,
+    /// Make as written by the camera, or implied by the format
+    pub vendor: Option<String>,
+    /// e.g. "2.0" for CR2, "1.4.0.0" for DNG
+    pub version: Option<String>
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", format!("{:?}", self.format).to_uppercase()));
+        if let Some(ref v) = self.version {
+            try!(write!(f, " {}", v));
+        }
+        if let Some(ref v) = self.vendor {
+            try!(write!(f, " ({})", v));
+        }
+        Ok(())
+    }
+}
+
+/// Little helper for reading numbers in either byte order
+struct Head<'a> {
+    data: &'a [u8],
+    intel: bool
+}
+
+impl<'a> Head<'a> {
+    fn u16(&self, i: usize) -> Option<u16> {
+        self.data.get(i..i + 2).map(|b| if self.intel { u16::from_le_bytes([b[0], b[1]]) }
+                                         else { u16::from_be_bytes([b[0], b[1]]) })
+    }
+
+    fn u32(&self, i: usize) -> Option<u32> {
+        self.data.get(i..i + 4).map(|b| if self.intel { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) }
+                                         else { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) })
+    }
+
+    /// Value field of a tag in IFD0, as far as it is within the head
+    fn tag(&self, id: u16) -> Option<(u16, u32, &'a [u8])> {
+        let ifd = self.u32(4)? as usize;
+        let n = self.u16(ifd)? as usize;
+        for e in 0..n {
+            let entry = ifd + 2 + e * 12;
+            if self.u16(entry)? != id {
+                continue;
+            }
+            let (tagtype, count) = (self.u16(entry + 2)?, self.u32(entry + 4)?);
+            let size = match tagtype { 1 | 2 | 7 => 1, 3 => 2, _ => 4 } * count as usize;
+            let start = if size <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+            return self.data.get(start..start.checked_add(size)?).map(|v| (tagtype, count, v));
+        }
+        None
+    }
+}
+
+/// Identifies a file from its first bytes, see identify
+///
+/// Decoders registered with decoder::register are asked first.
+pub fn identify_bytes(head: &[u8]) -> FileKind {
+    if let Some((_, kind)) = decoder::sniff(head) {
+        return kind;
+    }
+    let kind = |format, vendor: Option<&str>, version: Option<String>| FileKind {
+        format: format, vendor: vendor.map(String::from), version: version
+    };
+    if head.starts_with(b"FUJIFILMCCD-RAW ") {
+        return kind(Format::Raf, Some("FUJIFILM"), head.get(60..64).map(|v| String::from_utf8_lossy(v).into_owned()));
+    }
+    if head.get(4..8) == Some(b"ftyp") && head.get(8..12) == Some(b"crx ") {
+        return kind(Format::Cr3, Some("Canon"), None);
+    }
+    if head.starts_with(&[0xff, 0xd8, 0xff]) {
+        return kind(Format::Jpeg, None, None);
+    }
+    let intel = match head.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return kind(Format::Unknown, None, None)
+    };
+    if head.get(6..14) == Some(b"HEAPCCDR") {
+        return kind(Format::Crw, Some("Canon"), None);
+    }
+    let h = Head { data: head, intel: intel };
+    let make = h.tag(0x10f).map(|(_, _, v)| String::from_utf8_lossy(v).trim_end_matches('\0').trim().to_string());
+    let make = make.as_ref().map(|m| m.as_str());
+    match h.u16(2) {
+        Some(0x4f52) | Some(0x5352) => return kind(Format::Orf, make.or(Some("OLYMPUS")), None),
+        Some(0x55) => return kind(Format::Rw2, make.or(Some("Panasonic")), None),
+        Some(42) => {},
+        _ => return kind(Format::Unknown, None, None)
+    }
+    if head.get(8..10) == Some(b"CR") {
+        let version = head.get(10..12).map(|v| format!("{}.{}", v[0], v[1]));
+        return kind(Format::Cr2, make.or(Some("Canon")), version);
+    }
+    if let Some((1, 4, v)) = h.tag(0xc612) {
+        return kind(Format::Dng, make, Some(format!("{}.{}.{}.{}", v[0], v[1], v[2], v[3])));
+    }
+    let upper = make.map(|m| m.to_uppercase()).unwrap_or_default();
+    let format = if upper.starts_with("NIKON") { Format::Nef }
+                 else if upper.starts_with("SONY") { Format::Arw }
+                 else if upper.starts_with("PENTAX") || upper.starts_with("RICOH") { Format::Pef }
+                 else { Format::Tiff };
+    kind(format, make, None)
+}
+
+/// Identifies a raw file by its first few hundred bytes
+///
+/// The rest of the file is not read and no RawImage is built, so this is
+/// cheap enough for sorting large trees. The vendor of TIFF based files
+/// is only known if the Make tag is stored near the start of the file.
+pub fn identify<P: AsRef<Path>>(path: P) -> io::Result<FileKind> {
+    let mut head = Vec::new();
+    try!(try!(File::open(path)).take(HEAD).read_to_end(&mut head));
+    Ok(identify_bytes(&head))
+}
+
+#[test]
+fn test_identify() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let cr2 = Cr2Builder::new().ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon"))).build();
+    assert_eq!("CR2 2.0 (Canon)", identify_bytes(&cr2).to_string());
+    let mut nef = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x0f\0\x02\0\0\0\x06\0\0\0\x1a\0\0\0\0NIKON\0".to_vec();
+    assert_eq!(FileKind { format: Format::Nef, vendor: Some("NIKON".to_string()), version: None }, identify_bytes(&nef));
+    nef[21] = 0xff;
+    assert_eq!(Format::Tiff, identify_bytes(&nef).format);
+    assert_eq!(Format::Cr3, identify_bytes(b"\0\0\0\x18ftypcrx \0\0\0\x01").format);
+    assert_eq!(Format::Unknown, identify_bytes(b"GIF89a").format);
+}
@@ -0,0 +1,3 @@
+//! Building blocks for TIFF/EXIF structures
+
+pub mod writer;
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use cr2::{RawFileError,TagData,FormatErrorKind};
+pub use cr2::ByteOrder;
+
+/// Value of a tag, one variant per TIFF type
+#[derive(Debug,Clone,PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    /// Written with a terminating NUL
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    /// Numerator and denominator
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>)
+}
+
+impl Value {
+    /// Type, count and bytes of the value
+    pub(crate) fn encode(&self, order: ByteOrder) -> Result<(u16, u32, Vec<u8>),RawFileError> {
+        macro_rules! encode {
+            ($tagtype:expr, $v:expr) => {
+                ($tagtype, $v.len(), $v.iter().flat_map(|x| match order {
+                    ByteOrder::Intel => x.to_le_bytes().to_vec(),
+                    ByteOrder::Motorola => x.to_be_bytes().to_vec()
+                }).collect())
+            }
+        }
+        let (tagtype, count, bytes) = match *self {
+            Value::Byte(ref v) => (1, v.len(), v.clone()),
+            Value::Ascii(ref s) => {
+                if s.contains('\0') {
+                    return Err(RawFileError::format(FormatErrorKind::Invalid, "String contains NUL"));
+                }
+                let mut b = s.as_bytes().to_vec();
+                b.push(0);
+                (2, b.len(), b)
+            },
+            Value::Short(ref v) => encode!(3, v),
+            Value::Long(ref v) => encode!(4, v),
+            Value::Rational(ref v) => {
+                let (_, _, b) = encode!(4, v.iter().flat_map(|r| vec![r.0, r.1]).collect::<Vec<u32>>());
+                (5, v.len(), b)
+            },
+            Value::SByte(ref v) => (6, v.len(), v.iter().map(|b| *b as u8).collect()),
+            Value::Undefined(ref v) => (7, v.len(), v.clone()),
+            Value::SShort(ref v) => encode!(8, v),
+            Value::SLong(ref v) => encode!(9, v),
+            Value::SRational(ref v) => {
+                let (_, _, b) = encode!(9, v.iter().flat_map(|r| vec![r.0, r.1]).collect::<Vec<i32>>());
+                (10, v.len(), b)
+            },
+            Value::Float(ref v) => encode!(11, v),
+            Value::Double(ref v) => encode!(12, v)
+        };
+        if count > u32::max_value() as usize {
+            return Err(RawFileError::format(FormatErrorKind::TooLarge, format!("{} values exceed the TIFF count", count)));
+        }
+        Ok((tagtype, count as u32, bytes))
+    }
+}
+
+impl Value {
+    /// Value of a parsed tag, None for types the parser does not keep
+    pub(crate) fn from_tag(tagtype: u16, data: &[TagData]) -> Option<Value> {
+        let u = || data.iter().filter_map(|d| d.as_u32());
+        let i = || data.iter().filter_map(|d| match *d { TagData::Signed(v) => Some(v), _ => None });
+        let r = || data.iter().filter_map(|d| match *d { TagData::U64(v) => Some(v), TagData::I64(v) => Some(v as u64), _ => None })
+            .map(|v| (v as u32, (v >> 32) as u32));
+        let f = || data.iter().filter_map(|d| match *d { TagData::Float(v) => Some(v), _ => None });
+        Some(match tagtype {
+            1 => Value::Byte(u().map(|v| v as u8).collect()),
+            2 => match data.first() {
+                Some(&TagData::Strg(ref s)) => Value::Ascii(s.clone()),
+                _ => return None
+            },
+            3 => Value::Short(u().map(|v| v as u16).collect()),
+            4 => Value::Long(u().collect()),
+            5 => Value::Rational(r().collect()),
+            6 => Value::SByte(i().map(|v| v as i8).collect()),
+            7 => Value::Undefined(u().map(|v| v as u8).collect()),
+            8 => Value::SShort(i().map(|v| v as i16).collect()),
+            9 => Value::SLong(i().collect()),
+            10 => Value::SRational(r().map(|(n, d)| (n as i32, d as i32)).collect()),
+            11 => Value::Float(f().map(|v| v as f32).collect()),
+            12 => Value::Double(f().collect()),
+            _ => return None
+        })
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Entry {
+    Value(Value),
+    /// IFD referenced by a LONG pointer, e.g. the EXIF IFD
+    Ifd(Ifd)
+}
+
+/// An IFD with its tags and the IFDs it points to
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Ifd {
+    entries: BTreeMap<u16, Entry>
+}
+
+fn u16_bytes(v: u16, order: ByteOrder) -> [u8; 2] {
+    match order { ByteOrder::Intel => v.to_le_bytes(), ByteOrder::Motorola => v.to_be_bytes() }
+}
+
+fn u32_bytes(v: u32, order: ByteOrder) -> [u8; 4] {
+    match order { ByteOrder::Intel => v.to_le_bytes(), ByteOrder::Motorola => v.to_be_bytes() }
+}
+
+/// Builds a 12 byte IFD entry, field holds the value or its offset
+pub(crate) fn entry(id: u16, tagtype: u16, count: u32, field: [u8; 4], order: ByteOrder) -> [u8; 12] {
+    let mut e = [0u8; 12];
+    e[0..2].copy_from_slice(&u16_bytes(id, order));
+    e[2..4].copy_from_slice(&u16_bytes(tagtype, order));
+    e[4..8].copy_from_slice(&u32_bytes(count, order));
+    e[8..12].copy_from_slice(&field);
+    e
+}
+
+/// Pads the data to a word boundary, returns the offset after it
+fn align(out: &mut Vec<u8>) -> Result<u32,RawFileError> {
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+    if out.len() > u32::max_value() as usize {
+        return Err(RawFileError::format(FormatErrorKind::TooLarge, "TIFF data exceeds 4 GB"));
+    }
+    Ok(out.len() as u32)
+}
+
+impl Ifd {
+    pub fn new() -> Ifd {
+        Ifd::default()
+    }
+
+    /// Sets a tag, replacing an earlier value
+    pub fn tag(mut self, id: u16, value: Value) -> Ifd {
+        self.entries.insert(id, Entry::Value(value));
+        self
+    }
+
+    /// Sets a pointer to an IFD, e.g. 0x8769 for the EXIF IFD
+    pub fn sub_ifd(mut self, id: u16, ifd: Ifd) -> Ifd {
+        self.entries.insert(id, Entry::Ifd(ifd));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Appends the IFD, its values and sub IFDs to out, returns the
+    /// offset of the IFD
+    ///
+    /// Offsets are relative to the start of out, which has to be the
+    /// start of the TIFF header. Everything is aligned to word boundaries.
+    /// The pointer to the next IFD is 0.
+    pub fn write(&self, out: &mut Vec<u8>, order: ByteOrder) -> Result<u32,RawFileError> {
+        if self.entries.len() > u16::max_value() as usize {
+            return Err(RawFileError::format(FormatErrorKind::TooLarge, format!("{} entries exceed the IFD count", self.entries.len())));
+        }
+        let start = try!(align(out));
+        out.extend_from_slice(&u16_bytes(self.entries.len() as u16, order));
+        let mut fields = Vec::new();
+        for (&id, e) in &self.entries {
+            let (tagtype, count) = match *e {
+                Entry::Value(ref v) => { let e = try!(v.encode(order)); (e.0, e.1) },
+                Entry::Ifd(_) => (4, 1)
+            };
+            fields.push(out.len() + 8);
+            out.extend_from_slice(&entry(id, tagtype, count, [0; 4], order));
+        }
+        out.extend_from_slice(&[0; 4]);
+        for (field, entry) in fields.into_iter().zip(self.entries.values()) {
+            let value = match *entry {
+                Entry::Value(ref v) => {
+                    let (_, _, bytes) = try!(v.encode(order));
+                    if bytes.len() <= 4 {
+                        out[field..field + bytes.len()].copy_from_slice(&bytes);
+                        continue;
+                    }
+                    let offset = try!(align(out));
+                    out.extend_from_slice(&bytes);
+                    offset
+                },
+                Entry::Ifd(ref ifd) => try!(ifd.write(out, order))
+            };
+            out[field..field + 4].copy_from_slice(&u32_bytes(value, order));
+        }
+        Ok(start)
+    }
+}
+
+/// Serializes a chain of IFDs as TIFF file
+pub fn to_tiff(chain: &[Ifd], order: ByteOrder) -> Result<Vec<u8>,RawFileError> {
+    let mut out = match order { ByteOrder::Intel => b"II*\0".to_vec(), ByteOrder::Motorola => b"MM\0*".to_vec() };
+    out.extend_from_slice(&[0; 4]);
+    let mut pointer = 4;
+    for ifd in chain {
+        let offset = try!(ifd.write(&mut out, order));
+        out[pointer..pointer + 4].copy_from_slice(&u32_bytes(offset, order));
+        pointer = offset as usize + 2 + 12 * ifd.entries.len();
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_write() {
+    use cr2;
+    let ifd = Ifd::new()
+        .tag(0x110, Value::Ascii("Canon EOS 6D".to_string()))
+        .tag(0x112, Value::Short(vec![6]))
+        .tag(0x11a, Value::Rational(vec![(72, 1)]))
+        .sub_ifd(0x8769, Ifd::new().tag(0x8827, Value::Short(vec![400])).tag(0x9204, Value::SRational(vec![(-1, 3)])));
+    for &(order, head) in &[(ByteOrder::Intel, b"II*\0\0\0\0\0CR\x02\0\0\0\0\0"), (ByteOrder::Motorola, b"MM\0*\0\0\0\0CR\x02\0\0\0\0\0")] {
+        let mut out = head.to_vec();
+        let offset = ifd.write(&mut out, order).unwrap();
+        out[4..8].copy_from_slice(&u32_bytes(offset, order));
+        let image = cr2::open_bytes(out, "written".to_string()).unwrap();
+        assert_eq!(Some("Canon EOS 6D"), image.model());
+        assert_eq!(Some(6), image.orientation());
+        let e = image.exif_summary();
+        assert_eq!((Some(400), Some(-1.0 / 3.0)), (e.iso, e.exposure_compensation));
+    }
+    let tiff = to_tiff(&[Ifd::new(), Ifd::new().tag(1, Value::Byte(vec![1]))], ByteOrder::Motorola).unwrap();
+    assert_eq!(b"MM\0*\0\0\0\x08\0\0\0\0\0\x0e\0\x01\0\x01\0\x01\0\0\0\x01\x01\0\0\0\0\0\0\0".to_vec(), tiff);
+    // counts that do not fit the TIFF fields are errors, not truncated
+    let mut many = Ifd::new();
+    for id in 0..u16::max_value() {
+        many = many.tag(id, Value::Byte(vec![0]));
+    }
+    assert!(to_tiff(&[many.clone()], ByteOrder::Intel).is_ok());
+    assert!(to_tiff(&[many.tag(u16::max_value(), Value::Byte(vec![0]))], ByteOrder::Intel).is_err());
+    assert!(to_tiff(&[Ifd::new().tag(0x13b, Value::Ascii("A\0B".to_string()))], ByteOrder::Intel).is_err());
+}
@@ -0,0 +1,178 @@
+use catalog::json_string;
+use cr2::{self,RawImage,RawFileError};
+use validate;
+
+/// A part of the file and the structure it belongs to
+#[derive(Debug,Clone,PartialEq)]
+pub struct Region {
+    pub offset: u64,
+    pub length: u64,
+    /// IFD name as in tag lists, followed by the tag or the data, e.g.
+    /// "exif makernote" or "ifd3 strip_offset 0"
+    pub name: String
+}
+
+/// Regions of the file: the header, the IFD tables, the values outside
+/// the IFD entries and the image data, sorted by offset. Regions may
+/// overlap, e.g. the MakerNote IFD lies within the MakerNote value.
+pub fn regions(image: &RawImage) -> Result<Vec<Region>,RawFileError> {
+    let region = |offset, length, name: String| Region { offset: offset, length: length, name: name };
+    let mut regions = vec![region(0, 16, "header".to_string())];
+    let big_endian = image.layout_state().0;
+    for (name, _, ifd) in image.ifds() {
+        // the entry count is read again, tags of unknown type are not kept
+        let n = match *try!(image.read_range(ifd.offset as u64, 2)) {
+            [a, b] if big_endian => u16::from_be_bytes([a, b]),
+            [a, b] => u16::from_le_bytes([a, b]),
+            _ => 0
+        };
+        regions.push(region(ifd.offset as u64, 2 + n as u64 * 12 + 4, name.clone()));
+        for (tag, p) in &ifd.pos {
+            let size = cr2::type_size(p.tagtype).unwrap_or(1) as u64 * p.count as u64;
+            if size > 4 {
+                regions.push(region(p.offset, size, format!("{} {}", name, tag)));
+            }
+        }
+        for (data, offset, length) in validate::data_regions(&name, ifd) {
+            regions.push(region(offset, length, data));
+        }
+    }
+    regions.sort_by(|a, b| (a.offset, b.length).cmp(&(b.offset, a.length)));
+    Ok(regions)
+}
+
+/// Structure of a whole file: the regions with the gaps between them,
+/// named "gap", and the file length
+pub fn map(image: &RawImage) -> Result<(Vec<Region>, u64),RawFileError> {
+    let len = try!(image.len());
+    let mut regions = try!(regions(image));
+    let mut gaps = Vec::new();
+    let mut covered = 0;
+    for r in &regions {
+        // regions of truncated files may reach beyond the end
+        if r.offset.min(len) > covered {
+            gaps.push(Region { offset: covered, length: r.offset.min(len) - covered, name: "gap".to_string() });
+        }
+        covered = covered.max(r.offset + r.length).min(len);
+    }
+    if covered < len {
+        gaps.push(Region { offset: covered, length: len - covered, name: "gap".to_string() });
+    }
+    regions.extend(gaps.into_iter().filter(|g| g.length > 0));
+    regions.sort_by(|a, b| (a.offset, b.length).cmp(&(b.offset, a.length)));
+    Ok((regions, len))
+}
+
+/// The map as JSON: {"length": .., "regions": [{"offset": .., "length": .., "name": ..}, ..]}
+pub fn map_json(regions: &[Region], len: u64) -> String {
+    let r: Vec<String> = regions.iter().map(|r| format!("{{\"offset\": {}, \"length\": {}, \"name\": {}}}",
+                                                        r.offset, r.length, json_string(&r.name))).collect();
+    format!("{{\"length\": {}, \"regions\": [{}]}}\n", len, r.join(", "))
+}
+
+/// Row and color of a region in the SVG map
+fn row(name: &str) -> (u32, &'static str) {
+    if name == "header" { (0, "#4e79a7") }
+    else if name == "gap" { (3, "#bab0ac") }
+    else if ["strip_offset", "tile_offset", "thumbnail_offset"].iter().any(|d| name.contains(d)) { (2, "#59a14f") }
+    else if !name.contains(' ') { (0, "#f28e2b") }
+    else { (1, "#e15759") }
+}
+
+/// The map as SVG bar chart, one row each for the header and IFDs, the
+/// tag values, the image data and the gaps. Regions show their name and
+/// offset on hover.
+pub fn map_svg(regions: &[Region], len: u64) -> String {
+    const WIDTH: f64 = 1000.0;
+    let scale = if len > 0 { WIDTH / len as f64 } else { 0.0 };
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"130\">\n", WIDTH + 120.0);
+    for (i, label) in ["header, IFDs", "values", "image data", "gaps"].iter().enumerate() {
+        svg += &format!("<text x=\"0\" y=\"{}\" font-size=\"12\">{}</text>\n", i * 30 + 17, label);
+    }
+    for r in regions {
+        let (row, color) = row(&r.name);
+        let title = format!("{} 0x{:x} ({} bytes)", r.name, r.offset, r.length)
+            .replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        svg += &format!("<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"24\" fill=\"{}\"><title>{}</title></rect>\n",
+                        120.0 + r.offset as f64 * scale, row * 30, (r.length as f64 * scale).max(1.0), color,
+                        title);
+    }
+    svg + "</svg>\n"
+}
+
+/// Hex dump of a range of the file, 16 bytes a line with the names of
+/// the regions the line overlaps
+pub fn hexdump(image: &RawImage, offset: u64, len: u64) -> Result<String,RawFileError> {
+    let data = try!(image.read_range(offset, len));
+    let regions = try!(regions(image));
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let start = offset + i as u64 * 16;
+        let end = start + line.len() as u64;
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line.iter().map(|&b| if b >= 0x20 && b < 0x7f { b as char } else { '.' }).collect();
+        let owners: Vec<&str> = regions.iter().filter(|r| r.offset < end && r.offset + r.length > start)
+            .map(|r| r.name.as_str()).collect();
+        out += &format!("{:08x}  {:<47}  |{:<16}|  {}\n", start, hex.join(" "), ascii, owners.join(", "));
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_hexdump() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .build();
+    let image = cr2::open_bytes(f, "a".to_string()).unwrap();
+    let regions = regions(&image).unwrap();
+    assert_eq!(Region { offset: 0, length: 16, name: "header".to_string() }, regions[0]);
+    let model = regions.iter().find(|r| r.name == "ifd0 model").unwrap();
+    assert_eq!(13, model.length);
+    let dump = hexdump(&image, model.offset, 16).unwrap();
+    assert!(dump.contains("43 61 6e 6f 6e") && dump.contains("|Canon EOS 6D"), "{}", dump);
+    assert!(dump.contains("ifd0 model"), "{}", dump);
+    assert_eq!("", hexdump(&image, 1 << 20, 16).unwrap());
+}
+
+#[test]
+fn test_map() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let mut f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .build();
+    f.extend_from_slice(&[0; 8]);
+    let image = cr2::open_bytes(f.clone(), "a".to_string()).unwrap();
+    let (regions, len) = map(&image).unwrap();
+    assert_eq!(f.len() as u64, len);
+    let last = regions.last().unwrap();
+    assert_eq!(("gap", len - 8, 8), (last.name.as_str(), last.offset, last.length));
+    let json = map_json(&regions, len);
+    assert!(json.contains("{\"offset\": 0, \"length\": 16, \"name\": \"header\"}"), "{}", json);
+    let svg = map_svg(&regions, len);
+    assert_eq!(regions.len(), svg.matches("<rect").count());
+    assert!(svg.contains("<title>ifd0 model 0x"), "{}", svg);
+}
+
+#[test]
+fn test_map_truncated() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let mut f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 0xff, 0xd9]))
+        .ifd(Ifd::new().thumbnail(&[0xff, 0xd8, 1, 2, 3]))
+        .build();
+    // the preview reaches beyond the end, the thumbnail starts after it,
+    // as in a file cut short
+    let len = f.len() as u32;
+    let image = cr2::open_bytes(f.clone(), "a".to_string()).unwrap();
+    let ifds = image.ifds();
+    for &(ifd, tag, value) in &[("ifd0", "strip_byte_count", len), ("ifd1", "thumbnail_offset", 3 * len)] {
+        let p = ifds.iter().find(|i| i.0 == ifd).unwrap().2.pos[tag].offset as usize;
+        f[p..p + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    let image = cr2::open_bytes(f, "a".to_string()).unwrap();
+    let (regions, len) = map(&image).unwrap();
+    for g in regions.iter().filter(|r| r.name == "gap") {
+        assert!(g.length > 0 && g.offset + g.length <= len, "{:?}", g);
+    }
+}
@@ -0,0 +1,88 @@
+use cr2::{RawFileError,FormatErrorKind};
+use ifd::writer::{self,ByteOrder,Ifd,Value};
+
+/// Segments of a JPEG up to the start of scan as (marker, start, end),
+/// end is the index after the segment
+fn segments(jpeg: &[u8]) -> Result<Vec<(u8, usize, usize)>,RawFileError> {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return Err(RawFileError::format(FormatErrorKind::BadMagic, "Not a JPEG"));
+    }
+    let mut segments = Vec::new();
+    let mut i = 2;
+    while i + 4 <= jpeg.len() && jpeg[i] == 0xff {
+        let marker = jpeg[i + 1];
+        if marker == 0xda {
+            return Ok(segments);
+        }
+        // the length counts itself
+        let len = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+        if len < 2 {
+            return Err(RawFileError::format(FormatErrorKind::Invalid, format!("JPEG segment 0x{:02x} with length {}", marker, len)));
+        }
+        let end = i + 2 + len;
+        if end > jpeg.len() {
+            break;
+        }
+        segments.push((marker, i, end));
+        i = end;
+    }
+    Err(RawFileError::format(FormatErrorKind::Missing, "JPEG without start of scan"))
+}
+
+/// Replaces the EXIF segment of a JPEG with one holding the TIFF data
+///
+/// The segment is placed after a JFIF segment, which has to come first.
+pub fn set_exif(jpeg: &[u8], tiff: &[u8]) -> Result<Vec<u8>,RawFileError> {
+    let segments = try!(segments(jpeg));
+    if tiff.len() + 8 > 0xffff {
+        return Err(RawFileError::format(FormatErrorKind::TooLarge, "EXIF data exceeds 64K"));
+    }
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(tiff);
+    let mut out = jpeg[..2].to_vec();
+    let mut pos = 2;
+    let mut inserted = false;
+    for (marker, start, end) in segments {
+        if !inserted && marker != 0xe0 {
+            out.extend_from_slice(&app1);
+            inserted = true;
+        }
+        if marker != 0xe1 || !jpeg[start + 4..end].starts_with(b"Exif\0\0") {
+            out.extend_from_slice(&jpeg[start..end]);
+        }
+        pos = end;
+    }
+    if !inserted {
+        out.extend_from_slice(&app1);
+    }
+    out.extend_from_slice(&jpeg[pos..]);
+    Ok(out)
+}
+
+/// Sets the EXIF orientation of a JPEG, viewers rotate it accordingly
+///
+/// Other EXIF data of the JPEG is dropped, Canon previews have none.
+pub fn set_orientation(jpeg: &[u8], orientation: u16) -> Result<Vec<u8>,RawFileError> {
+    let ifd = Ifd::new().tag(0x112, Value::Short(vec![orientation]));
+    set_exif(jpeg, &try!(writer::to_tiff(&[ifd], ByteOrder::Intel)))
+}
+
+#[test]
+fn test_set_orientation() {
+    let jpeg = b"\xff\xd8\xff\xe0\0\x04JF\xff\xe1\0\x08Exif\0\0\xff\xda\0\x02\x01\x02\xff\xd9";
+    let out = set_orientation(jpeg, 6).unwrap();
+    let s = segments(&out).unwrap();
+    assert_eq!(vec![0xe0, 0xe1], s.iter().map(|s| s.0).collect::<Vec<_>>());
+    assert_eq!(&[6, 0], &out[s[1].1 + 4 + 6 + 18..s[1].1 + 4 + 6 + 20]);
+    assert!(out.ends_with(b"\xff\xda\0\x02\x01\x02\xff\xd9"));
+    assert!(set_orientation(b"\xff\xd8\xff\xd9", 1).is_err());
+    // APP1 with a length too short for itself
+    for len in &[b"\0\0", b"\0\x01"] {
+        let mut jpeg = b"\xff\xd8\xff\xe1".to_vec();
+        jpeg.extend_from_slice(&len[..]);
+        jpeg.extend_from_slice(b"\xff\xda\0\x02\x01\x02\xff\xd9");
+        assert!(set_orientation(&jpeg, 6).is_err());
+    }
+}
@@ -1,4 +1,46 @@
 pub mod cr2;
+pub mod cameras;
+pub mod canon;
+pub mod exif;
+pub mod datetime;
+pub mod xmp;
+pub mod geotag;
+pub mod diff;
+pub mod hash;
+pub mod grouping;
+pub mod cache;
+pub mod catalog;
+pub mod zip;
+pub mod source;
+pub mod trace;
+pub mod validate;
+pub mod identify;
+pub mod memo;
+pub mod editor;
+pub mod repair;
+pub mod jpeg;
+pub mod ifd;
+pub mod export;
+pub mod lut;
+pub mod color;
+pub mod analysis;
+pub mod sidecar;
+pub mod decoder;
+pub mod prelude;
+pub mod inspect;
+pub mod remote;
+#[cfg(test)]
+mod golden;
+#[cfg(any(test, feature = "synth"))]
+pub mod synth;
+#[cfg(feature = "http")]
+pub mod http;
+
+pub use diff::diff;
+pub use identify::identify;
+
+/// Result of fallible operations of the crate
+pub type Result<T> = ::std::result::Result<T,cr2::RawFileError>;
 
 
 #[cfg(test)]
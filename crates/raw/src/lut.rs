@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use cr2::{RawFileError,FormatErrorKind};
+
+/// A 3D color lookup table as in .cube files
+#[derive(Debug,Clone,PartialEq)]
+pub struct Lut3d {
+    pub title: Option<String>,
+    /// Number of points per axis
+    pub size: usize,
+    /// Input values mapped to the first and last points
+    pub domain_min: [f64; 3],
+    pub domain_max: [f64; 3],
+    /// Output colors, red changing fastest
+    table: Vec<[f64; 3]>
+}
+
+fn numbers(s: &str, line: usize) -> Result<[f64; 3],RawFileError> {
+    let v: Vec<f64> = s.split_whitespace().filter_map(|n| n.parse().ok()).collect();
+    if v.len() != 3 || s.split_whitespace().count() != 3 {
+        return Err(RawFileError::format(FormatErrorKind::Invalid, format!("Line {}: expected 3 numbers", line)));
+    }
+    Ok([v[0], v[1], v[2]])
+}
+
+impl Lut3d {
+    /// Parses a .cube file, 1D tables are not supported
+    pub fn parse(text: &str) -> Result<Lut3d,RawFileError> {
+        let mut lut = Lut3d { title: None, size: 0, domain_min: [0.0; 3], domain_max: [1.0; 3], table: Vec::new() };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let (key, rest) = match line.find(char::is_whitespace) {
+                Some(p) => (&line[..p], line[p..].trim()),
+                None => (line, "")
+            };
+            match key {
+                "" => {},
+                k if k.starts_with('#') => {},
+                "TITLE" => lut.title = Some(rest.trim_matches('"').to_string()),
+                "LUT_3D_SIZE" => match rest.parse() {
+                    Ok(n) if n >= 2 && n <= 256 => lut.size = n,
+                    _ => return Err(RawFileError::format(FormatErrorKind::Invalid, format!("Line {}: invalid size {}", i + 1, rest)))
+                },
+                "DOMAIN_MIN" => lut.domain_min = try!(numbers(rest, i + 1)),
+                "DOMAIN_MAX" => lut.domain_max = try!(numbers(rest, i + 1)),
+                "LUT_1D_SIZE" => return Err(RawFileError::NotImplemented("1D LUTs".to_string())),
+                _ => lut.table.push(try!(numbers(line, i + 1)))
+            }
+        }
+        if lut.size == 0 || lut.table.len() != lut.size * lut.size * lut.size {
+            return Err(RawFileError::format(FormatErrorKind::Invalid, format!("{} entries for LUT size {}", lut.table.len(), lut.size)));
+        }
+        Ok(lut)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Lut3d,RawFileError> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Lut3d::parse(&text)
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> [f64; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    /// Maps a color with tetrahedral interpolation, inputs outside the
+    /// domain are clamped
+    pub fn apply(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let n = self.size - 1;
+        let mut base = [0; 3];
+        let mut f = [0.0; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let x = if range > 0.0 { (rgb[c] - self.domain_min[c]) / range } else { 0.0 };
+            let x = x.max(0.0).min(1.0) * n as f64;
+            base[c] = (x.floor() as usize).min(n - 1);
+            f[c] = x - base[c] as f64;
+        }
+        let (r, g, b) = (base[0], base[1], base[2]);
+        let (fr, fg, fb) = (f[0], f[1], f[2]);
+        let c000 = self.at(r, g, b);
+        let c111 = self.at(r + 1, g + 1, b + 1);
+        // the unit cube is split into six tetrahedra along its diagonal
+        let (w, c1, c2) = if fr > fg {
+            if fg > fb {
+                ([1.0 - fr, fr - fg, fg - fb, fb], self.at(r + 1, g, b), self.at(r + 1, g + 1, b))
+            } else if fr > fb {
+                ([1.0 - fr, fr - fb, fb - fg, fg], self.at(r + 1, g, b), self.at(r + 1, g, b + 1))
+            } else {
+                ([1.0 - fb, fb - fr, fr - fg, fg], self.at(r, g, b + 1), self.at(r + 1, g, b + 1))
+            }
+        } else if fb > fg {
+            ([1.0 - fb, fb - fg, fg - fr, fr], self.at(r, g, b + 1), self.at(r, g + 1, b + 1))
+        } else if fb > fr {
+            ([1.0 - fg, fg - fb, fb - fr, fr], self.at(r, g + 1, b), self.at(r, g + 1, b + 1))
+        } else {
+            ([1.0 - fg, fg - fr, fr - fb, fb], self.at(r, g + 1, b), self.at(r + 1, g + 1, b))
+        };
+        let mut out = [0.0; 3];
+        for c in 0..3 {
+            out[c] = w[0] * c000[c] + w[1] * c1[c] + w[2] * c2[c] + w[3] * c111[c];
+        }
+        out
+    }
+
+    /// Maps all pixels of an image
+    pub fn apply_all(&self, pixels: &mut [[f64; 3]]) {
+        for p in pixels {
+            *p = self.apply(*p);
+        }
+    }
+}
+
+#[test]
+fn test_lut() {
+    // rotates the channels, a linear map is reproduced exactly
+    let mut cube = "# test\nTITLE \"rotate\"\nLUT_3D_SIZE 3\n".to_string();
+    for b in 0..3 {
+        for g in 0..3 {
+            for r in 0..3 {
+                cube += &format!("{} {} {}\n", g as f64 / 2.0, b as f64 / 2.0, r as f64 / 2.0);
+            }
+        }
+    }
+    let lut = Lut3d::parse(&cube).unwrap();
+    assert_eq!(Some("rotate".to_string()), lut.title);
+    for &rgb in &[[0.2, 0.5, 0.9], [0.9, 0.5, 0.2], [0.5, 0.9, 0.2], [1.0, 1.0, 0.0]] {
+        let out = lut.apply(rgb);
+        for c in 0..3 {
+            assert!((out[c] - rgb[(c + 1) % 3]).abs() < 1e-12, "{:?} -> {:?}", rgb, out);
+        }
+    }
+    assert_eq!([0.0, 1.0, 1.0], lut.apply([2.0, -1.0, 1.0]));
+    assert!(Lut3d::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self,Read};
+use std::path::{Path,PathBuf};
+
+/// A sound memo recorded with an image
+///
+/// Canon bodies store memos as WAV file next to the raw file with the
+/// same name, IMG_0001.CR2 -> IMG_0001.WAV. They are not embedded in
+/// the raw file.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Memo {
+    pub path: PathBuf,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// Length in seconds
+    pub duration: f64
+}
+
+impl Memo {
+    /// Reads the WAV file, e.g. to import it with the image
+    pub fn data(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        try!(try!(File::open(&self.path)).read_to_end(&mut data));
+        Ok(data)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parses the format of a WAV file from its chunks
+fn parse_wav(data: &[u8]) -> io::Result<(u16, u32, u16, f64)> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid("Not a WAV file"));
+    }
+    let le16 = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let le32 = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    let mut format = None;
+    let mut i = 12;
+    while i + 8 <= data.len() {
+        let size = le32(i + 4) as usize;
+        match &data[i..i + 4] {
+            b"fmt " if i + 24 <= data.len() => format = Some((le16(i + 10), le32(i + 12), le32(i + 16), le16(i + 22))),
+            b"data" => {
+                let (channels, rate, byte_rate, bits) = match format {
+                    Some(f) => f,
+                    None => return Err(invalid("WAV data before format"))
+                };
+                let duration = if byte_rate > 0 { size as f64 / byte_rate as f64 } else { 0.0 };
+                return Ok((channels, rate, bits, duration));
+            },
+            _ => {}
+        }
+        // chunks are padded to an even size
+        i += 8 + size + size % 2;
+    }
+    Err(invalid("WAV file without data"))
+}
+
+/// Finds the sound memo of a raw file, None if there is none
+pub fn find(raw: &Path) -> io::Result<Option<Memo>> {
+    for ext in &["WAV", "wav"] {
+        let path = raw.with_extension(ext);
+        if !path.is_file() {
+            continue;
+        }
+        // only the header is needed
+        let mut head = Vec::new();
+        try!(try!(File::open(&path)).take(4096).read_to_end(&mut head));
+        let (channels, rate, bits, duration) = try!(parse_wav(&head));
+        return Ok(Some(Memo { path: path, channels: channels, sample_rate: rate,
+                              bits_per_sample: bits, duration: duration }));
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_parse_wav() {
+    let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x40\x1f\0\0\x80\x3e\0\0\x02\0\x10\0".to_vec();
+    wav.extend_from_slice(b"LIST\x03\0\0\0abc\0data\0\x7d\0\0");
+    assert_eq!((1, 8000, 16, 2.0), parse_wav(&wav).unwrap());
+    assert!(parse_wav(b"RIFF\0\0\0\0WAVEdata\0\0\0\0").is_err());
+}
@@ -0,0 +1,18 @@
+//! Commonly used types and traits, for `use raw::prelude::*;`
+
+pub use Result;
+pub use cr2::{RawImage,RawMetadata,Frame,RawFileError,ErrorCode,FormatErrorKind,TagData,Compression,Layout,IfdKind};
+pub use cameras::{Camera,Area};
+pub use identify::{identify,FileKind,Format};
+pub use validate::Warning;
+pub use source::RawSource;
+pub use decoder::RawDecoder;
+
+#[test]
+fn test_prelude() {
+    fn model(data: Vec<u8>) -> Result<Option<String>> {
+        let image: RawImage = try!(::cr2::open_bytes(data, "a".to_string()));
+        Ok(image.model().map(String::from))
+    }
+    assert_eq!(ErrorCode::FileFormat, model(b"II*\0".to_vec()).unwrap_err().code());
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc,Mutex,RwLock};
+use cr2::{self,RawImage,RawFileError,FormatErrorKind};
+use source::RawSource;
+
+/// Least number of bytes requested for a missing range, the values of
+/// an IFD usually follow its entries
+const FETCH: u64 = 64 * 1024;
+
+/// What a client wants from a remote file
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Target {
+    Thumbnail,
+    Preview,
+    /// All IFDs, the MakerNote and the header of the raw data
+    Metadata
+}
+
+/// The fetched parts of a file, reads of other parts fail and are
+/// recorded as missing
+struct Partial {
+    len: u64,
+    chunks: RwLock<BTreeMap<u64,Vec<u8>>>,
+    missing: Mutex<Vec<(u64,u64)>>
+}
+
+/// Parts of offset..offset+len not in the chunks
+fn gaps(chunks: &BTreeMap<u64,Vec<u8>>, offset: u64, len: u64) -> Vec<(u64,u64)> {
+    let end = offset + len;
+    let mut gaps = Vec::new();
+    let mut pos = offset;
+    for (&o, d) in chunks.range(..end) {
+        let e = o + d.len() as u64;
+        if e <= pos {
+            continue;
+        }
+        if o > pos {
+            gaps.push((pos, o - pos));
+        }
+        pos = e;
+        if pos >= end {
+            break;
+        }
+    }
+    if pos < end {
+        gaps.push((pos, end - pos));
+    }
+    gaps
+}
+
+impl RawSource for Partial {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let end = ::std::cmp::min(offset + buf.len() as u64, self.len);
+        let chunks = self.chunks.read().unwrap();
+        // a short read would look like the end of the file, so the whole
+        // range has to be fetched
+        if let Some(&(start, _)) = gaps(&chunks, offset, end - offset).first() {
+            let len = ::std::cmp::min(::std::cmp::max(end - start, FETCH), self.len - start);
+            self.missing.lock().unwrap().extend(gaps(&chunks, start, len));
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Bytes at {} not fetched", start)));
+        }
+        let mut pos = offset;
+        while pos < end {
+            let d = chunks.range(..pos + 1).rev()
+                .filter_map(|(&o, d)| d.get((pos - o) as usize..)).find(|d| !d.is_empty()).unwrap_or(&[]);
+            let n = ::std::cmp::min((end - pos) as usize, d.len());
+            let i = (pos - offset) as usize;
+            buf[i..i + n].copy_from_slice(&d[..n]);
+            pos += n as u64;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+/// Plans the range requests to read a remote file, e.g. from cloud storage
+///
+/// Starting from the first bytes of the file, plan reports the ranges to
+/// fetch next. Once they are added the plan is repeated until it is
+/// empty, each round follows the IFD offsets one step further. Parsing
+/// stops at the first missing byte, so a round usually asks for a single
+/// range, rounded up to 64 KB so that the values of an IFD come with it.
+pub struct Planner {
+    source: Arc<Partial>
+}
+
+impl Planner {
+    /// A planner for a file of len bytes starting with head
+    pub fn new(head: Vec<u8>, len: u64) -> Planner {
+        let p = Planner { source: Arc::new(Partial { len: len, chunks: RwLock::new(BTreeMap::new()),
+                                                     missing: Mutex::new(Vec::new()) }) };
+        p.add(0, head);
+        p
+    }
+
+    /// Adds fetched bytes at an offset
+    pub fn add(&self, offset: u64, data: Vec<u8>) {
+        if !data.is_empty() {
+            self.source.chunks.write().unwrap().insert(offset, data);
+        }
+    }
+
+    /// Offset and length of the ranges to fetch next for the target,
+    /// empty once everything needed was added
+    pub fn plan(&self, target: Target) -> Result<Vec<(u64,u64)>,RawFileError> {
+        self.source.missing.lock().unwrap().clear();
+        let image = match self.image() {
+            Ok(image) => image,
+            Err(e) => {
+                let missing = self.source.missing.lock().unwrap().clone();
+                return if missing.is_empty() { Err(e) } else { Ok(missing) };
+            }
+        };
+        let range = match target {
+            Target::Thumbnail => image.thumbnail_range(),
+            Target::Preview => image.preview_range(),
+            Target::Metadata => return Ok(Vec::new())
+        };
+        match range {
+            Some((offset, len)) => {
+                let len = ::std::cmp::min(len, self.source.len.saturating_sub(offset));
+                Ok(gaps(&self.source.chunks.read().unwrap(), offset, len))
+            },
+            None => Err(RawFileError::format(FormatErrorKind::Missing, format!("No {:?} in file", target)))
+        }
+    }
+
+    /// The image parsed from the fetched bytes, once plan is empty for a
+    /// target its data can be read, e.g. with RawImage::thumbnail
+    pub fn image(&self) -> Result<RawImage,RawFileError> {
+        cr2::open_source(self.source.clone(), "remote".to_string())
+    }
+}
+
+#[test]
+fn test_plan() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let mut preview = vec![0xff, 0xd8];
+    preview.resize(200000, 7);
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&preview))
+        .ifd(Ifd::new().thumbnail(&[0xff, 0xd8, 1, 2]))
+        .build();
+    let fetch = |p: &Planner, f: &[u8], target| {
+        let mut rounds = 0;
+        loop {
+            let ranges = p.plan(target).unwrap();
+            if ranges.is_empty() {
+                return rounds;
+            }
+            for (o, l) in ranges {
+                p.add(o, f[o as usize..(o + l) as usize].to_vec());
+            }
+            rounds += 1;
+        }
+    };
+    let p = Planner::new(f[..16].to_vec(), f.len() as u64);
+    assert!(fetch(&p, &f, Target::Metadata) > 0);
+    assert_eq!(Some("Canon EOS 6D"), p.image().unwrap().model());
+    let fetched: usize = p.source.chunks.read().unwrap().values().map(|d| d.len()).sum();
+    assert!(fetched < f.len(), "{} of {}", fetched, f.len());
+    assert_eq!(1, fetch(&p, &f, Target::Thumbnail));
+    assert_eq!(vec![0xff, 0xd8, 1, 2], p.image().unwrap().thumbnail().unwrap());
+    assert_eq!(0, fetch(&p, &f, Target::Thumbnail));
+    fetch(&p, &f, Target::Preview);
+    assert_eq!(preview, p.image().unwrap().preview().unwrap());
+    // a value running past the fetched bytes is fetched, not truncated
+    let artist = "A. Photographer ".repeat(10);
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x13b, &artist)).strip(&preview))
+        .build();
+    let image = cr2::open_bytes(f.clone(), "f".to_string()).unwrap();
+    let cut = image.ifds()[0].2.pos["artist"].offset + 20;
+    let p = Planner::new(f[..cut as usize].to_vec(), f.len() as u64);
+    assert_eq!(cut, p.plan(Target::Metadata).unwrap()[0].0);
+    fetch(&p, &f, Target::Metadata);
+    assert_eq!(Some(artist.as_str()), p.image().unwrap().artist());
+    let p = Planner::new(b"not a raw file".to_vec(), 14);
+    assert!(p.plan(Target::Metadata).is_err());
+}
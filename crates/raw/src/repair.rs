@@ -0,0 +1,220 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Read,Write};
+use std::path::Path;
+use cr2::{self,RawFileError,FormatErrorKind};
+use source;
+
+/// A change made by repair
+#[derive(Debug,Clone,PartialEq)]
+pub struct Fix {
+    /// Absolute file offset of the changed data
+    pub offset: u64,
+    pub msg: String
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset 0x{:x}: {}", self.offset, self.msg)
+    }
+}
+
+fn u16_at(data: &[u8], i: usize) -> Option<u16> {
+    data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], i: usize) -> Option<u32> {
+    data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Whether the IFD at offset lies within the data
+fn fits(data: &[u8], offset: usize) -> bool {
+    match u16_at(data, offset) {
+        Some(n) => offset + 2 + n as usize * 12 + 4 <= data.len(),
+        None => false
+    }
+}
+
+/// Removes entries repeating the id of an earlier entry, returns the
+/// position of the pointer to the next IFD
+fn remove_duplicates(data: &mut Vec<u8>, offset: usize, fixes: &mut Vec<Fix>) -> usize {
+    let n = u16_at(data, offset).unwrap_or(0) as usize;
+    let end = offset + 2 + n * 12;
+    let mut entries: Vec<Vec<u8>> = Vec::new();
+    for e in data[offset + 2..end].chunks(12) {
+        let id = u16_at(e, 0);
+        if entries.iter().any(|k| u16_at(k, 0) == id) {
+            fixes.push(Fix { offset: offset as u64,
+                             msg: format!("duplicate entry for tag 0x{:04x} removed", id.unwrap_or(0)) });
+        } else {
+            entries.push(e.to_vec());
+        }
+    }
+    if entries.len() == n {
+        return end;
+    }
+    // the table shrinks in place, the freed space is cleared
+    let next = u32_at(data, end).unwrap_or(0);
+    let mut table = (entries.len() as u16).to_le_bytes().to_vec();
+    for e in &entries {
+        table.extend_from_slice(e);
+    }
+    table.extend_from_slice(&next.to_le_bytes());
+    table.resize(end + 4 - offset, 0);
+    data[offset..end + 4].copy_from_slice(&table);
+    offset + 2 + entries.len() * 12
+}
+
+/// Position of the entry of a tag in the IFD at offset
+fn find_entry(data: &[u8], offset: usize, id: u16) -> Option<usize> {
+    let n = u16_at(data, offset).unwrap_or(0) as usize;
+    (0..n).map(|i| offset + 2 + i * 12).find(|&e| u16_at(data, e) == Some(id))
+}
+
+/// Positions, sizes and values of a SHORT or LONG tag
+fn values(data: &[u8], entry: usize) -> Vec<(usize, usize, u32)> {
+    let (tagtype, count) = (u16_at(data, entry + 2), u32_at(data, entry + 4));
+    let size = match tagtype { Some(3) => 2, Some(4) => 4, _ => return Vec::new() };
+    let count = count.unwrap_or(0) as usize;
+    let start = if size * count <= 4 { entry + 8 } else { u32_at(data, entry + 8).unwrap_or(0) as usize };
+    if count > data.len() || start + size * count > data.len() {
+        return Vec::new();
+    }
+    (0..count).map(|i| {
+        let p = start + i * size;
+        (p, size, if size == 2 { u16_at(data, p).unwrap_or(0) as u32 } else { u32_at(data, p).unwrap_or(0) })
+    }).collect()
+}
+
+/// Shortens data lengths that reach beyond the end of the file
+fn clamp_lengths(data: &mut Vec<u8>, ifd: usize, offsets: u16, lengths: u16, name: &str, fixes: &mut Vec<Fix>) {
+    let (o, l) = match (find_entry(data, ifd, offsets), find_entry(data, ifd, lengths)) {
+        (Some(o), Some(l)) => (values(data, o), values(data, l)),
+        _ => return
+    };
+    let len = data.len() as u64;
+    for (i, (&(_, _, offset), &(pos, size, length))) in o.iter().zip(l.iter()).enumerate() {
+        if (offset as u64) < len && offset as u64 + length as u64 > len {
+            let fixed = (len - offset as u64) as u32;
+            if size == 2 {
+                data[pos..pos + 2].copy_from_slice(&(fixed as u16).to_le_bytes());
+            } else {
+                data[pos..pos + 4].copy_from_slice(&fixed.to_le_bytes());
+            }
+            fixes.push(Fix { offset: pos as u64, msg: format!("{} {} reduced from {} to {}", name, i, length, fixed) });
+        }
+    }
+}
+
+/// Fixes recoverable damage of a little endian TIFF based raw file
+///
+/// Pointers to IFDs beyond the end of the file or back into the chain are
+/// cleared, duplicated IFD entries removed and strip and thumbnail
+/// lengths reaching beyond the end of the file shortened. No data is
+/// added or moved. Returns the repaired data with the fixes made, or an
+/// error if the result still does not parse.
+pub fn repair(mut data: Vec<u8>) -> Result<(Vec<u8>, Vec<Fix>),RawFileError> {
+    if !data.starts_with(b"II*\0") {
+        return Err(RawFileError::NotImplemented("Repairing files other than little endian TIFF".to_string()));
+    }
+    let mut fixes = Vec::new();
+    let mut chain = Vec::new();
+    let mut pointer = 4;
+    loop {
+        let offset = u32_at(&data, pointer).unwrap_or(0) as usize;
+        if offset == 0 {
+            break;
+        }
+        if chain.contains(&offset) || !fits(&data, offset) {
+            if pointer == 4 {
+                return Err(RawFileError::format(FormatErrorKind::Truncated, "IFD0 beyond end of file"));
+            }
+            fixes.push(Fix { offset: pointer as u64, msg: format!("pointer to next IFD 0x{:x} cleared", offset) });
+            data[pointer..pointer + 4].copy_from_slice(&[0; 4]);
+            break;
+        }
+        chain.push(offset);
+        pointer = remove_duplicates(&mut data, offset, &mut fixes);
+    }
+    if chain.is_empty() {
+        return Err(RawFileError::format(FormatErrorKind::Missing, "no IFD0"));
+    }
+    for &id in &[0x8769, 0x8825] {
+        let sub = find_entry(&data, chain[0], id).and_then(|e| u32_at(&data, e + 8)).unwrap_or(0) as usize;
+        if sub != 0 && fits(&data, sub) {
+            remove_duplicates(&mut data, sub, &mut fixes);
+        }
+    }
+    for &ifd in &chain {
+        clamp_lengths(&mut data, ifd, 0x111, 0x117, "strip_byte_count", &mut fixes);
+        clamp_lengths(&mut data, ifd, 0x201, 0x202, "thumbnail_length", &mut fixes);
+    }
+    try!(cr2::open_bytes(data.clone(), "repaired".to_string()));
+    Ok((data, fixes))
+}
+
+/// Writes a repaired copy of a file, the original is not changed
+///
+/// The copy is written through a temporary file, so dest may also be the
+/// original.
+pub fn repair_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> Result<Vec<Fix>,RawFileError> {
+    let mut data = Vec::new();
+    try!(try!(File::open(src)).read_to_end(&mut data));
+    let (data, fixes) = try!(repair(data));
+    try!(source::replace_file(dest.as_ref(), |f| f.write_all(&data)));
+    Ok(fixes)
+}
+
+#[test]
+fn test_repair() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 1, 2]))
+        .ifd(Ifd::new().tag(Tag::short(0x103, &[6])).tag(Tag::short(0x103, &[1])))
+        .build();
+    assert_eq!(1, repair(f.clone()).unwrap().1.len());
+    let image = cr2::open_bytes(f.clone(), "a".to_string()).unwrap();
+    let ifds = image.ifds();
+    let ifd0 = ifds.iter().find(|i| i.0 == "ifd0").unwrap().2;
+    let ifd1 = ifds.iter().find(|i| i.0 == "ifd1").unwrap().2.offset;
+    let mut bad = f.clone();
+    // strip length beyond end of file and next pointer after IFD1 beyond it
+    let p = ifd0.pos["strip_byte_count"].offset as usize;
+    bad[p..p + 4].copy_from_slice(&1000u32.to_le_bytes());
+    bad[ifd1 + 2 + 24..ifd1 + 2 + 28].copy_from_slice(&0x10000u32.to_le_bytes());
+    let (fixed, fixes) = repair(bad).unwrap();
+    assert_eq!(3, fixes.len(), "{:?}", fixes);
+    let image = cr2::open_bytes(fixed, "fixed".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert!(image.preview().is_ok());
+    assert_eq!(2, image.ifds().iter().filter(|i| i.0.starts_with("ifd")).count());
+    let ifd1 = image.ifds().into_iter().find(|i| i.0 == "ifd1").unwrap().2;
+    assert_eq!(Some(6), ifd1.tags["compression"][0].as_u32());
+}
+
+#[test]
+fn test_repair_without_ifd0() {
+    for data in &[&b"II*\0\0\0\0\0"[..], &b"II*\0"[..], &b"II*\0\x08"[..]] {
+        match repair(data.to_vec()) {
+            Err(RawFileError::FileFormat { ref msg, .. }) => assert_eq!("no IFD0", msg),
+            r => panic!("{:?}", r)
+        }
+    }
+}
+
+#[test]
+fn test_repair_file() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let mut f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x110, "Canon EOS 6D")).tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8]))
+        .build();
+    f.extend_from_slice(b"tail");
+    let path = ::std::env::temp_dir().join(format!("rraw-repair-{}.cr2", ::std::process::id()));
+    ::std::fs::write(&path, &f).unwrap();
+    // repairing in place
+    assert_eq!(1, repair_file(&path, &path).unwrap().len());
+    let image = cr2::open(path.to_str().unwrap().to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(f.len(), ::std::fs::read(&path).unwrap().len());
+    ::std::fs::remove_file(&path).unwrap();
+}
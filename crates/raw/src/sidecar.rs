@@ -0,0 +1,476 @@
+use std::fs::File;
+use std::io::{self,Read,Seek,SeekFrom,Write};
+use std::path::Path;
+use cache;
+use cr2::{self,RawFileError,RawImage,TagData,TagPos,IfdKind,Layout,FormatErrorKind};
+use hash::Sha256;
+
+/// Metadata sidecar: everything open() parses from a raw file, so it can
+/// be reloaded without reading the file again
+///
+/// The format starts with MAGIC and VERSION, followed by flags, the raw
+/// offset, the file name, the lossless JPEG format and the IFDs with the
+/// position and values of each tag. Integers are LEB128 varints, signed
+/// ones zigzag encoded. Tag names are not stored but derived from the ids.
+const MAGIC: &'static [u8] = b"RRSC";
+const VERSION: u8 = 1;
+
+const BIG_ENDIAN: u8 = 1;
+const RAW_FORMAT: u8 = 2;
+
+fn put(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn put_signed(out: &mut Vec<u8>, v: i64) {
+    put(out, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn kind_code(kind: IfdKind) -> u8 {
+    match kind {
+        IfdKind::Tiff => 0,
+        IfdKind::Exif => 1,
+        IfdKind::Gps => 2,
+        IfdKind::Canon => 3
+    }
+}
+
+/// Serializes the parsed metadata of an image
+pub fn encode(image: &RawImage) -> Vec<u8> {
+    let (big_endian, raw_offset, raw_format) = image.layout_state();
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.push(if big_endian { BIG_ENDIAN } else { 0 } | if raw_format.is_some() { RAW_FORMAT } else { 0 });
+    put(&mut out, raw_offset as u64);
+    put_str(&mut out, &image.file_name().to_string_lossy());
+    if let Some((width, height, bits)) = raw_format {
+        put(&mut out, width as u64);
+        put(&mut out, height as u64);
+        put(&mut out, bits as u64);
+    }
+    let ifds = image.ifds();
+    put(&mut out, ifds.len() as u64);
+    for (_, kind, ifd) in ifds {
+        out.push(kind_code(kind));
+        put(&mut out, ifd.offset as u64);
+        // in file order, so the output does not depend on hashing
+        let mut tags: Vec<(&String, &TagPos)> = ifd.pos.iter().collect();
+        tags.sort_by_key(|t| t.1.entry);
+        put(&mut out, tags.len() as u64);
+        for (name, pos) in tags {
+            put(&mut out, pos.id as u64);
+            put(&mut out, pos.tagtype as u64);
+            put(&mut out, pos.count as u64);
+            put(&mut out, pos.entry);
+            put(&mut out, pos.offset);
+            // values of removed tags are missing, their count is 0
+            let values = match ifd.tags.get(name) {
+                Some(v) => v,
+                None => { put(&mut out, 0); continue; }
+            };
+            put(&mut out, values.len() as u64 + 1);
+            for v in values {
+                match *v {
+                    TagData::Unsigned(u) => { out.push(0); put(&mut out, u as u64); },
+                    TagData::Signed(i) => { out.push(1); put_signed(&mut out, i as i64); },
+                    TagData::U64(u) => { out.push(2); put(&mut out, u); },
+                    TagData::I64(i) => { out.push(3); put_signed(&mut out, i); },
+                    TagData::Strg(ref s) => { out.push(4); put_str(&mut out, s); },
+                    TagData::Float(f) => { out.push(5); out.extend_from_slice(&f.to_bits().to_le_bytes()); }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Reader over sidecar data, running past the end is a format error
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8],RawFileError> {
+        if n > self.data.len() - self.pos {
+            return Err(RawFileError::format(FormatErrorKind::Truncated, "Sidecar truncated"));
+        }
+        self.pos += n;
+        Ok(&self.data[self.pos - n..self.pos])
+    }
+
+    fn byte(&mut self) -> Result<u8,RawFileError> {
+        Ok(try!(self.bytes(1))[0])
+    }
+
+    fn get(&mut self) -> Result<u64,RawFileError> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = try!(self.byte());
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(RawFileError::format(FormatErrorKind::Invalid, "Sidecar varint too long"))
+    }
+
+    fn get_signed(&mut self) -> Result<i64,RawFileError> {
+        let v = try!(self.get());
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    /// A count of items taking at least one byte each
+    fn count(&mut self) -> Result<usize,RawFileError> {
+        let n = try!(self.get());
+        if n > (self.data.len() - self.pos) as u64 {
+            return Err(RawFileError::format(FormatErrorKind::Truncated, "Sidecar truncated"));
+        }
+        Ok(n as usize)
+    }
+
+    fn str(&mut self) -> Result<String,RawFileError> {
+        let n = try!(self.count());
+        Ok(try!(::std::str::from_utf8(try!(self.bytes(n)))).to_string())
+    }
+}
+
+/// Rebuilds an image from a sidecar
+///
+/// Metadata accessors work without the raw file. Reading previews or
+/// raw data opens the file by the name it was parsed from.
+pub fn decode(data: &[u8]) -> Result<RawImage,RawFileError> {
+    if !data.starts_with(MAGIC) {
+        return Err(RawFileError::format(FormatErrorKind::BadMagic, "Not a metadata sidecar"));
+    }
+    let mut c = Cursor { data: data, pos: MAGIC.len() };
+    let version = try!(c.byte());
+    if version != VERSION {
+        return Err(RawFileError::NotImplemented(format!("Sidecar version {}", version)));
+    }
+    let flags = try!(c.byte());
+    let raw_offset = try!(c.get()) as usize;
+    let file_name = try!(c.str());
+    let raw_format = if flags & RAW_FORMAT != 0 {
+        Some((try!(c.get()) as u32, try!(c.get()) as u32, try!(c.get()) as u16))
+    } else {
+        None
+    };
+    let mut ifds = Vec::new();
+    for _ in 0..try!(c.count()) {
+        let kind = match try!(c.byte()) {
+            0 => IfdKind::Tiff,
+            1 => IfdKind::Exif,
+            2 => IfdKind::Gps,
+            3 => IfdKind::Canon,
+            k => return Err(RawFileError::format(FormatErrorKind::Invalid, format!("Sidecar IFD kind {}", k)))
+        };
+        let mut ifd = cr2::Ifd::new(try!(c.get()) as usize);
+        for _ in 0..try!(c.count()) {
+            let id = try!(c.get()) as u16;
+            let pos = TagPos { id: id, tagtype: try!(c.get()) as u16, count: try!(c.get()) as usize,
+                               entry: try!(c.get()), offset: try!(c.get()) };
+            let name = cr2::tag_key(kind, id);
+            let n = try!(c.count());
+            if n > 0 {
+                let mut values = Vec::with_capacity(n - 1);
+                for _ in 1..n {
+                    values.push(match try!(c.byte()) {
+                        0 => TagData::Unsigned(try!(c.get()) as u32),
+                        1 => TagData::Signed(try!(c.get_signed()) as i32),
+                        2 => TagData::U64(try!(c.get())),
+                        3 => TagData::I64(try!(c.get_signed())),
+                        4 => TagData::Strg(try!(c.str())),
+                        5 => {
+                            let b = try!(c.bytes(8));
+                            TagData::Float(f64::from_bits(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])))
+                        },
+                        t => return Err(RawFileError::format(FormatErrorKind::Invalid, format!("Sidecar value type {}", t)))
+                    });
+                }
+                ifd.tags.insert(name.clone(), values);
+            }
+            ifd.pos.insert(name, pos);
+        }
+        ifds.push((kind, ifd));
+    }
+    Ok(RawImage::from_state(file_name, flags & BIG_ENDIAN != 0, raw_offset, raw_format, ifds))
+}
+
+/// Writes the sidecar of an image to a file
+pub fn save<P: AsRef<Path>>(image: &RawImage, path: P) -> io::Result<()> {
+    try!(File::create(path)).write_all(&encode(image))
+}
+
+/// Loads an image from a sidecar file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<RawImage,RawFileError> {
+    let mut data = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut data));
+    decode(&data)
+}
+
+/// How a file changed since its snapshot
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Change {
+    /// Same modification time and size, the file was not read
+    Unchanged,
+    /// Modification time or size differ but the metadata is the same,
+    /// e.g. the file was copied or touched
+    Touched,
+    /// Values of some IFDs changed in place, only those were read again
+    Updated,
+    /// The structure of the metadata changed and the file was parsed again
+    Reparsed
+}
+
+/// A part of the metadata with the ranges of the file it was read from
+/// and their hash
+#[derive(Debug,Clone,PartialEq)]
+struct Part {
+    /// IFD name as in tag lists, "header" or "raw_header"
+    name: String,
+    ranges: Vec<(u64, u64)>,
+    /// SHA-256 of the ranges
+    digest: [u8; 32]
+}
+
+/// Parsed metadata of a file with its modification time and size, to
+/// detect changes in repeated scans of a library
+pub struct Snapshot {
+    pub image: RawImage,
+    /// Modification time in nanoseconds
+    pub mtime: u128,
+    pub size: u64,
+    parts: Vec<Part>
+}
+
+/// Bytes of the raw data open() reads for the lossless JPEG header
+const RAW_HEADER: u64 = 1024;
+
+/// Ranges (start, length) of the file open() reads by part: the header,
+/// each IFD with its table and the values outside the entries, and the
+/// header of the raw data
+fn metadata_ranges(image: &RawImage) -> Result<Vec<(String, Vec<(u64, u64)>)>,RawFileError> {
+    let mut parts = vec![("header".to_string(), vec![(0, 16)])];
+    for (name, _, ifd) in image.ifds() {
+        // the table as stored, Ifd::pos leaves out skipped entries
+        let entries = try!(image.entry_count(ifd)) as u64;
+        let mut ranges = vec![(ifd.offset as u64, 2 + entries * 12 + 4)];
+        for pos in ifd.pos.values() {
+            if pos.offset < pos.entry || pos.offset > pos.entry + 8 {
+                ranges.push((pos.offset, cr2::type_size(pos.tagtype).unwrap_or(1) as u64 * pos.count as u64));
+            }
+        }
+        ranges.sort();
+        ranges.dedup();
+        parts.push((name, ranges));
+    }
+    if let Some(Layout::Strips(ref s)) = image.raw_layout() {
+        if let Some(&(offset, _)) = s.first() {
+            parts.push(("raw_header".to_string(), vec![(offset, RAW_HEADER)]));
+        }
+    }
+    Ok(parts)
+}
+
+/// Hash of ranges of a file, parts beyond the end of the file are left out
+fn digest(f: &mut File, ranges: &[(u64, u64)]) -> io::Result<[u8; 32]> {
+    let mut h = Sha256::default();
+    for &(start, len) in ranges {
+        try!(f.seek(SeekFrom::Start(start)));
+        let mut buf = Vec::new();
+        try!((&mut *f).take(len).read_to_end(&mut buf));
+        h.update(&(buf.len() as u64).to_le_bytes());
+        h.update(&buf);
+    }
+    Ok(h.finish())
+}
+
+/// The metadata parts of an image with the hashes of the file at path
+fn parts(image: &RawImage, path: &str) -> Result<Vec<Part>,RawFileError> {
+    let mut f = try!(File::open(path));
+    let mut parts = Vec::new();
+    for (name, ranges) in try!(metadata_ranges(image)) {
+        let digest = try!(digest(&mut f, &ranges));
+        parts.push(Part { name: name, ranges: ranges, digest: digest });
+    }
+    Ok(parts)
+}
+
+impl Snapshot {
+    /// Parses a file and records its state
+    pub fn take(path: &str) -> Result<Snapshot,RawFileError> {
+        let (mtime, size) = try!(cache::stat(path));
+        let image = try!(cr2::open(path.to_string()));
+        let parts = try!(parts(&image, path));
+        Ok(Snapshot { image: image, mtime: mtime, size: size, parts: parts })
+    }
+
+    /// Serializes the snapshot, the modification time, size and metadata
+    /// parts followed by the sidecar of the image
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.mtime.to_le_bytes().to_vec();
+        out.extend_from_slice(&self.size.to_le_bytes());
+        put(&mut out, self.parts.len() as u64);
+        for p in &self.parts {
+            put_str(&mut out, &p.name);
+            put(&mut out, p.ranges.len() as u64);
+            for &(start, len) in &p.ranges {
+                put(&mut out, start);
+                put(&mut out, len);
+            }
+            out.extend_from_slice(&p.digest);
+        }
+        out.extend_from_slice(&encode(&self.image));
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Snapshot,RawFileError> {
+        if data.len() < 24 {
+            return Err(RawFileError::format(FormatErrorKind::Truncated, "Snapshot truncated"));
+        }
+        let mut mtime = [0; 16];
+        mtime.copy_from_slice(&data[..16]);
+        let mut size = [0; 8];
+        size.copy_from_slice(&data[16..24]);
+        let mut c = Cursor { data: data, pos: 24 };
+        let mut parts = Vec::new();
+        for _ in 0..try!(c.count()) {
+            let name = try!(c.str());
+            let mut ranges = Vec::new();
+            for _ in 0..try!(c.count()) {
+                ranges.push((try!(c.get()), try!(c.get())));
+            }
+            let mut digest = [0; 32];
+            digest.copy_from_slice(try!(c.bytes(32)));
+            parts.push(Part { name: name, ranges: ranges, digest: digest });
+        }
+        Ok(Snapshot { image: try!(decode(&data[c.pos..])), mtime: u128::from_le_bytes(mtime),
+                      size: u64::from_le_bytes(size), parts: parts })
+    }
+
+    /// Checks the file at path against the snapshot
+    ///
+    /// Only the modification time and size are compared if they are
+    /// unchanged. Otherwise only the metadata ranges of the snapshot are
+    /// read and hashed. IFDs whose ranges differ are read again if they
+    /// still point to the same data, else the file is parsed again.
+    /// Returns the updated snapshot.
+    pub fn rescan(self, path: &str) -> Result<(Snapshot, Change),RawFileError> {
+        let (mtime, size) = try!(cache::stat(path));
+        if mtime == self.mtime && size == self.size {
+            return Ok((self, Change::Unchanged));
+        }
+        let mut f = try!(File::open(path));
+        let mut changed = Vec::new();
+        for p in &self.parts {
+            if try!(digest(&mut f, &p.ranges)) != p.digest {
+                changed.push(p.name.clone());
+            }
+        }
+        let mut image = self.image;
+        image.set_file_name(path.to_string());
+        if changed.is_empty() {
+            return Ok((Snapshot { image: image, mtime: mtime, size: size, parts: self.parts }, Change::Touched));
+        }
+        for name in &changed {
+            // the header and the raw header are not part of an IFD
+            if !image.reread_ifd(name).unwrap_or(false) {
+                return Ok((try!(Snapshot::take(path)), Change::Reparsed));
+            }
+        }
+        let parts = try!(parts(&image, path));
+        Ok((Snapshot { image: image, mtime: mtime, size: size, parts: parts }, Change::Updated))
+    }
+}
+
+#[test]
+fn test_sidecar() {
+    use synth::{Cr2Builder,Ifd,Tag};
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x110, "Canon EOS 6D"))
+             .tag(Tag::short(0x112, &[6])).tag(Tag::short(0x9999, &[1, 2])).strip(&[0xff, 0xd8, 1, 2]))
+        .build();
+    let image = cr2::open_bytes(f, "a.cr2".to_string()).unwrap();
+    let data = encode(&image);
+    let copy = decode(&data).unwrap();
+    assert_eq!(Path::new("a.cr2"), copy.file_name());
+    assert_eq!(image.model(), copy.model());
+    assert_eq!(image.orientation(), copy.orientation());
+    assert_eq!(image.layout_state(), copy.layout_state());
+    let dump = |image: &RawImage| image.ifds().iter().map(|&(ref name, _, ifd)| {
+        let mut tags: Vec<String> = ifd.tags.iter().map(|(k, v)| format!("{} {:?} {}", k, v, ifd.pos[k].entry)).collect();
+        tags.sort();
+        format!("{} {} {:?}", name, ifd.offset, tags)
+    }).collect::<Vec<_>>();
+    assert_eq!(dump(&image), dump(&copy));
+    assert_eq!(data, encode(&copy));
+    assert!(decode(&data[..data.len() - 1]).is_err());
+    assert!(decode(b"RRSC\x09").is_err());
+}
+
+#[test]
+fn test_rescan() {
+    use std::fs;
+    use std::time::{Duration,SystemTime};
+    use synth::{Cr2Builder,Ifd,Tag};
+    let build = |model, orientation| Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x110, model))
+             .tag(Tag::short(0x9999, &[1])).tag(Tag::short(0x9999, &[2])).tag(Tag::short(0x112, &[orientation]))
+             .strip(&[0xff, 0xd8, 1, 2]))
+        .build();
+    let path = ::std::env::temp_dir().join(format!("rraw-rescan-{}.cr2", ::std::process::id()));
+    let p = path.to_str().unwrap();
+    // each write gets its own modification time, file systems may not
+    // resolve the time between them
+    let write = |data: Vec<u8>, secs| {
+        fs::write(&path, data).unwrap();
+        File::options().write(true).open(&path).unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    };
+    write(build("Canon EOS 6D", 1), 1000);
+    let snapshot = Snapshot::decode(&Snapshot::take(p).unwrap().encode()).unwrap();
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Unchanged, change);
+    // same metadata, different pixels
+    let mut f = build("Canon EOS 6D", 1);
+    let strip = f.windows(4).position(|w| w == [0xff, 0xd8, 1, 2]).unwrap();
+    f[strip + 3] = 3;
+    write(f, 2000);
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Touched, change);
+    // the last entry follows a repeated one, which IFD::pos leaves out
+    write(build("Canon EOS 6D", 8), 3000);
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Updated, change);
+    assert_eq!(Some(8), snapshot.image.orientation());
+    write(build("Canon EOS 5D", 8), 4000);
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Updated, change);
+    assert_eq!(Some("Canon EOS 5D"), snapshot.image.model());
+    // values of another length are read again in place
+    write(build("Canon EOS 5D Mark IV", 8), 5000);
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Updated, change);
+    assert_eq!(Some("Canon EOS 5D Mark IV"), snapshot.image.model());
+    // a new IFD in the chain
+    let f = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x110, "Canon EOS 5D Mark IV"))
+             .tag(Tag::short(0x9999, &[1])).tag(Tag::short(0x9999, &[2])).tag(Tag::short(0x112, &[8]))
+             .strip(&[0xff, 0xd8, 1, 2]))
+        .ifd(Ifd::new().thumbnail(&[0xff, 0xd8, 3]));
+    write(f.build(), 6000);
+    let (snapshot, change) = snapshot.rescan(p).unwrap();
+    assert_eq!(Change::Reparsed, change);
+    assert_eq!(vec![0xff, 0xd8, 3], snapshot.image.thumbnail().unwrap());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,215 @@
+use std::ffi::OsString;
+use std::fs::{self,File};
+use std::io::{self,Read,Seek,SeekFrom};
+use std::path::{Path,PathBuf};
+use std::process;
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+/// Random access storage a raw file is read from
+///
+/// Implement this for other backends, e.g. object stores, to open files
+/// with cr2::open_source.
+pub trait RawSource: Send + Sync {
+    /// Reads up to buf.len() bytes at offset, returns the number of bytes
+    /// read. Fewer bytes are only returned at the end of the source.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Length of the source in bytes
+    fn len(&self) -> io::Result<u64>;
+}
+
+/// A local file
+pub struct FileSource {
+    file: Mutex<File>
+}
+
+impl FileSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileSource> {
+        Ok(FileSource { file: Mutex::new(try!(File::open(path))) })
+    }
+}
+
+impl RawSource for FileSource {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut f = self.file.lock().unwrap();
+        try!(f.seek(SeekFrom::Start(offset)));
+        let mut n = 0;
+        while n < buf.len() {
+            match f.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(m) => n += m,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(n)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(try!(self.file.lock().unwrap().metadata()).len())
+    }
+}
+
+/// A file held in memory
+impl RawSource for Vec<u8> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = ::std::cmp::min(offset, self.len() as u64) as usize;
+        let n = ::std::cmp::min(buf.len(), self.len() - start);
+        buf[..n].copy_from_slice(&self[start..start + n]);
+        Ok(n)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+}
+
+/// A stream that cannot seek, e.g. a pipe or a tar entry, read as far as
+/// the parser needs
+///
+/// The stream is buffered up to the furthest offset read, at most limit
+/// bytes. Data beyond the limit reads as the end of the source, so the
+/// metadata at the start of a file is parsed without buffering the image
+/// data. The length is the limit until the stream ends.
+pub struct StreamSource<R> {
+    state: Mutex<(R, Vec<u8>, bool)>,
+    limit: u64
+}
+
+impl<R: Read> StreamSource<R> {
+    pub fn new(stream: R, limit: u64) -> StreamSource<R> {
+        StreamSource { state: Mutex::new((stream, Vec::new(), false)), limit: limit }
+    }
+
+    /// Number of bytes read from the stream so far
+    pub fn buffered(&self) -> usize {
+        self.state.lock().unwrap().1.len()
+    }
+}
+
+impl<R: Read + Send> RawSource for StreamSource<R> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let (ref mut stream, ref mut data, ref mut eof) = *state;
+        let want = ::std::cmp::min(offset + buf.len() as u64, self.limit);
+        let mut chunk = vec![0u8; 1 << 16];
+        while (data.len() as u64) < want && !*eof {
+            let n = ::std::cmp::min(chunk.len() as u64, want - data.len() as u64) as usize;
+            match stream.read(&mut chunk[..n]) {
+                Ok(0) => *eof = true,
+                Ok(m) => data.extend_from_slice(&chunk[..m]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+        let start = ::std::cmp::min(offset, data.len() as u64) as usize;
+        let n = ::std::cmp::min(buf.len(), ::std::cmp::min(want as usize, data.len()).saturating_sub(start));
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        let state = self.state.lock().unwrap();
+        Ok(if state.2 { state.1.len() as u64 } else { self.limit })
+    }
+}
+
+/// Writes a file through a temporary copy in the same directory that
+/// replaces it
+///
+/// Readers never see partial data and the old file is intact if writing
+/// fails, the copy is then removed. Each call uses its own temporary
+/// name, so concurrent writers of a file do not collide.
+pub(crate) fn replace_file<E, F>(path: &Path, write: F) -> Result<(),E>
+    where E: From<io::Error>, F: FnOnce(&mut File) -> Result<(),E>
+{
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let mut tmp: OsString = path.as_os_str().to_owned();
+    tmp.push(format!(".{}-{}.tmp", process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+    let tmp = PathBuf::from(tmp);
+    let res = File::create(&tmp).map_err(E::from)
+        .and_then(|mut f| write(&mut f).and_then(|_| f.sync_all().map_err(E::from)))
+        .and_then(|_| fs::rename(&tmp, path).map_err(E::from));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Sequential reader over a source, used by the parser
+pub(crate) struct SourceReader {
+    source: Arc<dyn RawSource>,
+    pos: u64
+}
+
+impl SourceReader {
+    pub(crate) fn new(source: Arc<dyn RawSource>) -> SourceReader {
+        SourceReader { source: source, pos: 0 }
+    }
+}
+
+impl Read for SourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.source.read_at(self.pos, buf));
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SourceReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let p = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::End(d) => try!(self.source.len()) as i64 + d,
+            SeekFrom::Current(d) => self.pos as i64 + d
+        };
+        if p < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of source"));
+        }
+        self.pos = p as u64;
+        Ok(self.pos)
+    }
+}
+
+#[test]
+fn test_source_reader() {
+    let mut r = SourceReader::new(Arc::new(vec![1u8, 2, 3, 4, 5]));
+    let mut b = [0u8; 3];
+    r.seek(SeekFrom::End(-2)).unwrap();
+    assert_eq!(2, r.read(&mut b).unwrap());
+    assert_eq!([4, 5], b[..2]);
+    assert_eq!(0, r.read(&mut b).unwrap());
+    r.seek(SeekFrom::Start(1)).unwrap();
+    r.read_exact(&mut b).unwrap();
+    assert_eq!([2, 3, 4], b);
+}
+
+#[test]
+fn test_stream_source() {
+    let s = StreamSource::new(&[1u8, 2, 3, 4, 5, 6][..], 4);
+    let mut b = [0u8; 2];
+    assert_eq!(2, s.read_at(1, &mut b).unwrap());
+    assert_eq!(([2, 3], 3), (b, s.buffered()));
+    assert_eq!(4, s.len().unwrap());
+    assert_eq!(1, s.read_at(3, &mut b).unwrap());
+    assert_eq!(0, s.read_at(5, &mut b).unwrap());
+    let s = StreamSource::new(&[1u8, 2][..], 100);
+    assert_eq!(2, s.read_at(0, &mut [0u8; 8]).unwrap());
+    assert_eq!(2, s.len().unwrap());
+}
+
+#[test]
+fn test_replace_file() {
+    let path = ::std::env::temp_dir().join(format!("rraw-replace-{}.bin", process::id()));
+    fs::write(&path, b"old").unwrap();
+    let r: io::Result<()> = replace_file(&path, |f| { try!(io::Write::write_all(f, b"new")); Err(io::Error::new(io::ErrorKind::Other, "failed")) });
+    assert!(r.is_err());
+    assert_eq!(b"old".to_vec(), fs::read(&path).unwrap());
+    replace_file(&path, |f| io::Write::write_all(f, b"new")).unwrap();
+    assert_eq!(b"new".to_vec(), fs::read(&path).unwrap());
+    let dir: Vec<_> = fs::read_dir(::std::env::temp_dir()).unwrap().filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(&format!("rraw-replace-{}.bin.", process::id()))).collect();
+    assert!(dir.is_empty());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,276 @@
+// Generator for small CR2 files used in tests
+
+/// A tag with its value in file byte order
+///
+/// The count is written as given, so tags can claim more values than
+/// they hold. Values longer than 4 bytes are stored after the IFD,
+/// shorter ones go into the entry, where they act as offset if the
+/// count claims more than 4 bytes.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Tag {
+    pub id: u16,
+    pub tagtype: u16,
+    pub count: u32,
+    pub value: Vec<u8>
+}
+
+impl Tag {
+    pub fn new(id: u16, tagtype: u16, count: u32, value: Vec<u8>) -> Tag {
+        Tag { id: id, tagtype: tagtype, count: count, value: value }
+    }
+
+    /// NUL terminated string
+    pub fn ascii(id: u16, s: &str) -> Tag {
+        let mut v: Vec<u8> = From::from(s.as_bytes());
+        v.push(0);
+        Tag::new(id, 2, v.len() as u32, v)
+    }
+
+    pub fn bytes(id: u16, tagtype: u16, v: &[u8]) -> Tag {
+        Tag::new(id, tagtype, v.len() as u32, v.to_vec())
+    }
+
+    pub fn short(id: u16, v: &[u16]) -> Tag {
+        Tag::new(id, 3, v.len() as u32, v.iter().flat_map(|s| s.to_le_bytes().to_vec()).collect())
+    }
+
+    pub fn long(id: u16, v: &[u32]) -> Tag {
+        Tag::new(id, 4, v.len() as u32, v.iter().flat_map(|l| l.to_le_bytes().to_vec()).collect())
+    }
+
+    /// Rationals as (numerator, denominator)
+    pub fn rational(id: u16, v: &[(u32, u32)]) -> Tag {
+        Tag::new(id, 5, v.len() as u32, v.iter().flat_map(|r| {
+            let mut b = r.0.to_le_bytes().to_vec();
+            b.extend_from_slice(&r.1.to_le_bytes());
+            b
+        }).collect())
+    }
+
+    pub fn srational(id: u16, v: &[(i32, i32)]) -> Tag {
+        let mut t = Tag::rational(id, &v.iter().map(|r| (r.0 as u32, r.1 as u32)).collect::<Vec<_>>());
+        t.tagtype = 10;
+        t
+    }
+}
+
+/// An IFD, the strip, tile and thumbnail data are referenced by the usual tags
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Ifd {
+    pub tags: Vec<Tag>,
+    /// Stored in strip_offset/strip_byte_count
+    pub strip: Option<Vec<u8>>,
+    /// Tile width and length, and the tiles stored in tile_offset/tile_byte_count
+    pub tiles: Option<(u32, u32, Vec<Vec<u8>>)>,
+    /// Stored in thumbnail_offset/thumbnail_length
+    pub thumbnail: Option<Vec<u8>>
+}
+
+impl Ifd {
+    pub fn new() -> Ifd {
+        Ifd::default()
+    }
+
+    pub fn tag(mut self, tag: Tag) -> Ifd {
+        self.tags.push(tag);
+        self
+    }
+
+    pub fn strip(mut self, data: &[u8]) -> Ifd {
+        self.strip = Some(data.to_vec());
+        self
+    }
+
+    pub fn tiles(mut self, width: u32, length: u32, tiles: &[&[u8]]) -> Ifd {
+        self.tiles = Some((width, length, tiles.iter().map(|t| t.to_vec()).collect()));
+        self
+    }
+
+    pub fn thumbnail(mut self, data: &[u8]) -> Ifd {
+        self.thumbnail = Some(data.to_vec());
+        self
+    }
+}
+
+/// Builds a CR2 file from IFDs
+///
+/// The EXIF and GPS IFDs are referenced from IFD0, the MakerNote is
+/// stored as value of the EXIF maker_note tag.
+#[derive(Debug,Clone,Default)]
+pub struct Cr2Builder {
+    ifds: Vec<Ifd>,
+    exif: Option<Ifd>,
+    gps: Option<Ifd>,
+    makernote: Option<Ifd>,
+    raw_ifd: Option<usize>,
+    misalign: bool
+}
+
+impl Cr2Builder {
+    pub fn new() -> Cr2Builder {
+        Cr2Builder::default()
+    }
+
+    /// Appends an IFD to the chain
+    pub fn ifd(mut self, ifd: Ifd) -> Cr2Builder {
+        self.ifds.push(ifd);
+        self
+    }
+
+    pub fn exif(mut self, ifd: Ifd) -> Cr2Builder {
+        self.exif = Some(ifd);
+        self
+    }
+
+    pub fn gps(mut self, ifd: Ifd) -> Cr2Builder {
+        self.gps = Some(ifd);
+        self
+    }
+
+    pub fn makernote(mut self, ifd: Ifd) -> Cr2Builder {
+        self.makernote = Some(ifd);
+        self
+    }
+
+    /// Index of the IFD with the raw data, the header points to it
+    pub fn raw_ifd(mut self, index: usize) -> Cr2Builder {
+        self.raw_ifd = Some(index);
+        self
+    }
+
+    /// Places IFDs and values at odd offsets, TIFF requires even ones
+    pub fn misalign(mut self, misalign: bool) -> Cr2Builder {
+        self.misalign = misalign;
+        self
+    }
+
+    /// Pads the file to an even offset, or an odd one if misaligned
+    fn align(&self, out: &mut Vec<u8>) {
+        if (out.len() % 2 == 1) != self.misalign {
+            out.push(0);
+        }
+    }
+
+    /// Writes the data of the strip, tiles and thumbnail, returns their tags
+    fn write_data(&self, out: &mut Vec<u8>, ifd: &Ifd) -> Vec<Tag> {
+        let mut tags = Vec::new();
+        for &(data, offset, length) in &[(&ifd.strip, 0x111, 0x117), (&ifd.thumbnail, 0x201, 0x202)] {
+            if let Some(ref d) = *data {
+                self.align(out);
+                tags.push(Tag::long(offset, &[out.len() as u32]));
+                tags.push(Tag::long(length, &[d.len() as u32]));
+                out.extend_from_slice(d);
+            }
+        }
+        if let Some((width, length, ref tiles)) = ifd.tiles {
+            let mut offsets = Vec::new();
+            for t in tiles {
+                self.align(out);
+                offsets.push(out.len() as u32);
+                out.extend_from_slice(t);
+            }
+            tags.push(Tag::long(0x142, &[width]));
+            tags.push(Tag::long(0x143, &[length]));
+            tags.push(Tag::long(0x144, &offsets));
+            tags.push(Tag::long(0x145, &tiles.iter().map(|t| t.len() as u32).collect::<Vec<_>>()));
+        }
+        tags
+    }
+
+    /// Writes an IFD followed by its values, returns its offset
+    fn write_ifd(&self, out: &mut Vec<u8>, tags: &[Tag]) -> u32 {
+        let mut tags = tags.to_vec();
+        tags.sort_by_key(|t| t.id);
+        self.align(out);
+        let start = out.len();
+        let mut values = Vec::new();
+        let mut value_offset = start + 2 + tags.len() * 12 + 4;
+        out.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        for t in &tags {
+            out.extend_from_slice(&t.id.to_le_bytes());
+            out.extend_from_slice(&t.tagtype.to_le_bytes());
+            out.extend_from_slice(&t.count.to_le_bytes());
+            if t.value.len() <= 4 {
+                let mut field = t.value.clone();
+                field.resize(4, 0);
+                out.extend_from_slice(&field);
+            } else {
+                if (value_offset % 2 == 1) != self.misalign {
+                    values.push(0);
+                    value_offset += 1;
+                }
+                out.extend_from_slice(&(value_offset as u32).to_le_bytes());
+                values.extend_from_slice(&t.value);
+                value_offset += t.value.len();
+            }
+        }
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&values);
+        start as u32
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = b"II*\0\0\0\0\0CR\x02\0\0\0\0\0".to_vec();
+        // IFD0 is always written, the sub IFDs are referenced from it
+        let chain = if self.ifds.is_empty() { vec![Ifd::new()] } else { self.ifds.clone() };
+        let mut chain_tags: Vec<Vec<Tag>> = chain.iter().map(|i| self.write_data(&mut out, i)).collect();
+        let mut exif = self.exif.clone();
+        if let Some(ref mn) = self.makernote {
+            let mut tags = self.write_data(&mut out, mn);
+            tags.extend_from_slice(&mn.tags);
+            let offset = self.write_ifd(&mut out, &tags);
+            let len = out.len() as u32 - offset;
+            // the field of the maker_note tag points at the MakerNote IFD
+            let exif = exif.get_or_insert_with(Ifd::new);
+            exif.tags.retain(|t| t.id != 0x927c);
+            exif.tags.push(Tag::new(0x927c, 7, len, offset.to_le_bytes().to_vec()));
+        }
+        for &(ifd, pointer) in &[(&exif, 0x8769), (&self.gps, 0x8825)] {
+            if let Some(ref ifd) = *ifd {
+                let mut tags = self.write_data(&mut out, ifd);
+                tags.extend_from_slice(&ifd.tags);
+                let offset = self.write_ifd(&mut out, &tags);
+                chain_tags[0].push(Tag::long(pointer, &[offset]));
+            }
+        }
+        // the offset of each IFD goes into the header or the previous IFD
+        let mut next = 4;
+        let mut offsets = Vec::new();
+        for (ifd, mut tags) in chain.iter().zip(chain_tags.into_iter()) {
+            tags.extend_from_slice(&ifd.tags);
+            let offset = self.write_ifd(&mut out, &tags);
+            out[next..next + 4].copy_from_slice(&offset.to_le_bytes());
+            next = offset as usize + 2 + 12 * tags.len();
+            offsets.push(offset);
+        }
+        if let Some(o) = self.raw_ifd.and_then(|i| offsets.get(i)) {
+            out[12..16].copy_from_slice(&o.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[test]
+fn test_build() {
+    use cr2;
+    use validate;
+    let b = Cr2Builder::new()
+        .ifd(Ifd::new().tag(Tag::ascii(0x10f, "Canon")).tag(Tag::ascii(0x110, "Canon EOS 6D")).strip(&[0xff, 0xd8, 1]))
+        .ifd(Ifd::new().thumbnail(&[0xff, 0xd8, 2]))
+        .ifd(Ifd::new())
+        .ifd(Ifd::new().strip(&[1, 2, 3, 4, 5]))
+        .raw_ifd(3)
+        .exif(Ifd::new().tag(Tag::short(0x8827, &[800])).tag(Tag::rational(0x829a, &[(1, 250)])))
+        .gps(Ifd::new().tag(Tag::ascii(0x0001, "N")))
+        .makernote(Ifd::new().tag(Tag::ascii(0x0095, "EF50mm f/1.8 STM")));
+    let image = cr2::open_bytes(b.build(), "synth".to_string()).unwrap();
+    assert_eq!(4, image.ifds().iter().filter(|i| i.0.starts_with("ifd")).count());
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert_eq!(Some("EF50mm f/1.8 STM"), image.lens());
+    assert_eq!(Some(800), image.exif_summary().iso);
+    assert_eq!(vec![0xff, 0xd8, 2], image.thumbnail().unwrap());
+    assert!(validate::validate(&image).unwrap().is_empty());
+    let image = cr2::open_bytes(b.misalign(true).build(), "odd".to_string()).unwrap();
+    assert_eq!(Some("Canon EOS 6D"), image.model());
+    assert!(validate::validate(&image).unwrap().iter().any(|w| w.msg.contains("word boundary")));
+}
@@ -0,0 +1,53 @@
+use catalog::json_string;
+
+/// A step of the parse recorded in a trace
+#[derive(Debug,Clone,PartialEq)]
+pub enum Event {
+    /// An IFD with its number of entries and the offset of the next IFD
+    Ifd { name: String, offset: u64, entries: u16, next: u64 },
+    /// A tag, offset and size are those of its value
+    Tag { ifd: String, id: u16, tagtype: u16, count: usize, offset: u64, size: usize },
+    /// A problem the parser tolerated
+    Anomaly { offset: u64, msg: String }
+}
+
+/// Structured record of the parse of a file, see cr2::open_traced
+///
+/// Attach the JSON form to bug reports about files that are read incorrectly.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Trace {
+    pub events: Vec<Event>
+}
+
+impl Trace {
+    pub fn anomalies(&self) -> Vec<&Event> {
+        self.events.iter().filter(|e| match **e { Event::Anomaly{..} => true, _ => false }).collect()
+    }
+
+    /// Exports the trace as JSON array of events
+    pub fn to_json(&self) -> String {
+        let items: Vec<String> = self.events.iter().map(|e| match *e {
+            Event::Ifd{ref name,offset,entries,next} => format!(
+                "{{\"event\":\"ifd\",\"name\":{},\"offset\":{},\"entries\":{},\"next\":{}}}",
+                json_string(name), offset, entries, next),
+            Event::Tag{ref ifd,id,tagtype,count,offset,size} => format!(
+                "{{\"event\":\"tag\",\"ifd\":{},\"id\":{},\"type\":{},\"count\":{},\"offset\":{},\"size\":{}}}",
+                json_string(ifd), id, tagtype, count, offset, size),
+            Event::Anomaly{offset,ref msg} => format!(
+                "{{\"event\":\"anomaly\",\"offset\":{},\"message\":{}}}", offset, json_string(msg))
+        }).collect();
+        format!("[{}]", items.join(",\n "))
+    }
+}
+
+#[test]
+fn test_to_json() {
+    let t = Trace { events: vec![
+        Event::Ifd { name: "ifd0".to_string(), offset: 16, entries: 1, next: 0 },
+        Event::Anomaly { offset: 18, msg: "tag of unknown type 13 skipped".to_string() }
+    ] };
+    assert_eq!(1, t.anomalies().len());
+    assert_eq!("[{\"event\":\"ifd\",\"name\":\"ifd0\",\"offset\":16,\"entries\":1,\"next\":0},\n \
+                {\"event\":\"anomaly\",\"offset\":18,\"message\":\"tag of unknown type 13 skipped\"}]",
+               t.to_json());
+}
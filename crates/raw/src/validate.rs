@@ -0,0 +1,204 @@
+use std::fmt;
+use cr2::{self,RawImage,RawFileError,Ifd,IfdKind,TagData};
+use datetime;
+
+/// A deviation from the TIFF/EXIF specification
+#[derive(Debug,Clone,PartialEq)]
+pub struct Warning {
+    /// IFD name as in tag lists, e.g. "ifd0" or "exif"
+    pub ifd: String,
+    pub tag: Option<u16>,
+    /// Absolute file offset of the offending data
+    pub offset: u64,
+    pub msg: String
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.ifd));
+        if let Some(t) = self.tag {
+            try!(write!(f, " tag 0x{:04x}", t));
+        }
+        write!(f, " at offset 0x{:x}: {}", self.offset, self.msg)
+    }
+}
+
+/// Allowed types and count of a tag, None if any count is allowed
+fn spec(kind: IfdKind, id: u16) -> Option<(&'static [u16], Option<usize>)> {
+    const BYTE: &[u16] = &[1];
+    const ASCII: &[u16] = &[2];
+    const SHORT: &[u16] = &[3];
+    const LONG: &[u16] = &[4];
+    const SHORT_LONG: &[u16] = &[3, 4];
+    const RATIONAL: &[u16] = &[5];
+    const SRATIONAL: &[u16] = &[10];
+    const UNDEFINED: &[u16] = &[7];
+    const POINTER: &[u16] = &[4, 13];
+    Some(match kind {
+        IfdKind::Tiff => match id {
+            0x100 | 0x101 => (SHORT_LONG, Some(1)),
+            0x102 => (SHORT, None),
+            0x103 | 0x112 | 0x128 => (SHORT, Some(1)),
+            0x10f | 0x110 | 0x13b | 0x8298 => (ASCII, None),
+            0x111 | 0x117 => (SHORT_LONG, None),
+            0x11a | 0x11b => (RATIONAL, Some(1)),
+            0x132 => (ASCII, Some(20)),
+            0x142 | 0x143 => (SHORT_LONG, Some(1)),
+            0x144 => (LONG, None),
+            0x145 => (SHORT_LONG, None),
+            0x201 | 0x202 => (LONG, Some(1)),
+            0x8769 | 0x8825 => (POINTER, Some(1)),
+            _ => return None
+        },
+        IfdKind::Exif => match id {
+            0x829a | 0x829d | 0x920a => (RATIONAL, Some(1)),
+            0x8822 | 0x9207 | 0x9209 | 0xa402 => (SHORT, Some(1)),
+            0x8827 => (SHORT, None),
+            0x9003 | 0x9004 => (ASCII, Some(20)),
+            0x9204 => (SRATIONAL, Some(1)),
+            0x927c => (UNDEFINED, None),
+            0xa002 | 0xa003 => (SHORT_LONG, Some(1)),
+            0xa430 | 0xa431 | 0xa434 | 0xa435 => (ASCII, None),
+            _ => return None
+        },
+        IfdKind::Gps => match id {
+            0x0000 => (BYTE, Some(4)),
+            0x0001 | 0x0003 => (ASCII, Some(2)),
+            0x0002 | 0x0004 | 0x0007 => (RATIONAL, Some(3)),
+            0x0005 => (BYTE, Some(1)),
+            0x0006 => (RATIONAL, Some(1)),
+            0x001d => (ASCII, Some(11)),
+            _ => return None
+        },
+        IfdKind::Canon => return None
+    })
+}
+
+/// Checks a value against the range the specification allows
+fn check_range(kind: IfdKind, name: &str, values: &[TagData]) -> Option<String> {
+    let first = values.first();
+    let u = first.and_then(|d| d.as_u32());
+    let range = |lo, hi| match u {
+        Some(v) if v < lo || v > hi => Some(format!("{} value {} out of range {}..{}", name, v, lo, hi)),
+        _ => None
+    };
+    let string = match first {
+        Some(&TagData::Strg(ref s)) => Some(s.as_str()),
+        _ => None
+    };
+    match (kind, name) {
+        (IfdKind::Tiff, "orientation") => range(1, 8),
+        (IfdKind::Tiff, "res_unit") => range(1, 3),
+        (IfdKind::Exif, "exposure_program") => range(0, 9),
+        (IfdKind::Exif, "exposure_mode") => range(0, 2),
+        (IfdKind::Exif, "metering_mode") if u != Some(255) => range(0, 6),
+        (IfdKind::Tiff, "date_time") | (IfdKind::Exif, "date_time_original") |
+        (IfdKind::Exif, "date_time_digitized") => match string {
+            Some(s) if datetime::parse_exif(s).is_none() => Some(format!("{} \"{}\" is not a valid date", name, s)),
+            _ => None
+        },
+        (IfdKind::Gps, "latitude_ref") => match string {
+            Some(s) if s != "N" && s != "S" => Some(format!("{} \"{}\" is not N or S", name, s)),
+            _ => None
+        },
+        (IfdKind::Gps, "longitude_ref") => match string {
+            Some(s) if s != "E" && s != "W" => Some(format!("{} \"{}\" is not E or W", name, s)),
+            _ => None
+        },
+        _ => {
+            let zero = values.iter().any(|d| match *d {
+                TagData::U64(v) => v >> 32 == 0 && v != 0,
+                TagData::I64(v) => v >> 32 == 0 && v != 0,
+                _ => false
+            });
+            if zero { Some(format!("{} has a zero denominator", name)) } else { None }
+        }
+    }
+}
+
+/// Offsets and lengths of the image data referenced from an IFD
+pub(crate) fn data_regions(name: &str, ifd: &Ifd) -> Vec<(String, u64, u64)> {
+    let mut regions = Vec::new();
+    for &(offsets, lengths) in &[("strip_offset", "strip_byte_count"), ("tile_offset", "tile_byte_count"),
+                                 ("thumbnail_offset", "thumbnail_length")] {
+        if let (Some(o), Some(l)) = (ifd.tags.get(offsets), ifd.tags.get(lengths)) {
+            for (i, (o, l)) in o.iter().zip(l.iter()).enumerate() {
+                if let (Some(o), Some(l)) = (o.as_u32(), l.as_u32()) {
+                    regions.push((format!("{} {} {}", name, offsets, i), o as u64, l as u64));
+                }
+            }
+        }
+    }
+    regions
+}
+
+/// Checks an image against the TIFF/EXIF specification
+///
+/// Reports tags with wrong types or counts, values out of range, values
+/// and IFDs not at word boundaries, data beyond the end of the file and
+/// overlapping image data. The MakerNote is only checked for alignment.
+pub fn validate(image: &RawImage) -> Result<Vec<Warning>,RawFileError> {
+    let len = try!(image.len());
+    let mut warnings = Vec::new();
+    let mut regions = Vec::new();
+    for (name, kind, ifd) in image.ifds() {
+        let warn = |tag: Option<u16>, offset: u64, msg: String| Warning {
+            ifd: name.clone(), tag: tag, offset: offset, msg: msg
+        };
+        if ifd.offset % 2 == 1 {
+            warnings.push(warn(None, ifd.offset as u64, "IFD not at a word boundary".to_string()));
+        }
+        let mut tags: Vec<(&String, &cr2::TagPos)> = ifd.pos.iter().collect();
+        tags.sort_by_key(|t| t.1.entry);
+        for (tag, p) in tags {
+            let size = cr2::type_size(p.tagtype).unwrap_or(1) * p.count;
+            if size > 4 {
+                if p.offset % 2 == 1 {
+                    warnings.push(warn(Some(p.id), p.offset, format!("value of {} not at a word boundary", tag)));
+                }
+                if p.offset + size as u64 > len {
+                    warnings.push(warn(Some(p.id), p.offset, format!("value of {} beyond end of file", tag)));
+                }
+            }
+            if let Some((types, count)) = spec(kind, p.id) {
+                if !types.contains(&p.tagtype) {
+                    warnings.push(warn(Some(p.id), p.entry, format!("{} has type {}, expected {:?}", tag, p.tagtype, types)));
+                }
+                match count {
+                    Some(c) if c != p.count => warnings.push(warn(Some(p.id), p.entry,
+                        format!("{} has count {}, expected {}", tag, p.count, c))),
+                    _ => {}
+                }
+            }
+            if let Some(msg) = ifd.tags.get(tag).and_then(|v| check_range(kind, tag, v)) {
+                warnings.push(warn(Some(p.id), p.offset, msg));
+            }
+        }
+        for (region, offset, length) in data_regions(&name, ifd) {
+            if offset + length > len {
+                warnings.push(warn(None, offset, format!("{} beyond end of file", region)));
+            }
+            regions.push((region, offset, length));
+        }
+    }
+    regions.sort_by_key(|r| r.1);
+    for (i, a) in regions.iter().enumerate() {
+        for b in regions[i + 1..].iter().take_while(|b| b.1 < a.1 + a.2) {
+            // the same data may be referenced twice, e.g. JPEG thumbnails
+            if b.1 != a.1 || b.2 != a.2 {
+                warnings.push(Warning { ifd: b.0.split(' ').next().unwrap_or("").to_string(), tag: None,
+                                        offset: b.1, msg: format!("{} overlaps {}", b.0, a.0) });
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+#[test]
+fn test_check_range() {
+    assert_eq!(None, check_range(IfdKind::Tiff, "orientation", &[TagData::Unsigned(6)]));
+    assert!(check_range(IfdKind::Tiff, "orientation", &[TagData::Unsigned(9)]).is_some());
+    assert!(check_range(IfdKind::Gps, "latitude_ref", &[TagData::Strg("X".to_string())]).is_some());
+    assert!(check_range(IfdKind::Exif, "exposure_time", &[TagData::U64(1)]).is_some());
+    assert_eq!(None, check_range(IfdKind::Exif, "exposure_time", &[TagData::U64(1 | 200 << 32)]));
+}
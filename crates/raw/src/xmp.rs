@@ -0,0 +1,132 @@
+use std::fs::{self,File};
+use std::io::{self,Read,Write};
+use std::path::{Path,PathBuf};
+use cr2::{RawImage,RawFileError,FormatErrorKind};
+use datetime::ClockCorrection;
+
+/// Namespaces of the properties written by this crate
+const NAMESPACES: &[(&str, &str)] = &[
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/")
+];
+
+const SKELETON: &str = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"/>
+ </rdf:RDF>
+</x:xmpmeta>
+";
+
+/// Path of the sidecar of a raw file, IMG_0001.CR2 -> IMG_0001.xmp
+pub fn sidecar_path(raw: &Path) -> PathBuf {
+    raw.with_extension("xmp")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Removes a property in attribute or element form
+fn remove(xmp: &mut String, name: &str) {
+    let attr = format!(" {}=\"", name);
+    while let Some(i) = xmp.find(&attr) {
+        // the whitespace before the attribute goes with it
+        let start = xmp[..i].trim_end().len();
+        match xmp[i + attr.len()..].find('"') {
+            Some(end) => xmp.replace_range(start..i + attr.len() + end + 1, ""),
+            None => break
+        }
+    }
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    while let Some(start) = xmp.find(&open) {
+        match xmp[start..].find(&close) {
+            Some(end) => xmp.replace_range(start..start + end + close.len(), ""),
+            None => break
+        }
+    }
+}
+
+/// Sets simple properties in an XMP packet
+///
+/// Existing values are replaced, new ones are added as attributes of the
+/// first rdf:Description. Missing namespace declarations are added.
+pub fn set_properties(xmp: &str, props: &[(&str, String)]) -> Result<String, String> {
+    let mut xmp = xmp.to_string();
+    for &(name, _) in props {
+        remove(&mut xmp, name);
+    }
+    let mut attrs = String::new();
+    for &(name, ref value) in props {
+        let prefix = name.split(':').next().unwrap_or("");
+        let decl = format!("xmlns:{}=", prefix);
+        if !xmp.contains(&decl) && !attrs.contains(&decl) {
+            match NAMESPACES.iter().find(|n| n.0 == prefix) {
+                Some(n) => attrs.push_str(&format!("\n    {}\"{}\"", decl, n.1)),
+                None => return Err(format!("Unknown XMP namespace {}", prefix))
+            }
+        }
+        attrs.push_str(&format!("\n    {}=\"{}\"", name, escape(value)));
+    }
+    let start = match xmp.find("<rdf:Description") {
+        Some(s) => s,
+        None => return Err("No rdf:Description in XMP".to_string())
+    };
+    let end = match xmp[start..].find('>') {
+        Some(e) => start + e,
+        None => return Err("Unterminated rdf:Description".to_string())
+    };
+    let end = if xmp[..end].ends_with('/') { end - 1 } else { end };
+    xmp.insert_str(end, &attrs);
+    Ok(xmp)
+}
+
+/// Sets properties in the sidecar file at path, the file is created if
+/// it does not exist yet
+pub fn update(path: &Path, props: &[(&str, String)]) -> io::Result<()> {
+    let mut xmp = String::new();
+    match File::open(path) {
+        Ok(mut f) => { try!(f.read_to_string(&mut xmp)); },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => xmp.push_str(SKELETON),
+        Err(e) => return Err(e)
+    }
+    let xmp = try!(set_properties(&xmp, props).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    // write a copy and rename it, so a failed write keeps the old sidecar
+    let tmp = path.with_extension("xmp.tmp");
+    {
+        let mut f = try!(File::create(&tmp));
+        try!(f.write_all(xmp.as_bytes()));
+    }
+    fs::rename(&tmp, path)
+}
+
+/// Writes the corrected capture time of an image to its sidecar
+///
+/// Returns the written date, an image without capture time is an error.
+pub fn write_capture_time(image: &RawImage, correction: &ClockCorrection) -> Result<String,RawFileError> {
+    let date = match image.capture_time() {
+        Some(t) => correction.format(t),
+        None => return Err(RawFileError::format(FormatErrorKind::Missing, "No capture time"))
+    };
+    try!(update(&sidecar_path(image.file_name()), &[
+        ("exif:DateTimeOriginal", date.clone()),
+        ("photoshop:DateCreated", date.clone()),
+        ("xmp:CreateDate", date.clone())
+    ]));
+    Ok(date)
+}
+
+#[test]
+fn test_set_properties() {
+    let x = set_properties(SKELETON, &[("exif:GPSLatitude", "48,8.5N".to_string())]).unwrap();
+    assert!(x.contains("xmlns:exif=\"http://ns.adobe.com/exif/1.0/\""));
+    assert!(x.contains("exif:GPSLatitude=\"48,8.5N\"/>"));
+    let x = set_properties(&x, &[("exif:GPSLatitude", "1,0.0S".to_string())]).unwrap();
+    assert_eq!(1, x.matches("exif:GPSLatitude").count());
+    assert_eq!(1, x.matches("xmlns:exif").count());
+    let x = set_properties("<rdf:Description>\n<exif:GPSLatitude>2</exif:GPSLatitude></rdf:Description>",
+                           &[("exif:GPSLatitude", "<3>".to_string())]).unwrap();
+    assert!(!x.contains("<exif:GPSLatitude>"));
+    assert!(x.contains("exif:GPSLatitude=\"&lt;3&gt;\">"));
+}
@@ -0,0 +1,406 @@
+use std::fs::File;
+use std::io::{self,Read,Seek,SeekFrom};
+use cr2::{self,RawImage,RawFileError,FormatErrorKind};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const ZIP64_END_OF_DIRECTORY: u32 = 0x06064b50;
+
+/// A file in a ZIP archive
+#[derive(Debug,Clone,PartialEq)]
+pub struct ZipEntry {
+    pub name: String,
+    /// Uncompressed size
+    pub size: u64,
+    pub compressed_size: u64,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    /// Offset of the local header
+    offset: u64
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+fn u64_at(b: &[u8], i: usize) -> u64 {
+    u32_at(b, i) as u64 | (u32_at(b, i + 4) as u64) << 32
+}
+
+fn format_error(kind: FormatErrorKind, s: &str) -> RawFileError {
+    RawFileError::format(kind, format!("ZIP: {}", s))
+}
+
+/// Reads len bytes at offset
+fn read_at<R: Read + Seek>(r: &mut R, offset: u64, len: usize) -> Result<Vec<u8>,RawFileError> {
+    // lengths come from the archive, check them before allocating
+    if offset.saturating_add(len as u64) > try!(r.seek(SeekFrom::End(0))) {
+        return Err(format_error(FormatErrorKind::Truncated, "data beyond end of archive"));
+    }
+    try!(r.seek(SeekFrom::Start(offset)));
+    let mut b = vec![0u8; len];
+    try!(r.read_exact(&mut b));
+    Ok(b)
+}
+
+/// Lists the files of an archive from its central directory
+pub fn entries<R: Read + Seek>(r: &mut R) -> Result<Vec<ZipEntry>,RawFileError> {
+    // the end of directory record is followed by a comment of up to 64k
+    let len = try!(r.seek(SeekFrom::End(0)));
+    let tail_len = ::std::cmp::min(len, 22 + 0xffff);
+    let tail = try!(read_at(r, len - tail_len, tail_len as usize));
+    let eocd = match (0..tail.len().saturating_sub(21)).rev().find(|&i| u32_at(&tail, i) == END_OF_DIRECTORY) {
+        Some(i) => i,
+        None => return Err(format_error(FormatErrorKind::Missing, "no end of central directory"))
+    };
+    let mut count = u16_at(&tail, eocd + 10) as u64;
+    let mut dir_size = u32_at(&tail, eocd + 12) as u64;
+    let mut dir_offset = u32_at(&tail, eocd + 16) as u64;
+    if eocd >= 20 && u32_at(&tail, eocd - 20) == ZIP64_LOCATOR {
+        let z = try!(read_at(r, u64_at(&tail, eocd - 12), 56));
+        if u32_at(&z, 0) != ZIP64_END_OF_DIRECTORY {
+            return Err(format_error(FormatErrorKind::Invalid, "bad ZIP64 end of central directory"));
+        }
+        count = u64_at(&z, 32);
+        dir_size = u64_at(&z, 40);
+        dir_offset = u64_at(&z, 48);
+    }
+    let dir = try!(read_at(r, dir_offset, dir_size as usize));
+    let mut entries = Vec::new();
+    let mut p = 0;
+    for _ in 0..count {
+        if p + 46 > dir.len() || u32_at(&dir, p) != CENTRAL_HEADER {
+            return Err(format_error(FormatErrorKind::Invalid, "bad central directory"));
+        }
+        let name_len = u16_at(&dir, p + 28) as usize;
+        let extra_len = u16_at(&dir, p + 30) as usize;
+        let comment_len = u16_at(&dir, p + 32) as usize;
+        let end = p + 46 + name_len + extra_len;
+        if end > dir.len() {
+            return Err(format_error(FormatErrorKind::Invalid, "bad central directory"));
+        }
+        let mut e = ZipEntry {
+            name: String::from_utf8_lossy(&dir[p + 46..p + 46 + name_len]).into_owned(),
+            size: u32_at(&dir, p + 24) as u64,
+            compressed_size: u32_at(&dir, p + 20) as u64,
+            method: u16_at(&dir, p + 10),
+            flags: u16_at(&dir, p + 8),
+            crc: u32_at(&dir, p + 16),
+            offset: u32_at(&dir, p + 42) as u64
+        };
+        // ZIP64 extra field, holds the values that are 0xffffffff above
+        let mut x = p + 46 + name_len;
+        while x + 4 <= end {
+            let (id, size) = (u16_at(&dir, x), u16_at(&dir, x + 2) as usize);
+            if id == 1 {
+                let mut f = x + 4;
+                for v in &mut [&mut e.size, &mut e.compressed_size, &mut e.offset] {
+                    if **v == 0xffffffff && f + 8 <= x + 4 + size {
+                        **v = u64_at(&dir, f);
+                        f += 8;
+                    }
+                }
+            }
+            x += 4 + size;
+        }
+        entries.push(e);
+        p = end + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Reads and decompresses a file of an archive
+pub fn read_entry<R: Read + Seek>(r: &mut R, entry: &ZipEntry) -> Result<Vec<u8>,RawFileError> {
+    if entry.flags & 1 != 0 {
+        return Err(RawFileError::NotImplemented("Encrypted ZIP entries".to_string()));
+    }
+    let header = try!(read_at(r, entry.offset, 30));
+    if u32_at(&header, 0) != LOCAL_HEADER {
+        return Err(format_error(FormatErrorKind::Invalid, "bad local header"));
+    }
+    let start = entry.offset + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+    let data = try!(read_at(r, start, entry.compressed_size as usize));
+    let data = match entry.method {
+        0 => data,
+        8 => try!(inflate_to(&data, entry.size as usize)),
+        m => return Err(RawFileError::NotImplemented(format!("ZIP compression method {}", m)))
+    };
+    if data.len() as u64 != entry.size || crc32(&data) != entry.crc {
+        return Err(format_error(FormatErrorKind::Invalid, &format!("{} is corrupt", entry.name)));
+    }
+    Ok(data)
+}
+
+/// Opens a raw file stored in a ZIP archive
+///
+/// The file name of the image is the archive path followed by the entry name.
+pub fn open_zip_entry(archive: &str, name: &str) -> Result<RawImage,RawFileError> {
+    let mut f = try!(File::open(archive));
+    let entry = match try!(entries(&mut f)).into_iter().find(|e| e.name == name) {
+        Some(e) => e,
+        None => return Err(RawFileError::Io(io::Error::new(io::ErrorKind::NotFound,
+                                                            format!("{} not in {}", name, archive))))
+    };
+    let data = try!(read_entry(&mut f, &entry));
+    cr2::open_bytes(data, format!("{}/{}", archive, name))
+}
+
+/// CRC-32 as used by ZIP and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                              257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                              8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                              7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order of the code length code lengths in a dynamic block
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// LSB first bit reader over a deflate stream
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32
+}
+
+impl<'a> Bits<'a> {
+    fn get(&mut self, n: u32) -> Result<u32,RawFileError> {
+        while self.count < n {
+            match self.data.get(self.pos) {
+                Some(&b) => self.buf |= (b as u32) << self.count,
+                None => return Err(format_error(FormatErrorKind::Truncated, "deflate data ends early"))
+            }
+            self.pos += 1;
+            self.count += 8;
+        }
+        let v = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    /// Number of codes of each length
+    count: [u16; 16],
+    /// Symbols ordered by code
+    symbol: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0u16; 16];
+        for &l in lengths {
+            count[l as usize] += 1;
+        }
+        count[0] = 0;
+        let mut offs = [0u16; 16];
+        for l in 1..15 {
+            offs[l + 1] = offs[l] + count[l];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (s, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbol[offs[l as usize] as usize] = s as u16;
+                offs[l as usize] += 1;
+            }
+        }
+        Huffman { count: count, symbol: symbol }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16,RawFileError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for l in 1..16 {
+            code |= try!(bits.get(1)) as i32;
+            let count = self.count[l] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(format_error(FormatErrorKind::Invalid, "bad Huffman code"))
+    }
+}
+
+/// Decompresses a raw deflate stream (RFC 1951)
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>,RawFileError> {
+    inflate_to(data, usize::max_value())
+}
+
+/// Decompresses at most limit bytes, more are an error
+fn inflate_to(data: &[u8], limit: usize) -> Result<Vec<u8>,RawFileError> {
+    let mut bits = Bits { data: data, pos: 0, buf: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = try!(bits.get(1));
+        match try!(bits.get(2)) {
+            0 => {
+                // stored block starts at a byte boundary
+                bits.buf = 0;
+                bits.count = 0;
+                let p = bits.pos;
+                if p + 4 > data.len() {
+                    return Err(format_error(FormatErrorKind::Truncated, "deflate data ends early"));
+                }
+                let len = u16_at(data, p) as usize;
+                if !u16_at(data, p + 2) as usize != len {
+                    return Err(format_error(FormatErrorKind::Invalid, "bad stored block length"));
+                }
+                if out.len() + len > limit {
+                    return Err(format_error(FormatErrorKind::TooLarge, "data larger than expected"));
+                }
+                match data.get(p + 4..p + 4 + len) {
+                    Some(b) => out.extend_from_slice(b),
+                    None => return Err(format_error(FormatErrorKind::Truncated, "deflate data ends early"))
+                }
+                bits.pos = p + 4 + len;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (i, l) in lengths.iter_mut().enumerate() {
+                    *l = match i { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, _ => 8 };
+                }
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5u8; 30]);
+                try!(inflate_block(&mut bits, &mut out, &lit, &dist, limit));
+            },
+            2 => {
+                let (lit, dist) = try!(dynamic_codes(&mut bits));
+                try!(inflate_block(&mut bits, &mut out, &lit, &dist, limit));
+            },
+            _ => return Err(format_error(FormatErrorKind::Invalid, "bad deflate block type"))
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+/// Reads the code tables of a dynamic block
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman),RawFileError> {
+    let nlen = try!(bits.get(5)) as usize + 257;
+    let ndist = try!(bits.get(5)) as usize + 1;
+    let ncode = try!(bits.get(4)) as usize + 4;
+    let mut clen = [0u8; 19];
+    for &i in &CLEN_ORDER[..ncode] {
+        clen[i] = try!(bits.get(3)) as u8;
+    }
+    let clen = Huffman::new(&clen);
+    let mut lengths: Vec<u8> = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (value, repeat) = match try!(clen.decode(bits)) {
+            s @ 0..=15 => (s as u8, 1),
+            16 => match lengths.last() {
+                Some(&l) => (l, 3 + try!(bits.get(2))),
+                None => return Err(format_error(FormatErrorKind::Invalid, "repeat without length"))
+            },
+            17 => (0, 3 + try!(bits.get(3))),
+            _ => (0, 11 + try!(bits.get(7)))
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > nlen + ndist {
+        return Err(format_error(FormatErrorKind::Invalid, "too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+}
+
+/// Decodes the symbols of a compressed block up to the end of block code
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman, limit: usize) -> Result<(),RawFileError> {
+    loop {
+        if out.len() > limit {
+            return Err(format_error(FormatErrorKind::TooLarge, "data larger than expected"));
+        }
+        let s = try!(lit.decode(bits)) as usize;
+        if s < 256 {
+            out.push(s as u8);
+        } else if s == 256 {
+            return Ok(());
+        } else {
+            let s = s - 257;
+            if s >= 29 {
+                return Err(format_error(FormatErrorKind::Invalid, "bad length code"));
+            }
+            let len = LENGTH_BASE[s] as usize + try!(bits.get(LENGTH_EXTRA[s] as u32)) as usize;
+            let d = try!(dist.decode(bits)) as usize;
+            if d >= 30 {
+                return Err(format_error(FormatErrorKind::Invalid, "bad distance code"));
+            }
+            let d = DIST_BASE[d] as usize + try!(bits.get(DIST_EXTRA[d] as u32)) as usize;
+            if d > out.len() {
+                return Err(format_error(FormatErrorKind::Invalid, "distance too far back"));
+            }
+            let start = out.len() - d;
+            for i in 0..len {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_inflate() {
+    assert_eq!(b"hello".to_vec(), inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o']).unwrap());
+    // dynamic Huffman block
+    let t = b"aaaaaaaabbbbccd";
+    let expected: Vec<u8> = (0..500).map(|i| t[(i * i + i / 3) % 15]).collect();
+    let data = [0xed, 0x8a, 0xc1, 0x09, 0x00, 0x30, 0x10, 0xc2, 0x66, 0x4d, 0xbc, 0xfd, 0x67, 0xa8, 0x47, 0xa7,
+                0x28, 0x54, 0xd0, 0xf8, 0x08, 0x20, 0xda, 0x0d, 0x29, 0xd8, 0x46, 0xa7, 0xa7, 0x28, 0xc3, 0xc6,
+                0xf1, 0xf2, 0xcb, 0xcf, 0xcb, 0x07];
+    assert_eq!(expected, inflate(&data).unwrap());
+    assert!(inflate(&data[..20]).is_err());
+    assert_eq!(0xcbf43926, crc32(b"123456789"));
+}
+
+#[test]
+fn test_entries() {
+    let zip = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x00, 0xc3, 0x3c,
+        0x24, 0x04, 0x0e, 0x00, 0x00, 0x00, 0xa5, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x61, 0x2e,
+        0x74, 0x78, 0x74, 0x2b, 0x4a, 0x2c, 0x57, 0x28, 0x1a, 0xc4, 0x38, 0x2d, 0x33, 0x27, 0xb5, 0x18,
+        0x00, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x0b,
+        0xf9, 0x43, 0x56, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x62,
+        0x2e, 0x74, 0x78, 0x74, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03,
+        0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x00, 0xc3, 0x3c, 0x24, 0x04, 0x0e, 0x00,
+        0x00, 0x00, 0xa5, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x80, 0x01, 0x00, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01,
+        0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x0b, 0xf9, 0x43,
+        0x56, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x31, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74,
+        0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x66, 0x00, 0x00, 0x00,
+        0x5a, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let mut r = io::Cursor::new(&zip[..]);
+    let e = entries(&mut r).unwrap();
+    assert_eq!(vec!["a.txt", "b.txt"], e.iter().map(|e| e.name.as_str()).collect::<Vec<_>>());
+    let mut a = b"raw ".repeat(40);
+    a.extend_from_slice(b"files");
+    assert_eq!(a, read_entry(&mut r, &e[0]).unwrap());
+    assert_eq!(b"stored".to_vec(), read_entry(&mut r, &e[1]).unwrap());
+}
@@ -1,9 +1,137 @@
 extern crate raw;
 use raw::cr2;
+use raw::decoder;
+use raw::datetime::{self,ClockCorrection};
+use raw::xmp;
+use raw::inspect;
+use raw::hash::{self,Algorithm};
+use std::env;
+use std::io;
+
+fn usage() {
+    println!("Usage: rraw [--shift <+HH:MM>] [--timezone <+HH:MM>] [FILE]...");
+    println!("       rraw inspect FILE OFFSET [LENGTH]");
+    println!("       rraw map [--svg] FILE");
+    println!("       rraw verify FILE...");
+    println!("       rraw info FILE...");
+    println!("  --shift     corrects the camera clock by this offset");
+    println!("  --timezone  time zone of the corrected capture time");
+    println!("The corrected capture time is written to the XMP sidecar.");
+    println!("inspect dumps LENGTH bytes (default 256) at OFFSET, decimal or 0x hex,");
+    println!("with the IFDs and tags they belong to.");
+    println!("map prints the structure of the file as JSON or SVG.");
+    println!("verify prints checksums of the raw data and of the whole file.");
+    println!("info prints a summary and the warnings of each file, - reads stdin.");
+}
+
+fn verify(files: &[String]) {
+    for file in files {
+        let algorithms = [Algorithm::Xxh64,Algorithm::Sha256];
+        let sums = decoder::open(file.clone())
+            .and_then(|i| i.raw_checksums(&algorithms).and_then(|r| i.file_checksums(&algorithms).map(|f| (r,f))));
+        match sums {
+            Ok((r,f)) => {
+                println!("{}",file);
+                println!("  raw  xxh64 {} sha256 {}",hash::to_hex(&r[0]),hash::to_hex(&r[1]));
+                println!("  file xxh64 {} sha256 {}",hash::to_hex(&f[0]),hash::to_hex(&f[1]));
+            }
+            Err(e) => println!("{}: Error: {}",file,e)
+        }
+    }
+}
+
+fn info(files: &[String]) {
+    for file in files {
+        let res = if file == "-" { cr2::open_stream(io::stdin(),file.clone()) } else { decoder::open(file.clone()) };
+        match res {
+            Ok(image) => {
+                println!("{}",image);
+                for w in image.warnings() {
+                    println!("  warning: {}",w);
+                }
+            }
+            Err(e) => println!("{}: Error: {}",file,e)
+        }
+    }
+}
+
+fn map(args: &[String]) {
+    let (svg,file) = match args {
+        [f] => (false,f),
+        [o,f] if o == "--svg" => (true,f),
+        _ => { usage(); return; }
+    };
+    match decoder::open(file.clone()).and_then(|i| inspect::map(&i)) {
+        Ok((regions,len)) if svg => print!("{}",inspect::map_svg(&regions,len)),
+        Ok((regions,len)) => print!("{}",inspect::map_json(&regions,len)),
+        Err(e) => println!("Error: {}",e)
+    }
+}
+
+/// Number in decimal or hex with 0x
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(h) => u64::from_str_radix(h,16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+fn inspect(args: &[String]) {
+    let (file,offset,len) = match (args.first(),args.get(1).and_then(|s| parse_number(s)),args.get(2)) {
+        (Some(f),Some(o),None) => (f,o,256),
+        (Some(f),Some(o),Some(l)) => match parse_number(l) {
+            Some(l) => (f,o,l),
+            None => { usage(); return; }
+        },
+        _ => { usage(); return; }
+    };
+    match decoder::open(file.clone()).and_then(|i| inspect::hexdump(&i,offset,len)) {
+        Ok(d) => print!("{}",d),
+        Err(e) => println!("Error: {}",e)
+    }
+}
+
 fn main() {
-    let res = cr2::open("data/test.cr2".to_string());
-    match res {
-        Ok(ri) => { println!("File: {} Offset: {}",ri.file_name,ri.raw_offset);}
-        Err(e) => { println!("Fehler {}",e);}
+    let mut files = Vec::new();
+    let mut correction: Option<ClockCorrection> = None;
+    let mut args = env::args().skip(1);
+    match env::args().nth(1).as_deref() {
+        Some("inspect") => { inspect(&args.skip(1).collect::<Vec<_>>()); return; }
+        Some("map") => { map(&args.skip(1).collect::<Vec<_>>()); return; }
+        Some("verify") => { verify(&args.skip(1).collect::<Vec<_>>()); return; }
+        Some("info") => { info(&args.skip(1).collect::<Vec<_>>()); return; }
+        _ => {}
+    }
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--shift" | "--timezone" => {
+                let offset = match args.next().and_then(|v| datetime::parse_offset(&v)) {
+                    Some(o) => o,
+                    None => { usage(); return; }
+                };
+                let c = correction.get_or_insert_with(ClockCorrection::default);
+                if a == "--shift" { c.shift = offset } else { c.utc_offset = offset }
+            }
+            "-h" | "--help" => { usage(); return; }
+            _ => files.push(a)
+        }
+    }
+    if files.is_empty() {
+        files.push("data/test.cr2".to_string());
+    }
+    for file in files {
+        let res = decoder::open(file);
+        match res {
+            Ok(ri) => {
+                println!("File: {} Offset: {}",ri.file_name().display(),ri.raw_offset());
+                if let Some(ref c) = correction {
+                    match xmp::write_capture_time(&ri,c) {
+                        Ok(d) => { println!("Capture time: {}",d); }
+                        Err(e) => { println!("Error: {}",e); }
+                    }
+                }
+            }
+            Err(e) => { println!("Error: {}",e);}
+        }
     }
 }